@@ -4,7 +4,7 @@ pub fn import_ndjson(path: &str) -> Result<Value, Box<dyn Error>> {
     let lines: Vec<Value> = data
         .lines()
         .filter(|l| !l.trim().is_empty())
-        .map(|l| serde_json::from_str(l))
+        .map(serde_json::from_str)
         .collect::<Result<_, _>>()?;
     Ok(Value::Array(lines))
 }
@@ -290,19 +290,24 @@ pub fn export_csv(value: &Value, path: &str) -> Result<(), Box<dyn Error>> {
 }
 
 // -------- Conversion engine --------
+/// Read a file of any supported format into a `serde_json::Value`.
+pub fn import(input: &str, from: &Format) -> Result<Value, Box<dyn Error>> {
+    match from {
+        Format::Json => import_json(input),
+        Format::Yaml => import_yaml(input),
+        Format::Toml => import_toml(input),
+        Format::Csv  => import_csv(input),
+        Format::Parquet => import_parquet(input),
+        Format::Avro => import_avro(input),
+        Format::MsgPack => import_msgpack(input),
+        Format::Cbor => import_cbor(input),
+        Format::Ndjson => import_ndjson(input),
+    }
+}
+
 pub fn convert(input: &str, output: &str, from: Format, to: Format) -> Result<(), Box<dyn Error>> {
     // Step 1: Import into serde_json::Value
-    let value = match from {
-        Format::Json => import_json(input)?,
-        Format::Yaml => import_yaml(input)?,
-        Format::Toml => import_toml(input)?,
-        Format::Csv  => import_csv(input)?,
-        Format::Parquet => import_parquet(input)?,
-        Format::Avro => import_avro(input)?,
-        Format::MsgPack => import_msgpack(input)?,
-        Format::Cbor => import_cbor(input)?,
-        Format::Ndjson => import_ndjson(input)?,
-    };
+    let value = import(input, &from)?;
 
     // Step 2: Export to target format
     match to {
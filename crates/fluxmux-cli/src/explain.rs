@@ -0,0 +1,189 @@
+//! `--dry-run` / `--explain`: print the pipeline a command would run, as
+//! resolved from its arguments and config, without connecting to anything.
+//!
+//! Problems that a real run would silently work around (a flag overriding a
+//! different config value, a setting that has no effect) are listed at the
+//! end; any of them makes the dry run exit non-zero.
+
+use crate::spec::PipelineSpec;
+use fluxmux_core::endpoints::{SinkType, SourceType};
+use fluxmux_core::engine::MiddlewareConfig;
+use fluxmux_core::lineage::LineageHeader;
+use fluxmux_core::middleware::MiddlewareChain;
+use fluxmux_core::stream_join::StreamJoinConfig;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Print the steps and sinks of a `pipe` or `run` pipeline.
+pub fn pipeline(spec: &PipelineSpec) {
+    print_source(&spec.source);
+    print_union(&spec.union);
+    print_join(spec.stream_join.as_ref());
+    println!("steps:");
+    if spec.steps.is_empty() {
+        println!("  (none)");
+    }
+    for (i, step) in spec.steps.iter().enumerate() {
+        println!("  {}. {}", i + 1, step);
+    }
+    println!("sinks:");
+    if spec.sinks.is_empty() {
+        println!("  - stdout (default)");
+    }
+    for sink in &spec.sinks {
+        print_sink(sink);
+    }
+    println!("dead letter: {}", spec.dead_letter.as_deref().unwrap_or("(none)"));
+    if let Some(policy) = spec.retry_policy() {
+        println!("retry: {:?}", policy);
+    }
+    if let Some(breaker) = spec.circuit_breaker.as_ref() {
+        println!("circuit breaker: threshold={} reset_ms={}", breaker.threshold, breaker.reset_ms.unwrap_or(30_000));
+    }
+    if let Some(lineage) = spec.lineage.as_ref() {
+        println!("lineage: {} (pipeline {})", lineage, spec.name.as_deref().unwrap_or("(unnamed)"));
+    }
+    if let Some(event_time) = spec.event_time.as_ref() {
+        println!("event time: {}", event_time);
+    }
+}
+
+/// Print a bridge: its endpoints (`sources` starts with `--source`, then
+/// any `--union` sources), the middleware chain in order, and every
+/// setting with where it came from. Returns the ambiguities found.
+pub fn bridge(
+    sources: &[String],
+    join: Option<&StreamJoinConfig>,
+    sink: &str,
+    chain: &MiddlewareChain,
+    file: Option<&str>,
+    from_file: &MiddlewareConfig,
+    from_flags: &MiddlewareConfig,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    print_source(&sources[0]);
+    print_union(&sources[1..]);
+    print_join(join);
+    println!("middleware (in order):");
+    let names = chain.names();
+    if names.is_empty() {
+        println!("  (none)");
+    }
+    for (i, name) in names.iter().enumerate() {
+        println!("  {}. {}", i + 1, name);
+    }
+    println!("sink:");
+    print_sink(sink);
+
+    // Compare field by field; CLI flags win over the config file
+    let file_values = fields(from_file);
+    let flag_values = fields(from_flags);
+    let file = file.unwrap_or("config");
+    println!("settings:");
+    for (name, merged) in fields(&from_file.clone().with_overrides(from_flags)) {
+        if merged.is_null() {
+            continue;
+        }
+        let flag = format!("--{}", name.replace('_', "-"));
+        let origin = match (&file_values[&name], &flag_values[&name]) {
+            (Value::Null, _) => flag,
+            (_, Value::Null) => file.to_string(),
+            (in_file, on_flag) if in_file == on_flag => format!("{} and {}", file, flag),
+            (in_file, _) => {
+                problems.push(format!("{} overrides {} = {} from {}", flag, name, in_file, file));
+                format!("{}, overriding {} from {}", flag, in_file, file)
+            }
+        };
+        println!("  {} = {} ({})", name, without_nulls(merged), origin);
+    }
+    problems.extend(no_effect(&from_file.clone().with_overrides(from_flags)));
+    problems
+}
+
+/// Print problems, if any, and the exit status for the dry run.
+pub fn finish(problems: &[String]) -> i32 {
+    if problems.is_empty() {
+        return 0;
+    }
+    println!("problems:");
+    for problem in problems {
+        println!("  - {}", problem);
+    }
+    2
+}
+
+fn print_source(uri: &str) {
+    println!("source: {}", uri);
+    match SourceType::from_str(uri) {
+        Ok(parsed) => println!("  {:?}", parsed),
+        Err(e) => println!("  invalid: {}", e),
+    }
+}
+
+fn print_union(union: &[String]) {
+    for uri in union {
+        println!("union: {}", uri);
+        match SourceType::from_str(uri) {
+            Ok(parsed) => println!("  {:?}", parsed),
+            Err(e) => println!("  invalid: {}", e),
+        }
+    }
+}
+
+fn print_join(join: Option<&StreamJoinConfig>) {
+    let Some(join) = join else { return };
+    println!("joined with: {}", join);
+    match SourceType::from_str(&join.with) {
+        Ok(parsed) => println!("  {:?}", parsed),
+        Err(e) => println!("  invalid: {}", e),
+    }
+}
+
+fn print_sink(uri: &str) {
+    match SinkType::from_str(uri) {
+        Ok(parsed) => println!("  - {} {:?}", uri, parsed),
+        Err(e) => println!("  - {} invalid: {}", uri, e),
+    }
+}
+
+/// Config fields by name, in a stable order.
+fn fields(cfg: &MiddlewareConfig) -> serde_json::Map<String, Value> {
+    match serde_json::to_value(cfg) {
+        Ok(Value::Object(map)) => map,
+        _ => serde_json::Map::new(),
+    }
+}
+
+/// Nested settings (e.g. `rate_limit`) without their unset fields.
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(map.into_iter().filter(|(_, v)| !v.is_null()).collect()),
+        other => other,
+    }
+}
+
+/// Settings that are given but do nothing in this combination.
+fn no_effect(cfg: &MiddlewareConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+    if cfg.batch_timeout_ms.is_some() && cfg.batch_size.is_none() {
+        problems.push("batch_timeout_ms has no effect without batch_size".to_string());
+    }
+    if cfg.deduplicate == Some(false) && (cfg.dedup_by.is_some() || cfg.dedup_capacity.is_some() || cfg.dedup_ttl_ms.is_some()) {
+        problems.push("dedup_by, dedup_capacity and dedup_ttl_ms have no effect with deduplicate: false".to_string());
+    }
+    let retry_tuning = [cfg.retry_delay_ms.is_some(), cfg.retry_max_delay_ms.is_some(), cfg.retry_backoff_multiplier.is_some(), cfg.retry_jitter.is_some()];
+    if cfg.retry_max_attempts.is_none() && retry_tuning.contains(&true) {
+        problems.push("retry delay, backoff and jitter settings have no effect without retry_max_attempts".to_string());
+    }
+    if cfg.circuit_breaker_reset_ms.is_some() && cfg.circuit_breaker_threshold.is_none() {
+        problems.push("circuit_breaker_reset_ms has no effect without circuit_breaker_threshold".to_string());
+    }
+    if cfg.pipeline_name.is_some() && !cfg.lineage.as_ref().is_some_and(|l| l.contains(LineageHeader::Pipeline)) {
+        problems.push("pipeline_name has no effect unless lineage includes pipeline".to_string());
+    }
+    if cfg.rate_limit.as_ref().is_some_and(|rl| rl.route_to.is_some() && rl.mode != fluxmux_core::rate_limit::OverLimit::Route) {
+        problems.push("rate_limit.route_to has no effect unless mode is route".to_string());
+    }
+    problems
+}
@@ -0,0 +1,102 @@
+use clap::ValueEnum;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per event, including the pipeline and stage spans
+    Json,
+}
+
+/// Exporting tracer, kept so buffered spans can be flushed before exit.
+static TRACER_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Install the global subscriber. Diagnostics always go to stderr so stdout
+/// carries only data (e.g. the `stdout` sink).
+///
+/// `level` is a level (`info`, `debug`, ...) or a filter directive such as
+/// `warn,fluxmux_core=debug`. With an OTLP endpoint (or
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` set) spans are also exported to that
+/// collector, independently of `level`.
+pub fn init(format: LogFormat, level: &str, otlp_endpoint: Option<&str>) -> anyhow::Result<()> {
+    let filter = EnvFilter::try_new(level).map_err(|e| anyhow::anyhow!("Invalid log level {level}: {e}"))?;
+    let logs = match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_target(false)
+            .with_ansi(std::io::stderr().is_terminal())
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    let exporting = otlp_endpoint.is_some() || std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_some();
+    let traces = if exporting {
+        let provider = tracer_provider(otlp_endpoint)?;
+        let tracer = provider.tracer("fluxmux");
+        let _ = TRACER_PROVIDER.set(provider);
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        // Per-message spans (receive, stage, send) are debug level
+        let spans = Targets::new()
+            .with_default(Level::INFO)
+            .with_target("fluxmux_core", Level::DEBUG);
+        Some(tracing_opentelemetry::layer().with_tracer(tracer).with_filter(spans))
+    } else {
+        None
+    };
+
+    tracing_subscriber::registry()
+        .with(logs.with_filter(filter))
+        .with(traces)
+        .try_init()
+        .map_err(|e| anyhow::anyhow!("Cannot install logger: {e}"))
+}
+
+/// OTLP/HTTP exporter; `endpoint` is the collector's base URL, e.g.
+/// `http://localhost:4318`.
+fn tracer_provider(endpoint: Option<&str>) -> anyhow::Result<SdkTracerProvider> {
+    let mut exporter = opentelemetry_otlp::SpanExporter::builder().with_http();
+    if let Some(endpoint) = endpoint {
+        let endpoint = endpoint.trim_end_matches('/');
+        if endpoint.ends_with("/v1/traces") {
+            exporter = exporter.with_endpoint(endpoint);
+        } else {
+            exporter = exporter.with_endpoint(format!("{endpoint}/v1/traces"));
+        }
+    }
+    let exporter = exporter.build().map_err(|e| anyhow::anyhow!("Invalid OTLP endpoint: {e}"))?;
+
+    let mut resource = Resource::builder();
+    if std::env::var_os("OTEL_SERVICE_NAME").is_none() {
+        resource = resource.with_service_name("fluxmux");
+    }
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource.build())
+        .build())
+}
+
+/// Export spans still buffered; call before the process exits.
+pub fn shutdown() {
+    if let Some(provider) = TRACER_PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Failed to export remaining spans: {e}");
+        }
+    }
+}
@@ -1,19 +1,37 @@
-use fluxmux_core::engine::{MiddlewareConfig, build_middleware_chain, run_pipeline};
-use fluxmux_core::pipe_actions::*;
+use fluxmux_core::checkpoint::CheckpointStore;
+use fluxmux_core::dead_letter::{DeadLetterQueue, DeadLetterSender};
+use fluxmux_core::dedup::DedupKey;
+use fluxmux_core::endpoints::{SinkType, SourceType};
+use fluxmux_core::engine::{
+    MiddlewareConfig, PipelineOptions, build_circuit_breaker, build_middleware_chain, build_retry_policy, run_pipeline,
+};
+use fluxmux_core::event_time::{EventTime, LatePolicy, TimeFormat};
+use fluxmux_core::lineage::{Lineage, LineageHeaders};
+use fluxmux_core::metrics::Metrics;
 use fluxmux_core::pipe_engine::run_pipe;
-use serde_yaml;
+use fluxmux_core::rate_limit::OverLimit;
+use fluxmux_core::reload::{ChainReloader, DEFAULT_RELOAD_INTERVAL_MS};
+use fluxmux_core::stream_join::{StreamJoin, StreamJoinConfig, Unmatched};
+use fluxmux_core::union::UnionSource;
+use fluxmux_core::shutdown::{Shutdown, DEFAULT_DRAIN_TIMEOUT_MS, EXIT_INTERRUPTED};
 use std::fs;
+use std::net::SocketAddr;
 use std::str::FromStr;
+use tracing::Instrument;
 
 mod conversions;
-mod endpoints;
+mod explain;
 mod kafka_inspector;
+mod logging;
+mod pipe_args;
+mod spec;
 
 use clap::{Parser, Subcommand};
 use conversions::{Format, convert};
-use endpoints::{SourceType, SinkType};
+use logging::LogFormat;
+use spec::PipelineSpec;
 use fluxmux_connectors::{FileSource, KafkaSource, PipeSource};
-use fluxmux_sinks::{FileSink, KafkaSink, PostgresSink, PipeSink};
+use fluxmux_sinks::{sink_from_type, sink_from_uri, SinkOptions};
 
 #[derive(Parser)]
 #[command(name = "fluxmux", about = "Universal CLI for File Conversion & Stream Inspection")]
@@ -28,9 +46,19 @@ pub struct Cli {
     pub throttle_per_sec: Option<u64>,
     #[arg(long)]
     pub config: Option<String>, // path to YAML config
+    /// Log output format; logs always go to stderr
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+    /// Log level or filter directive (e.g. debug, warn,fluxmux_core=debug)
+    #[arg(long, global = true, default_value = "info")]
+    pub log_level: String,
+    /// Export per-message trace spans to this OTLP/HTTP collector (e.g. http://localhost:4318)
+    #[arg(long, global = true)]
+    pub otlp_endpoint: Option<String>,
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)] // parsed once at startup
 pub enum Commands {
     Convert {
         input: String,
@@ -43,6 +71,9 @@ pub enum Commands {
     Bridge {
         #[arg(long)]
         source: String,
+        /// Another source merged with --source (repeatable); each message is tagged with its source URI
+        #[arg(long)]
+        union: Vec<String>,
         #[arg(long)]
         sink: String,
         #[arg(long)]
@@ -51,24 +82,121 @@ pub enum Commands {
         batch_timeout_ms: Option<u64>,
         #[arg(long)]
         deduplicate: bool,
+        /// Dedup key: auto (default; the record key, or the content for records without one), content, key, or comma-separated field paths (e.g. user.id,event)
+        #[arg(long)]
+        dedup_by: Option<DedupKey>,
+        /// Most dedup keys remembered; least recently seen are forgotten first
+        #[arg(long)]
+        dedup_capacity: Option<usize>,
+        /// Forget dedup keys not seen for this long
+        #[arg(long)]
+        dedup_ttl_ms: Option<u64>,
         #[arg(long)]
         throttle_per_sec: Option<u64>,
         #[arg(long)]
         retry_max_attempts: Option<u32>,
         #[arg(long)]
         retry_delay_ms: Option<u64>,
+        /// Upper bound for the exponential retry backoff
+        #[arg(long)]
+        retry_max_delay_ms: Option<u64>,
+        /// Backoff growth factor between retries (default 2.0)
+        #[arg(long)]
+        retry_backoff_multiplier: Option<f64>,
+        /// Fraction of each retry delay that is randomized, 0.0-1.0 (default 0.2)
+        #[arg(long)]
+        retry_jitter: Option<f64>,
+        /// Consecutive transient sink failures that open the circuit breaker
+        #[arg(long)]
+        circuit_breaker_threshold: Option<u32>,
+        /// How long an open circuit pauses delivery before probing the sink (default 30000)
+        #[arg(long)]
+        circuit_breaker_reset_ms: Option<u64>,
         #[arg(long)]
         schema_path: Option<String>,
+        /// Sink URI for messages that fail validation or exhaust retries
+        #[arg(long)]
+        dead_letter: Option<String>,
+        /// Time allowed to drain and flush after SIGINT/SIGTERM
+        #[arg(long)]
+        shutdown_timeout_ms: Option<u64>,
+        /// Directory for source checkpoints, used to resume after a restart
+        #[arg(long)]
+        checkpoint_dir: Option<String>,
+        /// Serve Prometheus metrics on this address (e.g. 0.0.0.0:9898)
+        #[arg(long)]
+        metrics_addr: Option<String>,
+        /// How often --config and the schema file are checked for changes (default 1000, 0 disables reloading)
+        #[arg(long)]
+        reload_interval_ms: Option<u64>,
+        /// Lineage headers to stamp on messages: all, or a comma-separated list of id, parents, source, ingest_time, pipeline
+        #[arg(long)]
+        lineage: Option<LineageHeaders>,
+        /// Pipeline name for the pipeline lineage header (default bridge)
+        #[arg(long)]
+        pipeline_name: Option<String>,
+        /// Payload field (dotted path) holding each message's event time, used as its timestamp
+        #[arg(long)]
+        event_time: Option<String>,
+        /// Format of --event-time: auto (epoch ms or RFC 3339), rfc3339, epoch_ms, epoch_s or a strftime pattern
+        #[arg(long, requires = "event_time")]
+        event_time_format: Option<TimeFormat>,
+        #[command(flatten)]
+        join: JoinArgs,
         #[arg(long)]
         config: Option<String>,
+        /// Print the resolved endpoints, middleware order and settings without connecting; exits non-zero on ambiguities
+        #[arg(long, alias = "explain")]
+        dry_run: bool,
     },
     Pipe {
+        /// Sink URI for messages that fail validation or cannot be delivered
+        #[arg(long)]
+        dead_letter: Option<String>,
+        /// Time allowed to drain and flush after SIGINT/SIGTERM
+        #[arg(long)]
+        shutdown_timeout_ms: Option<u64>,
+        /// Directory for source checkpoints, used to resume after a restart
+        #[arg(long)]
+        checkpoint_dir: Option<String>,
+        /// Serve Prometheus metrics on this address (e.g. 0.0.0.0:9898)
+        #[arg(long)]
+        metrics_addr: Option<String>,
+        /// Lineage headers to stamp on messages: all, or a comma-separated list of id, parents, source, ingest_time, pipeline
+        #[arg(long)]
+        lineage: Option<LineageHeaders>,
+        /// Pipeline name for the pipeline lineage header (default pipe)
+        #[arg(long)]
+        pipeline_name: Option<String>,
+        /// Payload field (dotted path) holding each message's event time, used as its timestamp
+        #[arg(long)]
+        event_time: Option<String>,
+        /// Format of --event-time: auto (epoch ms or RFC 3339), rfc3339, epoch_ms, epoch_s or a strftime pattern
+        #[arg(long, requires = "event_time")]
+        event_time_format: Option<TimeFormat>,
+        #[command(flatten)]
+        join: JoinArgs,
+        /// Another source merged with the source (repeatable); each message is tagged with its source URI
+        #[arg(long)]
+        union: Vec<String>,
+        /// Print the parsed source, actions and sinks without connecting; exits non-zero on ambiguities
+        #[arg(long, alias = "explain")]
+        dry_run: bool,
         /// Source endpoint (file:path, kafka://host/topic, stdin, -)
         source: String,
         /// Pipeline actions and output destinations
-        /// Example: filter 'temp>30' transform 'fahrenheit=temp*1.8+32' tee output.json kafka://localhost/hot
+        /// Example: filter 'temp>30' transform 'fahrenheit=temp*1.8+32' tee file:output.json kafka://localhost/hot
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         args: Vec<String>,
     },
+    /// Run a pipeline defined in a YAML or TOML spec file
+    Run {
+        /// Pipeline spec (.yaml, .yml or .toml)
+        spec: String,
+        /// Print the resolved pipeline without connecting; exits non-zero on ambiguities
+        #[arg(long, alias = "explain")]
+        dry_run: bool,
+    },
     Kafka {
         /// Topic name
         #[arg(long)]
@@ -88,48 +216,74 @@ pub enum Commands {
     }
 }
 
+/// Stream join options shared by `bridge` and `pipe`.
+#[derive(clap::Args)]
+pub struct JoinArgs {
+    /// Second source (file:path, kafka://host/topic, stdin) joined with the source by key
+    #[arg(long, requires_all = ["join_on", "join_window_ms"])]
+    pub join_with: Option<String>,
+    /// Key field (dotted path) of source records
+    #[arg(long, requires = "join_with")]
+    pub join_on: Option<String>,
+    /// Key field of --join-with records (default: --join-on)
+    #[arg(long, requires = "join_with")]
+    pub join_right_on: Option<String>,
+    /// How far apart matching records may arrive
+    #[arg(long, requires = "join_with")]
+    pub join_window_ms: Option<u64>,
+    /// Records without a match within the window: drop (default), emit (other side null) or route
+    #[arg(long, requires = "join_with")]
+    pub join_unmatched: Option<Unmatched>,
+    /// Sink URI for unmatched records under --join-unmatched route (default: --dead-letter)
+    #[arg(long, requires = "join_with")]
+    pub join_unmatched_to: Option<String>,
+    /// Field holding the source record in joined messages (default left)
+    #[arg(long, requires = "join_with")]
+    pub join_left_as: Option<String>,
+    /// Field holding the --join-with record in joined messages (default right)
+    #[arg(long, requires = "join_with")]
+    pub join_right_as: Option<String>,
+}
+
+impl JoinArgs {
+    fn config(&self) -> Option<StreamJoinConfig> {
+        Some(StreamJoinConfig {
+            with: self.join_with.clone()?,
+            on: self.join_on.clone().unwrap_or_default(),
+            right_on: self.join_right_on.clone(),
+            window_ms: self.join_window_ms.unwrap_or_default(),
+            unmatched: self.join_unmatched.unwrap_or_default(),
+            unmatched_to: self.join_unmatched_to.clone(),
+            left_as: self.join_left_as.clone(),
+            right_as: self.join_right_as.clone(),
+        })
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
+    if let Err(e) = logging::init(cli.log_format, &cli.log_level, cli.otlp_endpoint.as_deref()) {
+        eprintln!("{e}");
+        std::process::exit(2);
+    }
 
-    // Helper: merge config from CLI and YAML for Bridge
-    fn load_middleware_config_bridge(
-        batch_size: Option<usize>,
-        batch_timeout_ms: Option<u64>,
-        deduplicate: bool,
-        throttle_per_sec: Option<u64>,
-        retry_max_attempts: Option<u32>,
-        retry_delay_ms: Option<u64>,
-        schema_path: Option<String>,
-        config_path: &Option<String>,
-    ) -> MiddlewareConfig {
-        let mut config = if let Some(ref path) = config_path {
-            let yaml = fs::read_to_string(path).expect("Failed to read config file");
-            serde_yaml::from_str::<MiddlewareConfig>(&yaml).expect("Invalid YAML")
-        } else {
-            MiddlewareConfig::default()
-        };
-        // CLI args override YAML
-        if let Some(bs) = batch_size {
-            config.batch_size = Some(bs);
-        }
-        if let Some(bt) = batch_timeout_ms {
-            config.batch_timeout_ms = Some(bt);
-        }
-        config.deduplicate = Some(deduplicate);
-        if let Some(tp) = throttle_per_sec {
-            config.throttle_per_sec = Some(tp);
-        }
-        if let Some(retry) = retry_max_attempts {
-            config.retry_max_attempts = Some(retry);
-        }
-        if let Some(delay) = retry_delay_ms {
-            config.retry_delay_ms = Some(delay);
-        }
-        if let Some(sp) = schema_path {
-            config.schema_path = Some(sp);
+    // Helper: read the bridge's YAML config, if any
+    fn read_middleware_config(config_path: Option<&str>) -> anyhow::Result<MiddlewareConfig> {
+        match config_path {
+            Some(path) => {
+                let yaml = fs::read_to_string(path)
+                    .map_err(|e| anyhow::anyhow!("Cannot read config file {path}: {e}"))?;
+                serde_yaml::from_str::<MiddlewareConfig>(&yaml)
+                    .map_err(|e| anyhow::anyhow!("Invalid YAML in {path}: {e}"))
+            }
+            None => Ok(MiddlewareConfig::default()),
         }
-        config
+    }
+
+    // Helper: files the middleware chain is built from, watched for reloads
+    fn reload_sources(config: &MiddlewareConfig, config_path: Option<&str>) -> Vec<std::path::PathBuf> {
+        config_path.into_iter().chain(config.schema_path.as_deref()).map(Into::into).collect()
     }
 
     // Helper: validate endpoints
@@ -143,43 +297,88 @@ async fn main() {
 
     match &cli.command {
         Commands::Convert { input, output, from, to } => {
-            let from_fmt = Format::from_ext(&from).unwrap_or_else(|| {
+            let from_fmt = Format::from_ext(from).unwrap_or_else(|| {
                 eprintln!("Unsupported input format: {from}");
                 std::process::exit(1);
             });
-            let to_fmt = Format::from_ext(&to).unwrap_or_else(|| {
+            let to_fmt = Format::from_ext(to).unwrap_or_else(|| {
                 eprintln!("Unsupported output format: {to}");
                 std::process::exit(1);
             });
 
-            if let Err(e) = convert(&input, &output, from_fmt, to_fmt) {
-                eprintln!("Conversion failed: {e}");
+            if let Err(e) = convert(input, output, from_fmt, to_fmt) {
+                tracing::error!("Conversion failed: {e}");
             } else {
-                println!("✓ Converted {input} ({from}) → {output} ({to})");
+                tracing::info!("✓ Converted {input} ({from}) → {output} ({to})");
             }
         }
         Commands::Bridge {
             source,
+            union,
             sink,
             batch_size,
             batch_timeout_ms,
             deduplicate,
+            dedup_by,
+            dedup_capacity,
+            dedup_ttl_ms,
             throttle_per_sec,
             retry_max_attempts,
             retry_delay_ms,
+            retry_max_delay_ms,
+            retry_backoff_multiplier,
+            retry_jitter,
+            circuit_breaker_threshold,
+            circuit_breaker_reset_ms,
             schema_path,
+            dead_letter,
+            shutdown_timeout_ms,
+            checkpoint_dir,
+            metrics_addr,
+            reload_interval_ms,
+            lineage,
+            pipeline_name,
+            event_time,
+            event_time_format,
+            join,
             config,
+            dry_run,
         } => {
-            let mw_config = load_middleware_config_bridge(
-                *batch_size,
-                *batch_timeout_ms,
-                *deduplicate,
-                *throttle_per_sec,
-                *retry_max_attempts,
-                *retry_delay_ms,
-                schema_path.clone(),
-                config,
-            );
+            let cli_overrides = MiddlewareConfig {
+                batch_size: *batch_size,
+                batch_timeout_ms: *batch_timeout_ms,
+                deduplicate: deduplicate.then_some(true),
+                dedup_by: dedup_by.clone(),
+                dedup_capacity: *dedup_capacity,
+                dedup_ttl_ms: *dedup_ttl_ms,
+                throttle_per_sec: *throttle_per_sec,
+                rate_limit: None,
+                retry_max_attempts: *retry_max_attempts,
+                retry_delay_ms: *retry_delay_ms,
+                retry_max_delay_ms: *retry_max_delay_ms,
+                retry_backoff_multiplier: *retry_backoff_multiplier,
+                retry_jitter: *retry_jitter,
+                circuit_breaker_threshold: *circuit_breaker_threshold,
+                circuit_breaker_reset_ms: *circuit_breaker_reset_ms,
+                schema_path: schema_path.clone(),
+                dead_letter: dead_letter.clone(),
+                shutdown_timeout_ms: *shutdown_timeout_ms,
+                checkpoint_dir: checkpoint_dir.clone(),
+                metrics_addr: metrics_addr.clone(),
+                reload_interval_ms: *reload_interval_ms,
+                lineage: lineage.clone(),
+                pipeline_name: pipeline_name.clone(),
+                event_time: event_time.as_ref().map(|field| EventTime::new(field, event_time_format.clone().unwrap_or_default())),
+            };
+            let file_config = match read_middleware_config(config.as_deref()) {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(2);
+                }
+            };
+            // CLI args override YAML
+            let mw_config = file_config.clone().with_overrides(&cli_overrides);
             
             // Parse endpoints
             let source_type = match SourceType::from_str(source) {
@@ -207,70 +406,233 @@ async fn main() {
                 }
             };
             
+            let mut union_types = Vec::new();
+            for uri in union {
+                match SourceType::from_str(uri) {
+                    Ok(st) => union_types.push(st),
+                    Err(e) => {
+                        eprintln!("Invalid --union source {uri}: {e}");
+                        std::process::exit(2);
+                    }
+                }
+            }
+
             // Validate endpoints
-            if let Err(e) = validate_endpoints(&source_type, &sink_type) {
-                eprintln!("Endpoint validation error: {e}");
-                std::process::exit(2);
+            for source_type in std::iter::once(&source_type).chain(&union_types) {
+                if let Err(e) = validate_endpoints(source_type, &sink_type) {
+                    eprintln!("Endpoint validation error: {e}");
+                    std::process::exit(2);
+                }
+            }
+            let stream_join = join.config();
+            if let Some(ref join) = stream_join {
+                let problems = spec::join_problems(join, mw_config.dead_letter.is_some());
+                for (field, message) in &problems {
+                    eprintln!("Invalid --join-{}: {message}", field.replace('_', "-"));
+                }
+                if !problems.is_empty() {
+                    std::process::exit(2);
+                }
+            }
+
+            if *dry_run {
+                let mut problems = ignored_global_options(&cli);
+                let side_sinks = [("dead_letter", mw_config.dead_letter.as_ref()), ("rate_limit.route_to", mw_config.rate_limit.as_ref().and_then(|rl| rl.route_to.as_ref()))];
+                for (setting, uri) in side_sinks {
+                    if let Some(Err(e)) = uri.map(|uri| SinkType::from_str(uri)) {
+                        problems.push(format!("{setting}: {e}"));
+                    }
+                }
+                // Nothing is connected, but the chain is built exactly as for a run
+                let dead_letter = mw_config.dead_letter.as_ref().map(|_| DeadLetterSender::discard());
+                let overflow = side_sinks[1].1.map(|_| DeadLetterSender::discard());
+                let chain = match build_middleware_chain(&mw_config, dead_letter.as_ref(), overflow.as_ref()) {
+                    Ok(chain) => chain,
+                    Err(e) => {
+                        eprintln!("Invalid middleware configuration: {e}");
+                        std::process::exit(2);
+                    }
+                };
+                problems.extend(explain::bridge(&[std::slice::from_ref(source), union].concat(), stream_join.as_ref(), sink, &chain, config.as_deref(), &file_config, &cli_overrides));
+                std::process::exit(explain::finish(&problems));
             }
             
-            let middleware_chain = build_middleware_chain(&mw_config);
+            let dead_letter_queue = mw_config.dead_letter.as_deref().map(spawn_dead_letter_queue);
+            let dead_letter_sender = dead_letter_queue.as_ref().map(|q| q.sender());
+            let overflow_queue = match mw_config.rate_limit {
+                Some(ref rate_limit) if rate_limit.mode == OverLimit::Route => {
+                    if rate_limit.route_to.is_none() && dead_letter_queue.is_none() {
+                        eprintln!("rate_limit mode 'route' requires route_to or dead_letter");
+                        std::process::exit(2);
+                    }
+                    rate_limit.route_to.as_deref().map(spawn_dead_letter_queue)
+                }
+                _ => None,
+            };
+            let overflow_sender = overflow_queue.as_ref().map(|q| q.sender());
+            let middleware_chain = match build_middleware_chain(&mw_config, dead_letter_sender.as_ref(), overflow_sender.as_ref()) {
+                Ok(chain) => chain,
+                Err(e) => {
+                    eprintln!("Invalid middleware configuration: {e}");
+                    std::process::exit(2);
+                }
+            };
+
+            // Rebuild the chain when the config or schema file changes
+            let reload_interval = mw_config.reload_interval_ms.unwrap_or(DEFAULT_RELOAD_INTERVAL_MS);
+            let watched = reload_sources(&mw_config, config.as_deref());
+            let reload = (reload_interval > 0 && !watched.is_empty()).then(|| {
+                let config_path = config.clone();
+                let active = mw_config.clone();
+                let mut applied = mw_config.clone(); // the last config reloaded
+                let (dlq, overflow) = (dead_letter_sender.clone(), overflow_sender.clone());
+                ChainReloader::watch(watched, std::time::Duration::from_millis(reload_interval), move || {
+                    let next = read_middleware_config(config_path.as_deref())?.with_overrides(&cli_overrides);
+                    let route_to = |c: &MiddlewareConfig| c.rate_limit.as_ref().and_then(|r| r.route_to.clone());
+                    if route_to(&next) != route_to(&active) {
+                        anyhow::bail!("rate_limit.route_to cannot change without a restart");
+                    }
+                    let chain = build_middleware_chain(&next, dlq.as_ref(), overflow.as_ref())?;
+                    // Warn once per change, and not when a setting returns to its running value
+                    let changed = restart_only_changes(&applied, &next);
+                    for setting in restart_only_changes(&active, &next).into_iter().filter(|s| changed.contains(s)) {
+                        tracing::warn!("Ignoring change to {setting} until the bridge is restarted");
+                    }
+                    let files = reload_sources(&next, config_path.as_deref());
+                    applied = next;
+                    Ok((chain, files))
+                })
+            });
+            drop(overflow_sender);
+
+            let metrics = start_metrics(mw_config.metrics_addr.as_deref()).await;
 
             // Build source
-            let source_box: Box<dyn fluxmux_core::traits::Source> = match source_type {
-                SourceType::File { path } => Box::new(FileSource { path }),
-                SourceType::Kafka { brokers, topic, group_id } => {
-                    Box::new(KafkaSource::new(brokers, topic, group_id))
-                }
-                SourceType::Stdin => Box::new(PipeSource::new()),
+            let inputs = std::iter::once((source.clone(), source_type)).chain(union.iter().cloned().zip(union_types)).collect();
+            let source_box = build_sources(inputs, mw_config.checkpoint_dir.as_deref(), metrics.as_ref());
+            let (source_box, unmatched_queue) = match stream_join {
+                Some(ref join) => join_source(source_box, join, dead_letter_sender.as_ref(), mw_config.checkpoint_dir.as_deref(), metrics.as_ref()),
+                None => (source_box, None),
             };
 
             // Build sink
-            let sink_box: Box<dyn fluxmux_core::traits::Sink> = match sink_type {
-                SinkType::File { path } => Box::new(FileSink::new(path, 1024)),
-                SinkType::Kafka { brokers, topic } => {
-                    Box::new(KafkaSink::new(brokers, topic))
-                }
-                SinkType::Postgres { connection_string, table, schema } => {
-                    Box::new(PostgresSink::new(connection_string, table, schema))
-                }
-                SinkType::Stdout => Box::new(PipeSink::new()),
+            let sink_box = sink_from_type(sink_type, &SinkOptions {
+                lineage: mw_config.lineage.clone(),
+                event_time: mw_config.event_time.is_some(),
+            });
+
+            let shutdown = shutdown_coordinator(mw_config.shutdown_timeout_ms);
+            let options = PipelineOptions {
+                dead_letter: dead_letter_sender,
+                shutdown: shutdown.clone(),
+                metrics,
+                retry: build_retry_policy(&mw_config),
+                circuit_breaker: build_circuit_breaker(&mw_config),
+                reload: reload.clone(),
+                lineage: mw_config.lineage.clone().map(|headers| {
+                    Lineage::new(headers, source.as_str(), mw_config.pipeline_name.as_deref().unwrap_or("bridge"))
+                }),
+                event_time: mw_config.event_time.clone(),
             };
 
-            println!("Starting bridge: {} → {}", source, sink);
-            if let Err(e) = run_pipeline(source_box, middleware_chain, sink_box).await {
-                eprintln!("Bridge failed: {e}");
-                std::process::exit(1);
+            tracing::info!("Starting bridge: {} → {}", source, sink);
+            let span = tracing::info_span!("pipeline", command = "bridge", source = %source, sink = %sink);
+            let result = run_pipeline(source_box, middleware_chain, sink_box, options).instrument(span).await;
+            if let Some(reload) = reload {
+                reload.stop();
+            }
+            close_dead_letter_queue(overflow_queue).await;
+            close_dead_letter_queue(unmatched_queue).await;
+            close_dead_letter_queue(dead_letter_queue).await;
+            if let Err(e) = result {
+                tracing::error!("Bridge failed: {e:#}");
+                exit(1);
+            }
+            if shutdown.is_triggered() {
+                tracing::warn!("Bridge interrupted; queued messages drained and sinks flushed");
+                exit(EXIT_INTERRUPTED);
             }
-            println!("✓ Bridge completed successfully");
+            tracing::info!("✓ Bridge completed successfully");
         }
-        Commands::Pipe { source, args } => {
-            // Parse source
-            let source_type = match SourceType::from_str(source) {
-                Ok(st) => st,
-                Err(e) => {
-                    eprintln!("Invalid source: {e}");
+        Commands::Pipe {
+            dead_letter,
+            shutdown_timeout_ms,
+            checkpoint_dir,
+            metrics_addr,
+            lineage,
+            pipeline_name,
+            event_time,
+            event_time_format,
+            join,
+            union,
+            dry_run,
+            source,
+            args,
+        } => {
+            let parsed = match pipe_args::parse(args) {
+                Ok(parsed) => parsed,
+                Err(errors) => {
+                    eprintln!("Invalid pipe arguments:");
+                    for e in errors {
+                        eprintln!("  {e}");
+                    }
                     std::process::exit(2);
                 }
             };
+            let spec = PipelineSpec {
+                source: source.clone(),
+                steps: parsed.steps,
+                sinks: parsed.sinks,
+                dead_letter: dead_letter.clone(),
+                retry: None,
+                circuit_breaker: None,
+                shutdown_timeout_ms: *shutdown_timeout_ms,
+                checkpoint_dir: checkpoint_dir.clone(),
+                metrics_addr: metrics_addr.clone(),
+                lineage: lineage.clone(),
+                name: Some(pipeline_name.clone().unwrap_or_else(|| "pipe".to_string())),
+                event_time: event_time.as_ref().map(|field| EventTime::new(field, event_time_format.clone().unwrap_or_default())),
+                stream_join: join.config(),
+                union: union.clone(),
+            };
+            let errors = spec.validate();
+            if !errors.is_empty() {
+                eprintln!("Invalid pipe arguments:");
+                for e in errors {
+                    eprintln!("  {e}");
+                }
+                std::process::exit(2);
+            }
+            if *dry_run {
+                explain::pipeline(&spec);
+                std::process::exit(explain::finish(&ignored_global_options(&cli)));
+            }
 
-            // Parse actions and sinks from args
-            let (actions, sinks) = parse_pipe_args(args);
-
-            // Build source
-            let source_box: Box<dyn fluxmux_core::traits::Source> = match source_type {
-                SourceType::File { path } => Box::new(FileSource { path }),
-                SourceType::Kafka { brokers, topic, group_id } => {
-                    Box::new(KafkaSource::new(brokers, topic, group_id))
+            let span = tracing::info_span!("pipeline", command = "pipe", source = %source);
+            run_spec(spec, "Pipe", span).await;
+        }
+        Commands::Run { spec: spec_path, dry_run } => {
+            let mut spec = match PipelineSpec::load(spec_path) {
+                Ok(spec) => spec,
+                Err(errors) => {
+                    eprintln!("Invalid pipeline spec {spec_path}:");
+                    for e in errors {
+                        eprintln!("  {e}");
+                    }
+                    std::process::exit(2);
                 }
-                SourceType::Stdin => Box::new(PipeSource::new()),
             };
-
-            println!("Starting pipe from {}", source);
-            if let Err(e) = run_pipe(source_box, actions, sinks).await {
-                eprintln!("Pipe failed: {e}");
-                std::process::exit(1);
+            // Unnamed pipelines are named after their spec file
+            if spec.name.is_none() {
+                spec.name = std::path::Path::new(spec_path).file_stem().map(|stem| stem.to_string_lossy().into_owned());
+            }
+            if *dry_run {
+                explain::pipeline(&spec);
+                std::process::exit(explain::finish(&ignored_global_options(&cli)));
             }
-            println!("✓ Pipe completed successfully");
+
+            let span = tracing::info_span!("pipeline", command = "run", spec = %spec_path, source = %spec.source);
+            run_spec(spec, "Pipeline", span).await;
         }
         Commands::Kafka { topic, broker, group, head, tail } => {
             if head.is_none() && tail.is_none() {
@@ -280,157 +642,299 @@ async fn main() {
 
             if let Some(n) = head {
                 if let Err(e) = kafka_inspector::kafka_head(broker, topic, group, *n).await {
-                    eprintln!("Head failed: {}", e);
+                    tracing::error!("Head failed: {}", e);
                     std::process::exit(1);
                 }
             } else if let Some(n) = tail {
                 if let Err(e) = kafka_inspector::kafka_tail(broker, topic, group, *n).await {
-                    eprintln!("Tail failed: {}", e);
+                    tracing::error!("Tail failed: {}", e);
                     std::process::exit(1);
                 }
             }
         }
     }
+    logging::shutdown();
 }
 
-fn parse_pipe_args(args: &[String]) -> (Vec<Box<dyn fluxmux_core::pipe_actions::PipeAction>>, Vec<Box<dyn fluxmux_core::traits::Sink>>) {
-    let mut actions: Vec<Box<dyn fluxmux_core::pipe_actions::PipeAction>> = vec![];
-    let mut sinks: Vec<Box<dyn fluxmux_core::traits::Sink>> = vec![];
-    
-    let mut i = 0;
-    while i < args.len() {
-        let cmd = &args[i];
-        match cmd.as_str() {
-            "filter" => {
-                if i + 1 < args.len() {
-                    actions.push(Box::new(FilterAction::new(args[i + 1].clone())));
-                    i += 2;
-                } else {
-                    eprintln!("filter requires an expression");
-                    i += 1;
-                }
-            }
-            "transform" => {
-                if i + 1 < args.len() {
-                    actions.push(Box::new(TransformAction::new(args[i + 1].clone())));
-                    i += 2;
-                } else {
-                    eprintln!("transform requires an expression");
-                    i += 1;
-                }
+/// Run a validated `pipe` or `run` pipeline; `what` names it in log messages.
+async fn run_spec(spec: PipelineSpec, what: &str, span: tracing::Span) {
+    let source_type = match SourceType::from_str(&spec.source) {
+        Ok(st) => st,
+        Err(e) => {
+            eprintln!("Invalid source: {e}");
+            std::process::exit(2);
+        }
+    };
+
+    let dead_letter_queue = spec.dead_letter.as_deref().map(spawn_dead_letter_queue);
+    let dead_letter_sender = dead_letter_queue.as_ref().map(|q| q.sender());
+
+    // Rate limit steps in route mode, and aggregate steps routing late
+    // messages, may send to their own sink
+    let mut route_queues = Vec::new();
+    let mut actions = Vec::new();
+    let outputs = spec.outputs();
+    for (i, step) in spec.steps.iter().enumerate() {
+        let route = match step {
+            spec::Step::Ratelimit(rl) if rl.mode == OverLimit::Route => {
+                rl.route_to.as_deref().map(spawn_dead_letter_queue)
             }
-            "aggregate" => {
-                let mut group_by = None;
-                let mut ops = vec![];
-                i += 1;
-                
-                while i < args.len() && !args[i].starts_with("--") {
-                    if args[i] == "--group-by" && i + 1 < args.len() {
-                        group_by = Some(args[i + 1].clone());
-                        i += 2;
-                    } else if args[i] == "--avg" && i + 1 < args.len() {
-                        ops.push(("avg".to_string(), args[i + 1].clone()));
-                        i += 2;
-                    } else if args[i] == "--sum" && i + 1 < args.len() {
-                        ops.push(("sum".to_string(), args[i + 1].clone()));
-                        i += 2;
-                    } else if args[i] == "--min" && i + 1 < args.len() {
-                        ops.push(("min".to_string(), args[i + 1].clone()));
-                        i += 2;
-                    } else if args[i] == "--max" && i + 1 < args.len() {
-                        ops.push(("max".to_string(), args[i + 1].clone()));
-                        i += 2;
-                    } else if args[i] == "--count" {
-                        ops.push(("count".to_string(), "_".to_string()));
-                        i += 1;
-                    } else {
-                        break;
-                    }
-                }
-                actions.push(Box::new(AggregateAction::new(group_by, ops)));
+            spec::Step::Aggregate(agg) if agg.late == LatePolicy::Route => {
+                agg.late_to.as_deref().map(spawn_dead_letter_queue)
             }
-            "normalize" => {
-                if i + 1 < args.len() && args[i + 1].starts_with("--schema") && i + 2 < args.len() {
-                    actions.push(Box::new(NormalizeAction::new(Some(args[i + 2].clone()))));
-                    i += 3;
-                } else {
-                    actions.push(Box::new(NormalizeAction::new(None)));
-                    i += 1;
-                }
+            _ => None,
+        };
+        let route_sender = route.as_ref().map(|q| q.sender());
+        match step.build(dead_letter_sender.as_ref(), route_sender.as_ref(), &outputs) {
+            Ok(action) => actions.push(action),
+            Err(e) => {
+                eprintln!("Invalid step steps[{i}].{}: {e:#}", step.name());
+                std::process::exit(2);
             }
-            "validate" => {
-                if i + 1 < args.len() && args[i + 1].starts_with("--schema") && i + 2 < args.len() {
-                    actions.push(Box::new(ValidateAction::new(Some(args[i + 2].clone()))));
-                    i += 3;
-                } else {
-                    actions.push(Box::new(ValidateAction::new(None)));
-                    i += 1;
-                }
+        }
+        route_queues.extend(route);
+    }
+
+    let mut sinks = Vec::new();
+    let sink_options = SinkOptions { lineage: spec.lineage.clone(), event_time: spec.event_time.is_some() };
+    for uri in &outputs {
+        match sink_from_uri(uri, &sink_options) {
+            Ok(sink) => sinks.push(sink),
+            Err(e) => {
+                eprintln!("Invalid sink {uri}: {e}");
+                std::process::exit(2);
             }
-            "limit" => {
-                if i + 1 < args.len() {
-                    if let Ok(n) = args[i + 1].parse::<usize>() {
-                        actions.push(Box::new(LimitAction::new(n)));
-                    }
-                    i += 2;
-                } else {
-                    i += 1;
-                }
+        }
+    }
+
+    let metrics = start_metrics(spec.metrics_addr.as_deref()).await;
+
+    let mut inputs = vec![(spec.source.clone(), source_type)];
+    for uri in &spec.union {
+        match SourceType::from_str(uri) {
+            Ok(st) => inputs.push((uri.clone(), st)),
+            Err(e) => {
+                eprintln!("Invalid union source {uri}: {e}");
+                std::process::exit(2);
             }
-            "sample" => {
-                if i + 1 < args.len() {
-                    if let Ok(n) = args[i + 1].parse::<usize>() {
-                        actions.push(Box::new(SampleAction::new(n)));
-                    }
-                    i += 2;
-                } else {
-                    i += 1;
-                }
+        }
+    }
+    let source_box = build_sources(inputs, spec.checkpoint_dir.as_deref(), metrics.as_ref());
+    let (source_box, unmatched_queue) = match spec.stream_join {
+        Some(ref join) => join_source(source_box, join, dead_letter_sender.as_ref(), spec.checkpoint_dir.as_deref(), metrics.as_ref()),
+        None => (source_box, None),
+    };
+
+    let shutdown = shutdown_coordinator(spec.shutdown_timeout_ms);
+    let options = PipelineOptions {
+        dead_letter: dead_letter_sender,
+        shutdown: shutdown.clone(),
+        metrics,
+        retry: spec.retry_policy(),
+        circuit_breaker: spec.circuit_breaker(),
+        lineage: spec.lineage.clone().map(|headers| {
+            Lineage::new(headers, spec.source.as_str(), spec.name.as_deref().unwrap_or(&what.to_lowercase()))
+        }),
+        event_time: spec.event_time.clone(),
+        ..Default::default()
+    };
+
+    tracing::info!("Starting {}: {} → {}", what.to_lowercase(), spec.source, outputs.join(", "));
+    let result = run_pipe(source_box, actions, sinks, options).instrument(span).await;
+    for queue in route_queues {
+        close_dead_letter_queue(Some(queue)).await;
+    }
+    close_dead_letter_queue(unmatched_queue).await;
+    close_dead_letter_queue(dead_letter_queue).await;
+    if let Err(e) = result {
+        tracing::error!("{what} failed: {e:#}");
+        exit(1);
+    }
+    if shutdown.is_triggered() {
+        tracing::warn!("{what} interrupted; queued messages drained and sinks flushed");
+        exit(EXIT_INTERRUPTED);
+    }
+    tracing::info!("✓ {what} completed successfully");
+}
+
+/// Options before the subcommand that no command reads.
+fn ignored_global_options(cli: &Cli) -> Vec<String> {
+    let mut ignored = Vec::new();
+    for (flag, given) in [
+        ("--batch-size", cli.batch_size.is_some()),
+        ("--deduplicate", cli.deduplicate.is_some()),
+        ("--throttle-per-sec", cli.throttle_per_sec.is_some()),
+        ("--config", cli.config.is_some()),
+    ] {
+        if given {
+            ignored.push(format!("{flag} before the subcommand is ignored"));
+        }
+    }
+    ignored
+}
+
+/// Exit after a pipeline ran, exporting its remaining trace spans first.
+fn exit(code: i32) -> ! {
+    logging::shutdown();
+    std::process::exit(code)
+}
+
+fn build_source(
+    source_type: SourceType,
+    checkpoint_dir: Option<&str>,
+    metrics: Option<&Metrics>,
+) -> Box<dyn fluxmux_core::traits::Source> {
+    let checkpoints = checkpoint_dir.map(|dir| match CheckpointStore::open(dir) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Cannot open checkpoint directory {dir}: {e}");
+            std::process::exit(2);
+        }
+    });
+
+    match source_type {
+        SourceType::File { path } => {
+            let source = FileSource::new(path);
+            match checkpoints {
+                Some(store) => Box::new(source.with_checkpoints(store)),
+                None => Box::new(source),
             }
-            "tee" => {
-                i += 1;
-                // Collect all following args as sink destinations until next action
-                while i < args.len() && !is_action(&args[i]) {
-                    if let Ok(sink) = parse_sink_endpoint(&args[i]) {
-                        sinks.push(sink);
-                    }
-                    i += 1;
-                }
+        }
+        SourceType::Kafka { brokers, topic, group_id } => {
+            let mut source = KafkaSource::new(brokers, topic, group_id);
+            if let Some(store) = checkpoints {
+                source = source.with_checkpoints(store);
             }
-            other => {
-                // Try to parse as sink endpoint (for final output)
-                if let Ok(sink) = parse_sink_endpoint(other) {
-                    sinks.push(sink);
-                }
-                i += 1;
+            if let Some(metrics) = metrics {
+                source = source.with_metrics(metrics.clone());
             }
+            Box::new(source)
         }
+        SourceType::Stdin => Box::new(PipeSource::new()),
     }
-    
-    // If no sinks specified, default to stdout
-    if sinks.is_empty() {
-        sinks.push(Box::new(PipeSink::new()));
+}
+
+/// One source, or a union of several, each given with its URI.
+fn build_sources(
+    mut inputs: Vec<(String, SourceType)>,
+    checkpoint_dir: Option<&str>,
+    metrics: Option<&Metrics>,
+) -> Box<dyn fluxmux_core::traits::Source> {
+    if inputs.len() == 1 {
+        let (_, source_type) = inputs.remove(0);
+        return build_source(source_type, checkpoint_dir, metrics);
     }
-    
-    (actions, sinks)
+    let inputs = inputs.into_iter().map(|(uri, st)| (uri, build_source(st, checkpoint_dir, metrics))).collect();
+    Box::new(UnionSource::new(inputs))
 }
 
-fn is_action(s: &str) -> bool {
-    matches!(s, "filter" | "transform" | "aggregate" | "normalize" | "validate" | "limit" | "sample" | "tee" | "buffer")
+/// Join `left` with the stream join's second source; unmatched records
+/// under `route` go to their own queue (returned) or the dead-letter queue.
+fn join_source(
+    left: Box<dyn fluxmux_core::traits::Source>,
+    join: &StreamJoinConfig,
+    dead_letter: Option<&DeadLetterSender>,
+    checkpoint_dir: Option<&str>,
+    metrics: Option<&Metrics>,
+) -> (Box<dyn fluxmux_core::traits::Source>, Option<DeadLetterQueue>) {
+    let right_type = match SourceType::from_str(&join.with) {
+        Ok(st) => st,
+        Err(e) => {
+            eprintln!("Invalid join source: {e}");
+            std::process::exit(2);
+        }
+    };
+    // A union of one tags the right side's records with their URI, so lineage
+    // does not credit them to the primary source
+    let right = build_source(right_type, checkpoint_dir, metrics);
+    let right = Box::new(UnionSource::new(vec![(join.with.clone(), right)]));
+    let joined = StreamJoin::new(left, right, join);
+    if join.unmatched != Unmatched::Route {
+        return (Box::new(joined), None);
+    }
+    let queue = join.unmatched_to.as_deref().map(spawn_dead_letter_queue);
+    match queue.as_ref().map(|q| q.sender()).or_else(|| dead_letter.cloned()) {
+        Some(route) => (Box::new(joined.with_route(route)), queue),
+        None => (Box::new(joined), queue),
+    }
+}
+
+/// Settings that only take effect at startup and differ between two configs.
+fn restart_only_changes(active: &MiddlewareConfig, next: &MiddlewareConfig) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if active.dead_letter != next.dead_letter {
+        changed.push("dead_letter");
+    }
+    if active.checkpoint_dir != next.checkpoint_dir {
+        changed.push("checkpoint_dir");
+    }
+    if active.metrics_addr != next.metrics_addr {
+        changed.push("metrics_addr");
+    }
+    if active.shutdown_timeout_ms != next.shutdown_timeout_ms {
+        changed.push("shutdown_timeout_ms");
+    }
+    if active.reload_interval_ms != next.reload_interval_ms {
+        changed.push("reload_interval_ms");
+    }
+    if (&active.lineage, &active.pipeline_name) != (&next.lineage, &next.pipeline_name) {
+        changed.push("lineage settings");
+    }
+    if active.event_time != next.event_time {
+        changed.push("event_time");
+    }
+    let retry = |c: &MiddlewareConfig| {
+        (c.retry_max_attempts, c.retry_delay_ms, c.retry_max_delay_ms, c.retry_backoff_multiplier, c.retry_jitter)
+    };
+    if retry(active) != retry(next) {
+        changed.push("retry settings");
+    }
+    if (active.circuit_breaker_threshold, active.circuit_breaker_reset_ms) != (next.circuit_breaker_threshold, next.circuit_breaker_reset_ms) {
+        changed.push("circuit breaker settings");
+    }
+    changed
 }
 
-fn parse_sink_endpoint(s: &str) -> anyhow::Result<Box<dyn fluxmux_core::traits::Sink>> {
-    if s == "-" || s.eq_ignore_ascii_case("stdout") {
-        return Ok(Box::new(PipeSink::new()));
+fn spawn_dead_letter_queue(uri: &str) -> DeadLetterQueue {
+    match sink_from_uri(uri, &SinkOptions::default()) {
+        Ok(sink) => DeadLetterQueue::spawn(sink),
+        Err(e) => {
+            eprintln!("Invalid dead-letter sink: {e}");
+            std::process::exit(2);
+        }
     }
-    
-    let sink_type = SinkType::from_str(s)?;
-    match sink_type {
-        SinkType::File { path } => Ok(Box::new(FileSink::new(path, 1024))),
-        SinkType::Kafka { brokers, topic } => Ok(Box::new(KafkaSink::new(brokers, topic))),
-        SinkType::Postgres { connection_string, table, schema } => {
-            Ok(Box::new(PostgresSink::new(connection_string, table, schema)))
+}
+
+async fn close_dead_letter_queue(queue: Option<DeadLetterQueue>) {
+    if let Some(queue) = queue {
+        if let Err(e) = queue.close().await {
+            tracing::error!("Dead-letter queue failed: {e}");
         }
-        SinkType::Stdout => Ok(Box::new(PipeSink::new())),
     }
 }
+
+async fn start_metrics(addr: Option<&str>) -> Option<Metrics> {
+    let addr = addr?;
+    let socket_addr: SocketAddr = match addr.parse() {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("Invalid metrics address {addr}: {e}");
+            std::process::exit(2);
+        }
+    };
+    let metrics = Metrics::new();
+    if let Err(e) = metrics.serve(socket_addr).await {
+        eprintln!("Cannot serve metrics on {addr}: {e}");
+        std::process::exit(2);
+    }
+    tracing::info!("Serving metrics on http://{addr}/metrics");
+    Some(metrics)
+}
+
+fn shutdown_coordinator(timeout_ms: Option<u64>) -> Shutdown {
+    let timeout = timeout_ms.unwrap_or(DEFAULT_DRAIN_TIMEOUT_MS);
+    let shutdown = Shutdown::new(std::time::Duration::from_millis(timeout));
+    shutdown.listen_for_signals();
+    shutdown
+}
+
@@ -0,0 +1,310 @@
+//! Parsing of the `fluxmux pipe` argument list into the same steps a spec
+//! file declares, e.g.
+//!
+//! ```text
+//! filter 'temp>30' aggregate --group-by sensor --avg temp --window tumbling --size-ms 60000 tee file:hot.ndjson stdout
+//! route 'level==error' kafka://localhost:9092/errors 'level==warn' file:warn.ndjson default stdout
+//! ```
+//!
+//! Every token must be consumed by an action or be a sink URI; anything else
+//! is reported rather than skipped.
+
+use crate::spec::{AggregateStep, JoinStep, RouteRule, RouteStep, SchemaStep, Step};
+use fluxmux_core::endpoints::SinkType;
+use fluxmux_core::rate_limit::{OverLimit, RateLimitConfig};
+use fluxmux_core::window::WindowKind;
+use std::str::FromStr;
+
+const ACTIONS: &[&str] = &["filter", "transform", "aggregate", "join", "normalize", "validate", "limit", "sample", "ratelimit", "route", "tee"];
+
+#[derive(Debug, Default)]
+pub struct PipeArgs {
+    pub steps: Vec<Step>,
+    /// Sink URIs, in the order given; empty means stdout
+    pub sinks: Vec<String>,
+}
+
+/// Parse `args`, returning every problem found rather than the first.
+pub fn parse(args: &[String]) -> Result<PipeArgs, Vec<String>> {
+    let mut parser = Parser { args, pos: 0, errors: Vec::new() };
+    let mut parsed = PipeArgs::default();
+
+    while let Some(token) = parser.next() {
+        match token {
+            "filter" => {
+                if let Some(expr) = parser.value("filter", "an expression") {
+                    parsed.steps.push(Step::Filter(expr));
+                }
+            }
+            "transform" => {
+                if let Some(expr) = parser.value("transform", "an expression") {
+                    parsed.steps.push(Step::Transform(expr));
+                }
+            }
+            "aggregate" => {
+                let mut agg = AggregateStep::default();
+                let (mut window, mut size_ms, mut advance_ms, mut gap_ms) = (None, None, None, None);
+                while let Some(option) = parser.option() {
+                    match option {
+                        "--count" => agg.count = true,
+                        "--group-by" => agg.group_by = parser.value("aggregate --group-by", "a field"),
+                        "--avg" | "--sum" | "--min" | "--max" => {
+                            let Some(field) = parser.value(&format!("aggregate {}", option), "a field") else { continue };
+                            match option {
+                                "--avg" => agg.avg.push(field),
+                                "--sum" => agg.sum.push(field),
+                                "--min" => agg.min.push(field),
+                                _ => agg.max.push(field),
+                            }
+                        }
+                        "--window" => window = parser.value("aggregate --window", "tumbling, hopping or session"),
+                        "--size-ms" => size_ms = parser.number("aggregate --size-ms"),
+                        "--advance-ms" => advance_ms = parser.number("aggregate --advance-ms"),
+                        "--gap-ms" => gap_ms = parser.number("aggregate --gap-ms"),
+                        "--time" => agg.time = parser.parsed("aggregate --time", "processing or event"),
+                        "--time-field" => agg.time_field = parser.value("aggregate --time-field", "a field"),
+                        "--time-format" => agg.time_format = parser.parsed("aggregate --time-format", "a time format"),
+                        "--allowed-lateness-ms" => agg.allowed_lateness_ms = parser.number("aggregate --allowed-lateness-ms"),
+                        "--late" => agg.late = parser.parsed("aggregate --late", "drop, route or update").unwrap_or_default(),
+                        "--late-to" => agg.late_to = parser.value("aggregate --late-to", "a sink URI"),
+                        other => parser.unknown_option("aggregate", other),
+                    }
+                }
+                // Options the chosen window (or no window) does not take
+                let takes: &[&str] = match window.as_deref() {
+                    Some("tumbling") => &["--size-ms"],
+                    Some("hopping") => &["--size-ms", "--advance-ms"],
+                    Some("session") => &["--gap-ms"],
+                    _ => &[],
+                };
+                for (option, given) in [("--size-ms", size_ms), ("--advance-ms", advance_ms), ("--gap-ms", gap_ms)] {
+                    if given.is_some() && !takes.contains(&option) {
+                        let window = window.as_deref().map_or("no window".to_string(), |w| format!("a {} window", w));
+                        parser.errors.push(format!("aggregate {}: not used with {}", option, window));
+                    }
+                }
+                agg.window = match window.as_deref() {
+                    None => None,
+                    Some("tumbling") => Some(WindowKind::Tumbling { size_ms: parser.required(size_ms, "aggregate --size-ms") }),
+                    Some("hopping") => Some(WindowKind::Hopping {
+                        size_ms: parser.required(size_ms, "aggregate --size-ms"),
+                        advance_ms: parser.required(advance_ms, "aggregate --advance-ms"),
+                    }),
+                    Some("session") => Some(WindowKind::Session { gap_ms: parser.required(gap_ms, "aggregate --gap-ms") }),
+                    Some(other) => {
+                        parser.errors.push(format!("aggregate --window: unknown window '{}', expected tumbling, hopping or session", other));
+                        None
+                    }
+                };
+                parsed.steps.push(Step::Aggregate(agg));
+            }
+            "join" => {
+                let Some(table) = parser.value("join", "a table file") else { continue };
+                let mut join = JoinStep {
+                    table,
+                    format: None,
+                    on: String::new(),
+                    table_key: None,
+                    columns: vec![],
+                    kind: Default::default(),
+                    reload: false,
+                    reload_interval_ms: None,
+                };
+                while let Some(option) = parser.option() {
+                    match option {
+                        "--on" => join.on = parser.value("join --on", "a field").unwrap_or_default(),
+                        "--table-key" => join.table_key = parser.value("join --table-key", "a column"),
+                        "--columns" => {
+                            if let Some(columns) = parser.value("join --columns", "comma-separated columns") {
+                                join.columns.extend(columns.split(',').map(|c| c.trim().to_string()));
+                            }
+                        }
+                        "--kind" => join.kind = parser.parsed("join --kind", "inner or left").unwrap_or(join.kind),
+                        "--format" => join.format = parser.value("join --format", "a format"),
+                        "--reload" => join.reload = true,
+                        "--reload-interval-ms" => join.reload_interval_ms = parser.number("join --reload-interval-ms"),
+                        other => parser.unknown_option("join", other),
+                    }
+                }
+                if join.on.is_empty() {
+                    parser.errors.push("join --on: required".to_string());
+                    continue;
+                }
+                parsed.steps.push(Step::Join(join));
+            }
+            "normalize" | "validate" => {
+                let mut step = SchemaStep::default();
+                while let Some(option) = parser.option() {
+                    match option {
+                        "--schema" => step.schema = parser.value(&format!("{} --schema", token), "a path"),
+                        other => parser.unknown_option(token, other),
+                    }
+                }
+                parsed.steps.push(if token == "normalize" { Step::Normalize(step) } else { Step::Validate(step) });
+            }
+            "limit" => {
+                if let Some(n) = parser.number("limit") {
+                    parsed.steps.push(Step::Limit(n));
+                }
+            }
+            "sample" => {
+                if let Some(n) = parser.number("sample") {
+                    parsed.steps.push(Step::Sample(n));
+                }
+            }
+            "ratelimit" => {
+                let Some(rate_per_sec) = parser.number("ratelimit") else { continue };
+                let mut cfg = RateLimitConfig {
+                    rate_per_sec,
+                    burst: None,
+                    per_key: false,
+                    per_field: None,
+                    mode: OverLimit::Delay,
+                    route_to: None,
+                };
+                while let Some(option) = parser.option() {
+                    match option {
+                        "--per-key" => cfg.per_key = true,
+                        "--burst" => cfg.burst = parser.number("ratelimit --burst").or(cfg.burst),
+                        "--by" => cfg.per_field = parser.value("ratelimit --by", "a field"),
+                        "--mode" => cfg.mode = parser.parsed("ratelimit --mode", "delay, drop or route").unwrap_or(cfg.mode),
+                        other => parser.unknown_option("ratelimit", other),
+                    }
+                }
+                parsed.steps.push(Step::Ratelimit(cfg));
+            }
+            "route" => {
+                // Predicates each followed by their sinks, then `default` and its sinks
+                let mut route = RouteStep::default();
+                let mut in_default = false;
+                while let Some(token) = parser.peek().filter(|t| !ACTIONS.contains(t)) {
+                    parser.pos += 1;
+                    match token {
+                        "--mode" => route.mode = parser.parsed("route --mode", "first or all").unwrap_or(route.mode),
+                        option if option.starts_with("--") => parser.unknown_option("route", option),
+                        "default" if in_default => parser.errors.push("route: default is given more than once".to_string()),
+                        "default" => in_default = true,
+                        uri if SinkType::from_str(uri).is_ok() => match (in_default, route.rules.last_mut()) {
+                            (true, _) => route.default.push(uri.to_string()),
+                            (false, Some(rule)) => rule.to.push(uri.to_string()),
+                            (false, None) => parser.errors.push(format!("route: sink {} has no predicate before it", uri)),
+                        },
+                        _ if in_default => parser.errors.push(format!("route: '{}' after default is not a sink URI", token)),
+                        predicate => route.rules.push(RouteRule { when: predicate.to_string(), to: vec![] }),
+                    }
+                }
+                for rule in route.rules.iter().filter(|rule| rule.to.is_empty()) {
+                    parser.errors.push(format!("route: predicate '{}' has no sink after it", rule.when));
+                }
+                if in_default && route.default.is_empty() {
+                    parser.errors.push("route: default expects at least one sink URI".to_string());
+                }
+                parsed.steps.push(Step::Route(route));
+            }
+            "tee" => {
+                let before = parsed.sinks.len();
+                while let Some(uri) = parser.peek().filter(|t| !ACTIONS.contains(t)) {
+                    parser.pos += 1;
+                    parser.sink(uri, &mut parsed.sinks);
+                }
+                if parsed.sinks.len() == before {
+                    parser.errors.push("tee: expects at least one sink URI".to_string());
+                }
+            }
+            other if other.starts_with("--") => parser.errors.push(format!(
+                "{}: not an option of the preceding action; options of pipe itself go before the source",
+                other
+            )),
+            // A bare sink URI, e.g. the final output
+            other => parser.sink(other, &mut parsed.sinks),
+        }
+    }
+
+    if parser.errors.is_empty() {
+        Ok(parsed)
+    } else {
+        Err(parser.errors)
+    }
+}
+
+struct Parser<'a> {
+    args: &'a [String],
+    pos: usize,
+    errors: Vec<String>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.args.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.peek()?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    /// The next `--option` of the current action, if any.
+    fn option(&mut self) -> Option<&'a str> {
+        self.peek().filter(|t| t.starts_with("--")).inspect(|_| self.pos += 1)
+    }
+
+    /// The value following `what`; an action name there means it is missing.
+    fn value(&mut self, what: &str, expected: &str) -> Option<String> {
+        match self.peek() {
+            Some(token) if !ACTIONS.contains(&token) && !token.starts_with("--") => {
+                self.pos += 1;
+                Some(token.to_string())
+            }
+            _ => {
+                self.errors.push(format!("{}: expects {}", what, expected));
+                None
+            }
+        }
+    }
+
+    /// A value parsed with `FromStr`, e.g. a mode name.
+    fn parsed<T: FromStr<Err = anyhow::Error>>(&mut self, what: &str, expected: &str) -> Option<T> {
+        let value = self.value(what, expected)?;
+        match value.parse() {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                self.errors.push(format!("{}: {}", what, e));
+                None
+            }
+        }
+    }
+
+    fn number<T: FromStr>(&mut self, what: &str) -> Option<T> {
+        let value = self.value(what, "a number")?;
+        match value.parse() {
+            Ok(n) => Some(n),
+            Err(_) => {
+                self.errors.push(format!("{}: '{}' is not a valid number", what, value));
+                None
+            }
+        }
+    }
+
+    /// A number option the chosen variant needs; 0 (and an error) if missing.
+    fn required(&mut self, value: Option<u64>, what: &str) -> u64 {
+        value.unwrap_or_else(|| {
+            self.errors.push(format!("{}: required for this window", what));
+            0
+        })
+    }
+
+    fn unknown_option(&mut self, action: &str, option: &str) {
+        self.errors.push(format!("{}: unknown option {}", action, option));
+    }
+
+    fn sink(&mut self, uri: &str, sinks: &mut Vec<String>) {
+        if let Err(e) = SinkType::from_str(uri) {
+            self.errors.push(format!("'{}' is neither an action nor a valid sink URI: {}", uri, e));
+        } else if sinks.iter().any(|s| s == uri) {
+            self.errors.push(format!("sink {} is given more than once", uri));
+        } else {
+            sinks.push(uri.to_string());
+        }
+    }
+}
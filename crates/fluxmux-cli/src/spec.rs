@@ -0,0 +1,790 @@
+//! Declarative pipeline definitions for `fluxmux run`.
+//!
+//! ```yaml
+//! source: kafka://localhost:9092/events?group=etl
+//! steps:
+//!   - validate: { schema: schema.json }
+//!   - filter: "temp>30"
+//!   - join: { table: devices.csv, on: device_id, columns: [site, owner] }
+//!   - dedup: { by: event_id, ttl_ms: 600000 }
+//!   - batch: { size: 100, timeout_ms: 2000 }
+//! sinks:
+//!   - file:hot.ndjson
+//!   - postgres:host=localhost user=etl?table=hot
+//! dead_letter: file:rejected.ndjson
+//! lineage: id,parents,source,ingest_time
+//! event_time: { field: ts, format: rfc3339 }
+//! ```
+
+use crate::conversions::{self, Format};
+use fluxmux_core::endpoints::{SinkType, SourceType};
+use fluxmux_core::dead_letter::DeadLetterSender;
+use fluxmux_core::dedup::DedupKey;
+use fluxmux_core::event_time::{EventTime, LatePolicy, TimeFormat};
+use fluxmux_core::expr::{self, Expr};
+use fluxmux_core::lineage::LineageHeaders;
+use fluxmux_core::lookup::{JoinKind, LookupTable};
+use fluxmux_core::middleware::{Batcher, Deduplicator, Throttler};
+use fluxmux_core::pipe_actions::*;
+use fluxmux_core::rate_limit::{OverLimit, RateLimitConfig, RateLimiter};
+use fluxmux_core::retry::{CircuitBreaker, RetryPolicy};
+use fluxmux_core::stream_join::{StreamJoinConfig, Unmatched};
+use fluxmux_core::window::{TimeDomain, Window, WindowKind, WindowTime};
+use serde::Deserialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineSpec {
+    pub source: String,
+    /// More sources merged with `source`; messages are tagged with theirs
+    #[serde(default)]
+    pub union: Vec<String>,
+    /// Actions and middleware, applied in order; each is a single-key map
+    /// such as `filter: "temp>30"`
+    #[serde(default, deserialize_with = "serde_yaml::with::singleton_map_recursive::deserialize")]
+    pub steps: Vec<Step>,
+    /// Every sink receives every message; defaults to stdout
+    #[serde(default)]
+    pub sinks: Vec<String>,
+    pub dead_letter: Option<String>,
+    pub retry: Option<RetrySpec>,
+    pub circuit_breaker: Option<CircuitBreakerSpec>,
+    pub shutdown_timeout_ms: Option<u64>,
+    pub checkpoint_dir: Option<String>,
+    pub metrics_addr: Option<String>,
+    /// Lineage headers stamped on messages, e.g. `id,parents,source`
+    pub lineage: Option<LineageHeaders>,
+    /// Value of the `pipeline` lineage header; defaults to the spec file name
+    pub name: Option<String>,
+    /// Payload field that sets each message's timestamp (its event time)
+    pub event_time: Option<EventTime>,
+    /// Second source joined with `source` by key within a time window
+    pub stream_join: Option<StreamJoinConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    Filter(String),
+    Transform(String),
+    Aggregate(AggregateStep),
+    Join(JoinStep),
+    Route(RouteStep),
+    Normalize(SchemaStep),
+    Validate(SchemaStep),
+    Limit(usize),
+    Sample(usize),
+    Ratelimit(RateLimitConfig),
+    Dedup(DedupStep),
+    Batch(BatchStep),
+    Throttle(u64),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AggregateStep {
+    pub group_by: Option<String>,
+    #[serde(default)]
+    pub avg: Vec<String>,
+    #[serde(default)]
+    pub sum: Vec<String>,
+    #[serde(default)]
+    pub min: Vec<String>,
+    #[serde(default)]
+    pub max: Vec<String>,
+    #[serde(default)]
+    pub count: bool,
+    /// Aggregate per time window, emitting each as it closes
+    pub window: Option<WindowKind>,
+    /// Clock the window follows; `event` uses the timestamp set by the
+    /// top-level `event_time`. Defaults to processing time, or event time
+    /// with a `time_field`
+    pub time: Option<TimeDomain>,
+    /// Payload field holding this window's event time
+    pub time_field: Option<String>,
+    /// Format of `time_field` (default auto: epoch ms or RFC 3339)
+    pub time_format: Option<TimeFormat>,
+    /// How long event time windows wait for out-of-order messages
+    pub allowed_lateness_ms: Option<u64>,
+    /// What happens to messages later than that
+    #[serde(default)]
+    pub late: LatePolicy,
+    /// Sink URI for `late: route`; defaults to the dead-letter sink
+    pub late_to: Option<String>,
+}
+
+impl AggregateStep {
+    pub fn is_event_time(&self) -> bool {
+        self.time_field.is_some() || self.time == Some(TimeDomain::Event)
+    }
+
+    fn window_time(&self) -> WindowTime {
+        match self.time_field {
+            Some(ref field) => WindowTime::Field(EventTime::new(field.clone(), self.time_format.clone().unwrap_or_default())),
+            None if self.is_event_time() => WindowTime::Timestamp,
+            None => WindowTime::Processing,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JoinStep {
+    /// Lookup table file
+    pub table: String,
+    /// Format of the table; defaults to its file extension
+    pub format: Option<String>,
+    /// Message field (dotted path) holding the key
+    pub on: String,
+    /// Table column matched against `on`; defaults to the last part of `on`
+    pub table_key: Option<String>,
+    /// Columns merged into the message; all but the key when empty
+    #[serde(default)]
+    pub columns: Vec<String>,
+    #[serde(default)]
+    pub kind: JoinKind,
+    /// Reload the table when its file changes
+    #[serde(default)]
+    pub reload: bool,
+    pub reload_interval_ms: Option<u64>,
+}
+
+impl JoinStep {
+    fn format(&self) -> Option<Format> {
+        let ext = match self.format {
+            Some(ref format) => format.as_str(),
+            None => Path::new(&self.table).extension()?.to_str()?,
+        };
+        Format::from_ext(ext)
+    }
+
+    pub fn table_key(&self) -> &str {
+        self.table_key.as_deref().unwrap_or_else(|| self.on.rsplit('.').next().unwrap_or(&self.on))
+    }
+
+    fn reload_interval_ms(&self) -> u64 {
+        self.reload_interval_ms.unwrap_or(fluxmux_core::reload::DEFAULT_RELOAD_INTERVAL_MS)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteStep {
+    #[serde(default)]
+    pub mode: RouteMode,
+    #[serde(default)]
+    pub rules: Vec<RouteRule>,
+    /// Sinks for messages matching no rule; without any they pass on unrouted
+    #[serde(default)]
+    pub default: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteRule {
+    /// Expression, e.g. `level==error`
+    pub when: String,
+    /// Sink URIs for matching messages
+    pub to: Vec<String>,
+}
+
+impl RouteStep {
+    fn targets(&self) -> impl Iterator<Item = &String> {
+        self.rules.iter().flat_map(|rule| &rule.to).chain(&self.default)
+    }
+}
+
+/// Read a lookup table file with the importers of `fluxmux convert`.
+fn import_table(path: &str, format: &Format) -> anyhow::Result<serde_json::Value> {
+    conversions::import(path, format).map_err(|e| anyhow::anyhow!("cannot load {}: {}", path, e))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaStep {
+    pub schema: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DedupStep {
+    #[serde(default)]
+    pub by: DedupKey,
+    pub capacity: Option<usize>,
+    pub ttl_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchStep {
+    pub size: usize,
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetrySpec {
+    pub max_attempts: u32,
+    pub delay_ms: Option<u64>,
+    pub max_delay_ms: Option<u64>,
+    pub backoff_multiplier: Option<f64>,
+    pub jitter: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreakerSpec {
+    pub threshold: u32,
+    pub reset_ms: Option<u64>,
+}
+
+/// A problem in a spec, located by its path (e.g. `steps[2].ratelimit.rate_per_sec`).
+#[derive(Debug)]
+pub struct SpecError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() || self.path == "." {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl PipelineSpec {
+    /// Read and validate a spec; `.toml` files are parsed as TOML, anything
+    /// else as YAML. Returns every problem found, not just the first.
+    pub fn load(path: &str) -> Result<Self, Vec<SpecError>> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            vec![SpecError { path: String::new(), message: format!("cannot read {}: {}", path, e) }]
+        })?;
+        let is_toml = Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+        let parsed: Result<Self, SpecError> = if is_toml {
+            serde_path_to_error::deserialize(toml::Deserializer::new(&text)).map_err(|e| SpecError {
+                path: e.path().to_string(),
+                message: e.inner().to_string().trim_end().to_string(),
+            })
+        } else {
+            serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(&text)).map_err(|e| {
+                let path = e.path().to_string();
+                // serde_yaml already prefixes nested errors with their path
+                let message = e.inner().to_string();
+                let message = match message.split_once(": ") {
+                    Some((prefix, rest)) if path.starts_with(prefix) => rest.to_string(),
+                    _ => message,
+                };
+                SpecError { path, message }
+            })
+        };
+        let spec = parsed.map_err(|e| vec![e])?;
+        let errors = spec.validate();
+        if errors.is_empty() {
+            Ok(spec)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Semantic checks serde cannot express: endpoint URIs, positive rates,
+    /// readable schema files.
+    pub fn validate(&self) -> Vec<SpecError> {
+        let mut errors = Vec::new();
+        let mut error = |path: String, message: String| errors.push(SpecError { path, message });
+
+        if let Err(e) = SourceType::from_str(&self.source) {
+            error("source".into(), e.to_string());
+        }
+        for (i, uri) in self.union.iter().enumerate() {
+            if let Err(e) = SourceType::from_str(uri) {
+                error(format!("union[{}]", i), e.to_string());
+            }
+        }
+        for (i, sink) in self.sinks.iter().enumerate() {
+            if let Err(e) = SinkType::from_str(sink) {
+                error(format!("sinks[{}]", i), e.to_string());
+            }
+        }
+        if let Some(ref dead_letter) = self.dead_letter {
+            if let Err(e) = SinkType::from_str(dead_letter) {
+                error("dead_letter".into(), e.to_string());
+            }
+        }
+        if let Some(ref addr) = self.metrics_addr {
+            if let Err(e) = addr.parse::<SocketAddr>() {
+                error("metrics_addr".into(), e.to_string());
+            }
+        }
+        if let Some(ref retry) = self.retry {
+            if retry.backoff_multiplier.is_some_and(|m| m < 1.0) {
+                error("retry.backoff_multiplier".into(), "must be at least 1.0".into());
+            }
+            if retry.jitter.is_some_and(|j| !(0.0..=1.0).contains(&j)) {
+                error("retry.jitter".into(), "must be between 0.0 and 1.0".into());
+            }
+        }
+        if self.circuit_breaker.as_ref().is_some_and(|cb| cb.threshold == 0) {
+            error("circuit_breaker.threshold".into(), "must be positive".into());
+        }
+        if self.event_time.as_ref().is_some_and(|et| et.field.is_empty()) {
+            error("event_time.field".into(), "must not be empty".into());
+        }
+        if let Some(ref join) = self.stream_join {
+            for (field, message) in join_problems(join, self.dead_letter.is_some()) {
+                error(format!("stream_join.{}", field), message);
+            }
+        }
+
+        for (i, step) in self.steps.iter().enumerate() {
+            let at = |field: &str| match field {
+                "" => format!("steps[{}].{}", i, step.name()),
+                field => format!("steps[{}].{}.{}", i, step.name(), field),
+            };
+            match step {
+                Step::Filter(expr) => {
+                    if let Err(e) = Expr::parse(expr) {
+                        error(at(""), e.to_string());
+                    }
+                }
+                Step::Transform(expr) => {
+                    if let Err(e) = expr::assignments(expr) {
+                        error(at(""), e.to_string());
+                    }
+                }
+                Step::Aggregate(agg)
+                    if agg.avg.is_empty() && agg.sum.is_empty() && agg.min.is_empty() && agg.max.is_empty() && !agg.count =>
+                {
+                    error(at(""), "needs at least one of avg, sum, min, max or count".into());
+                }
+                Step::Aggregate(agg) => {
+                    match agg.window {
+                        Some(WindowKind::Tumbling { size_ms: 0 } | WindowKind::Hopping { size_ms: 0, .. }) => {
+                            error(at("window.size_ms"), "must be positive".into());
+                        }
+                        Some(WindowKind::Hopping { advance_ms: 0, .. }) => error(at("window.advance_ms"), "must be positive".into()),
+                        Some(WindowKind::Session { gap_ms: 0 }) => error(at("window.gap_ms"), "must be positive".into()),
+                        _ => {}
+                    }
+                    let windowless = [
+                        ("time", agg.time.is_some()),
+                        ("time_field", agg.time_field.is_some()),
+                        ("allowed_lateness_ms", agg.allowed_lateness_ms.is_some()),
+                        ("late", agg.late != LatePolicy::Drop),
+                        ("late_to", agg.late_to.is_some()),
+                    ];
+                    if agg.window.is_none() {
+                        for (field, _) in windowless.iter().filter(|(_, given)| *given) {
+                            error(at(field), "requires a window".into());
+                        }
+                        continue;
+                    }
+                    match (agg.time, &agg.time_field) {
+                        (Some(TimeDomain::Processing), Some(_)) => error(at("time_field"), "cannot be used with time: processing".into()),
+                        (Some(TimeDomain::Event), None) if self.event_time.is_none() => {
+                            error(at("time"), "event requires time_field or a top-level event_time".into());
+                        }
+                        (_, None) if agg.time_format.is_some() => error(at("time_format"), "requires time_field".into()),
+                        _ => {}
+                    }
+                    if !agg.is_event_time() {
+                        for (field, _) in windowless[2..].iter().filter(|(_, given)| *given) {
+                            error(at(field), "requires event time".into());
+                        }
+                        continue;
+                    }
+                    if agg.late == LatePolicy::Update && agg.allowed_lateness_ms.unwrap_or(0) == 0 {
+                        error(at("late"), "update requires a positive allowed_lateness_ms".into());
+                    }
+                    match agg.late_to {
+                        Some(_) if agg.late != LatePolicy::Route => error(at("late_to"), "requires late: route".into()),
+                        Some(ref late_to) => {
+                            if let Err(e) = SinkType::from_str(late_to) {
+                                error(at("late_to"), e.to_string());
+                            }
+                        }
+                        None if agg.late == LatePolicy::Route && self.dead_letter.is_none() => {
+                            error(at("late"), "route requires late_to or a top-level dead_letter".into());
+                        }
+                        None => {}
+                    }
+                }
+                Step::Join(join) => {
+                    match (&join.format, join.format()) {
+                        (_, Some(_)) => {}
+                        (Some(format), None) => error(at("format"), format!("unknown format '{}', expected {}", format, TABLE_FORMATS)),
+                        (None, None) => error(at("table"), format!("cannot tell the format from the file name, set format to one of {}", TABLE_FORMATS)),
+                    }
+                    if let Err(e) = std::fs::File::open(&join.table) {
+                        error(at("table"), format!("cannot read {}: {}", join.table, e));
+                    }
+                    if join.on.is_empty() {
+                        error(at("on"), "must not be empty".into());
+                    }
+                    match join.reload_interval_ms {
+                        Some(_) if !join.reload => error(at("reload_interval_ms"), "requires reload".into()),
+                        Some(0) => error(at("reload_interval_ms"), "must be positive".into()),
+                        _ => {}
+                    }
+                }
+                Step::Route(route) => {
+                    if route.rules.is_empty() && route.default.is_empty() {
+                        error(at(""), "needs at least one rule or default".into());
+                    }
+                    let mut targets = vec![];
+                    for (j, rule) in route.rules.iter().enumerate() {
+                        if let Err(e) = Expr::parse(&rule.when) {
+                            error(at(&format!("rules[{}].when", j)), e.to_string());
+                        }
+                        if rule.to.is_empty() {
+                            error(at(&format!("rules[{}].to", j)), "needs at least one sink".into());
+                        }
+                        targets.extend(rule.to.iter().map(|uri| (format!("rules[{}].to", j), uri)));
+                    }
+                    targets.extend(route.default.iter().map(|uri| ("default".to_string(), uri)));
+                    for (field, uri) in targets {
+                        if let Err(e) = SinkType::from_str(uri) {
+                            error(at(&field), e.to_string());
+                        } else if self.sinks.contains(uri) {
+                            error(at(&field), format!("{} is also a sink of the pipeline, which gets every message not routed", uri));
+                        }
+                    }
+                }
+                Step::Normalize(SchemaStep { schema: Some(schema) }) | Step::Validate(SchemaStep { schema: Some(schema) }) => {
+                    if let Err(e) = check_schema(schema) {
+                        error(at("schema"), e);
+                    }
+                }
+                Step::Sample(0) => error(at(""), "must be positive".into()),
+                Step::Ratelimit(rl) => {
+                    if !(rl.rate_per_sec.is_finite() && rl.rate_per_sec > 0.0) {
+                        error(at("rate_per_sec"), "must be positive".into());
+                    }
+                    if rl.burst == Some(0) {
+                        error(at("burst"), "must be positive".into());
+                    }
+                    if rl.per_key && rl.per_field.is_some() {
+                        error(at("per_field"), "cannot be combined with per_key".into());
+                    }
+                    match rl.route_to {
+                        Some(ref route_to) => {
+                            if let Err(e) = SinkType::from_str(route_to) {
+                                error(at("route_to"), e.to_string());
+                            }
+                        }
+                        None if rl.mode == OverLimit::Route && self.dead_letter.is_none() => {
+                            error(at("mode"), "route requires route_to or a top-level dead_letter".into());
+                        }
+                        None => {}
+                    }
+                }
+                Step::Dedup(dedup) => {
+                    if dedup.capacity == Some(0) {
+                        error(at("capacity"), "must be positive".into());
+                    }
+                    if let DedupKey::Fields(ref fields) = dedup.by {
+                        if fields.iter().any(|f| f.is_empty()) {
+                            error(at("by"), "contains an empty field path".into());
+                        }
+                    }
+                }
+                Step::Batch(batch) if batch.size == 0 => error(at("size"), "must be positive".into()),
+                Step::Throttle(0) => error(at(""), "must be positive".into()),
+                _ => {}
+            }
+        }
+        errors
+    }
+
+    /// Sink URIs in the order the pipeline's sinks are built: its own (stdout
+    /// by default), then those only `route` steps send to.
+    pub fn outputs(&self) -> Vec<String> {
+        let mut outputs = if self.sinks.is_empty() { vec!["stdout".to_string()] } else { self.sinks.clone() };
+        let own = outputs.len();
+        for step in &self.steps {
+            if let Step::Route(route) = step {
+                for uri in route.targets() {
+                    if !outputs[own..].contains(uri) {
+                        outputs.push(uri.clone());
+                    }
+                }
+            }
+        }
+        outputs
+    }
+
+    pub fn retry_policy(&self) -> Option<RetryPolicy> {
+        let retry = self.retry.as_ref()?;
+        let mut policy = RetryPolicy::new(retry.max_attempts, Duration::from_millis(retry.delay_ms.unwrap_or(1000)));
+        if let Some(max_delay) = retry.max_delay_ms {
+            policy = policy.with_max_delay(Duration::from_millis(max_delay));
+        }
+        if let Some(multiplier) = retry.backoff_multiplier {
+            policy = policy.with_multiplier(multiplier);
+        }
+        if let Some(jitter) = retry.jitter {
+            policy = policy.with_jitter(jitter);
+        }
+        Some(policy)
+    }
+
+    pub fn circuit_breaker(&self) -> Option<CircuitBreaker> {
+        let cb = self.circuit_breaker.as_ref()?;
+        Some(CircuitBreaker::new(cb.threshold, Duration::from_millis(cb.reset_ms.unwrap_or(30_000))))
+    }
+}
+
+impl Step {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Step::Filter(_) => "filter",
+            Step::Transform(_) => "transform",
+            Step::Aggregate(_) => "aggregate",
+            Step::Join(_) => "join",
+            Step::Route(_) => "route",
+            Step::Normalize(_) => "normalize",
+            Step::Validate(_) => "validate",
+            Step::Limit(_) => "limit",
+            Step::Sample(_) => "sample",
+            Step::Ratelimit(_) => "ratelimit",
+            Step::Dedup(_) => "dedup",
+            Step::Batch(_) => "batch",
+            Step::Throttle(_) => "throttle",
+        }
+    }
+
+    /// Build the pipe action for this step. `route` receives over-limit
+    /// messages of a `ratelimit` step in route mode, or late messages of an
+    /// `aggregate` step with `late: route`, and defaults to the dead-letter
+    /// queue. `outputs` are the pipeline's sinks as `PipelineSpec::outputs`
+    /// lists them, which `route` steps send to. Fails when an expression
+    /// does not parse or a `join` table cannot be loaded.
+    pub fn build(
+        &self,
+        dead_letter: Option<&DeadLetterSender>,
+        route: Option<&DeadLetterSender>,
+        outputs: &[String],
+    ) -> anyhow::Result<Box<dyn PipeAction>> {
+        Ok(match self {
+            Step::Filter(expr) => Box::new(FilterAction::new(expr.clone())?),
+            Step::Transform(expr) => Box::new(TransformAction::new(expr.clone())?),
+            Step::Aggregate(agg) => {
+                let mut ops = Vec::new();
+                for (op, fields) in [("avg", &agg.avg), ("sum", &agg.sum), ("min", &agg.min), ("max", &agg.max)] {
+                    ops.extend(fields.iter().map(|f| (op.to_string(), f.clone())));
+                }
+                if agg.count {
+                    ops.push(("count".to_string(), "_".to_string()));
+                }
+                let mut aggregate = AggregateAction::new(agg.group_by.clone(), ops);
+                if let Some(kind) = agg.window {
+                    aggregate = aggregate
+                        .with_window(Window::new(kind, agg.window_time()))
+                        .with_lateness(Duration::from_millis(agg.allowed_lateness_ms.unwrap_or(0)), agg.late);
+                }
+                if let Some(route) = route.or(dead_letter) {
+                    aggregate = aggregate.with_late_route(route.clone());
+                }
+                Box::new(aggregate)
+            }
+            Step::Join(join) => {
+                let format = join.format().ok_or_else(|| anyhow::anyhow!("unknown format for {}", join.table))?;
+                let table = LookupTable::from_rows(import_table(&join.table, &format)?, join.table_key())
+                    .map_err(|e| anyhow::anyhow!("{}: {}", join.table, e))?;
+                let mut action = JoinAction::new(join.on.clone(), join.table_key(), join.kind, table)
+                    .with_columns(join.columns.clone());
+                if join.reload {
+                    let path = join.table.clone();
+                    let loader = move || import_table(&path, &format);
+                    let interval = Duration::from_millis(join.reload_interval_ms());
+                    action = action.with_reload(&join.table, interval, std::sync::Arc::new(loader));
+                }
+                Box::new(action)
+            }
+            Step::Route(step) => {
+                // Route sinks follow the pipeline's own (a default stdout among them)
+                let indices = |uris: &[String]| -> anyhow::Result<Vec<usize>> {
+                    uris.iter().map(|uri| outputs.iter().rposition(|o| o == uri).ok_or_else(|| anyhow::anyhow!("no sink {}", uri))).collect()
+                };
+                let mut action = RouteAction::new(step.mode).default_to(indices(&step.default)?);
+                for rule in &step.rules {
+                    action = action.rule(Expr::parse(&rule.when)?, indices(&rule.to)?);
+                }
+                Box::new(action)
+            }
+            Step::Normalize(step) => Box::new(NormalizeAction::new(step.schema.clone())),
+            Step::Validate(step) => {
+                let mut validate = ValidateAction::new(step.schema.clone());
+                if let Some(dlq) = dead_letter {
+                    validate = validate.with_dead_letter(dlq.clone());
+                }
+                Box::new(validate)
+            }
+            Step::Limit(n) => Box::new(LimitAction::new(*n)),
+            Step::Sample(n) => Box::new(SampleAction::new(*n)),
+            Step::Ratelimit(cfg) => {
+                let mut action = RateLimitAction::new(RateLimiter::from_config(cfg), cfg.mode);
+                if let Some(route) = route.or(dead_letter) {
+                    action = action.with_route(route.clone());
+                }
+                Box::new(action)
+            }
+            Step::Dedup(dedup) => {
+                let dedup = Deduplicator::with_key(dedup.by.clone())
+                    .bounded(dedup.capacity, dedup.ttl_ms.map(Duration::from_millis));
+                Box::new(MiddlewareAction::new(Box::new(dedup)))
+            }
+            Step::Batch(batch) => {
+                let batcher = Batcher::new(batch.size, batch.timeout_ms.unwrap_or(5000));
+                Box::new(MiddlewareAction::new(Box::new(batcher)))
+            }
+            Step::Throttle(rate) => Box::new(MiddlewareAction::new(Box::new(Throttler::new(*rate)))),
+        })
+    }
+}
+
+/// The step with its resolved parameters, e.g. `batch size=100 timeout_ms=5000`.
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        match self {
+            Step::Filter(expr) | Step::Transform(expr) => write!(f, " {:?}", expr),
+            Step::Aggregate(agg) => {
+                write!(f, " group_by={}", agg.group_by.as_deref().unwrap_or("(none)"))?;
+                for (op, fields) in [("avg", &agg.avg), ("sum", &agg.sum), ("min", &agg.min), ("max", &agg.max)] {
+                    if !fields.is_empty() {
+                        write!(f, " {}={}", op, fields.join(","))?;
+                    }
+                }
+                if agg.count {
+                    write!(f, " count")?;
+                }
+                if let Some(window) = agg.window {
+                    write!(f, " window={}", window)?;
+                    match agg.time_field {
+                        Some(ref field) => write!(f, " time_field={} time_format={}", field, agg.time_format.clone().unwrap_or_default())?,
+                        None if agg.is_event_time() => write!(f, " time=event")?,
+                        None => write!(f, " time=processing")?,
+                    }
+                    if agg.is_event_time() {
+                        write!(f, " allowed_lateness_ms={} late={}", agg.allowed_lateness_ms.unwrap_or(0), agg.late)?;
+                        match (agg.late, &agg.late_to) {
+                            (_, Some(late_to)) => write!(f, " late_to={}", late_to)?,
+                            (LatePolicy::Route, None) => write!(f, " late_to=(dead letter)")?,
+                            _ => {}
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Step::Join(join) => {
+                let format = join.format().map_or("(unknown)".to_string(), |f| format!("{:?}", f).to_lowercase());
+                write!(f, " table={} format={} on={} table_key={} kind={}", join.table, format, join.on, join.table_key(), join.kind)?;
+                match join.columns.is_empty() {
+                    true => write!(f, " columns=(all)")?,
+                    false => write!(f, " columns={}", join.columns.join(","))?,
+                }
+                if join.reload {
+                    write!(f, " reload_interval_ms={}", join.reload_interval_ms())?;
+                }
+                Ok(())
+            }
+            Step::Route(route) => {
+                write!(f, " mode={}", route.mode)?;
+                for rule in &route.rules {
+                    write!(f, " {:?} -> {};", rule.when, rule.to.join(","))?;
+                }
+                match route.default.is_empty() {
+                    true => write!(f, " default -> (not routed)"),
+                    false => write!(f, " default -> {}", route.default.join(",")),
+                }
+            }
+            Step::Normalize(step) | Step::Validate(step) => {
+                write!(f, " schema={}", step.schema.as_deref().unwrap_or("(none)"))
+            }
+            Step::Limit(n) | Step::Sample(n) => write!(f, " {}", n),
+            Step::Ratelimit(rl) => {
+                let burst = rl.burst.unwrap_or(rl.rate_per_sec.ceil().max(1.0) as u32);
+                write!(f, " rate_per_sec={} burst={} mode={}", rl.rate_per_sec, burst, format!("{:?}", rl.mode).to_lowercase())?;
+                if rl.per_key {
+                    write!(f, " per_key")?;
+                }
+                if let Some(ref field) = rl.per_field {
+                    write!(f, " per_field={}", field)?;
+                }
+                match (rl.mode, &rl.route_to) {
+                    (_, Some(route_to)) => write!(f, " route_to={}", route_to),
+                    (OverLimit::Route, None) => write!(f, " route_to=(dead letter)"),
+                    _ => Ok(()),
+                }
+            }
+            Step::Dedup(dedup) => {
+                write!(f, " by={}", dedup.by)?;
+                match (dedup.capacity, dedup.ttl_ms) {
+                    (None, None) => write!(f, " capacity={} (default)", fluxmux_core::dedup::DEFAULT_CAPACITY),
+                    (capacity, ttl_ms) => {
+                        if let Some(capacity) = capacity {
+                            write!(f, " capacity={}", capacity)?;
+                        }
+                        if let Some(ttl_ms) = ttl_ms {
+                            write!(f, " ttl_ms={}", ttl_ms)?;
+                        }
+                        Ok(())
+                    }
+                }
+            }
+            Step::Batch(batch) => write!(f, " size={} timeout_ms={}", batch.size, batch.timeout_ms.unwrap_or(5000)),
+            Step::Throttle(rate) => write!(f, " {}/s", rate),
+        }
+    }
+}
+
+const TABLE_FORMATS: &str = "csv, json, ndjson, yaml, toml, parquet, avro, msgpack or cbor";
+
+/// Problems with a stream join, as (field, message); shared by `pipe`/`run`
+/// specs and the bridge's `--join-*` flags.
+pub fn join_problems(join: &StreamJoinConfig, dead_letter: bool) -> Vec<(&'static str, String)> {
+    let mut problems = Vec::new();
+    if let Err(e) = SourceType::from_str(&join.with) {
+        problems.push(("with", e.to_string()));
+    }
+    if join.on.is_empty() {
+        problems.push(("on", "must not be empty".into()));
+    }
+    if join.right_on.as_ref().is_some_and(|on| on.is_empty()) {
+        problems.push(("right_on", "must not be empty".into()));
+    }
+    if join.window_ms == 0 {
+        problems.push(("window_ms", "must be positive".into()));
+    }
+    if join.left_as() == join.right_as() {
+        problems.push(("right_as", format!("must differ from left_as ('{}')", join.left_as())));
+    }
+    match join.unmatched_to {
+        Some(_) if join.unmatched != Unmatched::Route => problems.push(("unmatched_to", "requires unmatched: route".into())),
+        Some(ref to) => {
+            if let Err(e) = SinkType::from_str(to) {
+                problems.push(("unmatched_to", e.to_string()));
+            }
+        }
+        None if join.unmatched == Unmatched::Route && !dead_letter => {
+            problems.push(("unmatched", "route requires unmatched_to or dead_letter".into()));
+        }
+        None => {}
+    }
+    problems
+}
+
+fn check_schema(path: &str) -> Result<(), String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    serde_json::from_str::<serde_json::Value>(&content)
+        .map(|_| ())
+        .map_err(|e| format!("{} is not valid JSON: {}", path, e))
+}
@@ -1,77 +1,246 @@
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader};
 use tokio::fs::File;
 use tokio::sync::mpsc::Sender;
+use fluxmux_core::ack::{Ack, AckTracker};
+use fluxmux_core::checkpoint::{CheckpointStore, Checkpointer};
 use fluxmux_core::message::{Message, Format};
 use fluxmux_core::traits::Source;
 use async_trait::async_trait;
 use chrono::Utc;
-use tokio::io::AsyncReadExt;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::SeekFrom;
+use std::sync::{Arc, Mutex};
+
+/// Resume point within a file: byte offset of the next unprocessed line and its
+/// line number. For JSON array files `line` is the next element index.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct FilePosition {
+    pub offset: u64,
+    pub line: u64,
+}
+
+#[derive(Default)]
+struct FileProgress {
+    tracker: AckTracker,
+    spans: BTreeMap<u64, (u64, u64)>, // line -> (start offset, end offset) while in flight
+    position: FilePosition,
+}
+
+impl FileProgress {
+    fn ack(&mut self, line: u64) -> Option<FilePosition> {
+        let committed = self.tracker.ack(line)?;
+        let offset = match self.spans.get(&committed) {
+            Some(&(start, _)) => start,
+            None => self.spans.get(&(committed - 1)).map(|&(_, end)| end)?,
+        };
+        // Spans before the committed line are no longer needed
+        self.spans = self.spans.split_off(&committed.saturating_sub(1));
+        self.position = FilePosition { offset, line: committed };
+        Some(self.position)
+    }
+}
 
 pub struct FileSource {
     pub path: String,
+    checkpoints: Option<CheckpointStore>,
+    progress: Arc<Mutex<FileProgress>>,
+}
+
+impl FileSource {
+    pub fn new(path: String) -> Self {
+        Self { path, checkpoints: None, progress: Arc::new(Mutex::new(FileProgress::default())) }
+    }
+
+    /// Resume from, and periodically persist, the last acknowledged position.
+    pub fn with_checkpoints(mut self, store: CheckpointStore) -> Self {
+        self.checkpoints = Some(store);
+        self
+    }
+
+    /// Number of records from the start of the file that sinks have accepted.
+    pub fn committed_records(&self) -> u64 {
+        self.progress.lock().unwrap().position.line
+    }
+
+    fn checkpoint_key(&self) -> String {
+        format!("file-{}", self.path)
+    }
+
+    // Track the record and hand out an ack that records progress once it is delivered
+    fn ack_for(&self, line: u64, span: (u64, u64), checkpointer: &Option<Arc<Checkpointer<FilePosition>>>) -> Ack {
+        {
+            let mut progress = self.progress.lock().unwrap();
+            progress.tracker.track(line);
+            progress.spans.insert(line, span);
+        }
+        let progress = self.progress.clone();
+        let checkpointer = checkpointer.clone();
+        Ack::new(move || {
+            let position = progress.lock().unwrap().ack(line);
+            if let (Some(position), Some(checkpointer)) = (position, checkpointer) {
+                checkpointer.update(position);
+            }
+        })
+    }
+
+    fn message(&self, id: String, val: Value, payload: Vec<u8>, ack: Ack) -> Message {
+        Message {
+            id: Some(id),
+            parents: Vec::new(),
+            key: None,
+            payload,
+            format: Some(Format::Json),
+            parsed: Some(val),
+            timestamp: Utc::now(),
+            headers: Default::default(),
+            meta: Default::default(),
+            ack: Some(ack),
+        }
+    }
 }
 
 #[async_trait]
 impl Source for FileSource {
     async fn start(&mut self, tx: Sender<Message>) -> anyhow::Result<()> {
+        let checkpointer = self.checkpoints.clone()
+            .map(|store| Checkpointer::start(store, self.checkpoint_key()));
+        let mut resume = match self.checkpoints {
+            Some(ref store) => store.load::<FilePosition>(&self.checkpoint_key())?.unwrap_or_default(),
+            None => FilePosition::default(),
+        };
+        let file_len = tokio::fs::metadata(&self.path).await?.len();
+        if resume.offset > file_len {
+            tracing::warn!(path = %self.path, "Checkpoint is past the end of the file, starting over");
+            resume = FilePosition::default();
+        }
+        self.progress.lock().unwrap().position = resume;
+
         // First, try to read the entire file and parse as a single JSON value (array or object).
         // If that fails, fall back to line-by-line NDJSON parsing.
-        let mut file = File::open(&self.path).await?;
-        let mut buf = String::new();
-        file.read_to_string(&mut buf).await?;
+        let buf = tokio::fs::read_to_string(&self.path).await?;
         let trimmed = buf.trim();
 
-        // Helper to send a serde_json::Value
-        let send_value = |val: Value| async {
-            let payload = val.to_string().into_bytes();
-            let msg = Message {
-                id: None,
-                key: None,
-                payload,
-                format: Some(Format::Json),
-                parsed: Some(val),
-                timestamp: Utc::now(),
-                headers: Default::default(),
-                meta: Default::default(),
-            };
-            tx.send(msg).await
-        };
-
         // Try full JSON parse
         if let Ok(value) = serde_json::from_str::<Value>(trimmed) {
-            match value {
-                Value::Array(arr) => {
-                    for v in arr {
-                        send_value(v).await?;
-                    }
-                    return Ok(());
-                }
-                other => {
-                    send_value(other).await?;
-                    return Ok(());
-                }
+            let values = match value {
+                Value::Array(arr) => arr,
+                other => vec![other],
+            };
+            for (index, v) in values.into_iter().enumerate().skip(resume.line as usize) {
+                let index = index as u64;
+                let ack = self.ack_for(index, (0, 0), &checkpointer);
+                let payload = v.to_string().into_bytes();
+                let id = format!("{}[{}]", self.path, index);
+                tx.send(self.message(id, v, payload, ack)).await?;
             }
+            return Ok(());
         }
 
-        // Fall back to NDJSON parsing
-        let file = File::open(&self.path).await?;
-        let reader = BufReader::new(file);
-        let mut lines = reader.lines();
-        while let Some(line) = lines.next_line().await? {
-            let value: serde_json::Value = serde_json::from_str(&line)?;
-            let msg = Message {
-                id: None,
-                key: None,
-                payload: line.into_bytes(),
-                format: Some(Format::Json),
-                parsed: Some(value),
-                timestamp: Utc::now(),
-                headers: Default::default(),
-                meta: Default::default(),
-            };
-            tx.send(msg).await?;
+        // Fall back to NDJSON parsing, continuing after the last acknowledged line
+        let mut file = File::open(&self.path).await?;
+        file.seek(SeekFrom::Start(resume.offset)).await?;
+        let mut reader = BufReader::new(file);
+        let mut offset = resume.offset;
+        let mut line_no = resume.line;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line).await?;
+            if read == 0 {
+                break;
+            }
+            let span = (offset, offset + read as u64);
+            offset += read as u64;
+
+            let text = line.trim_end_matches(['\n', '\r']);
+            if !text.trim().is_empty() {
+                let value: serde_json::Value = serde_json::from_str(text)?;
+                let ack = self.ack_for(line_no, span, &checkpointer);
+                let id = format!("{}:{}", self.path, line_no + 1);
+                tx.send(self.message(id, value, text.as_bytes().to_vec(), ack)).await?;
+            }
+            line_no += 1;
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use tokio::sync::mpsc;
+
+    /// A fresh directory holding `input.ndjson` with `content`.
+    fn scratch(name: &str, content: &str) -> (PathBuf, String) {
+        let dir = std::env::temp_dir().join(format!("fluxmux-file-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("input.ndjson");
+        std::fs::write(&path, content).unwrap();
+        (dir.clone(), path.to_string_lossy().into_owned())
+    }
+
+    /// Read the file once, acknowledging the records whose `n` is in `acked`;
+    /// returns the `n` of every record read.
+    async fn run(dir: &Path, path: &str, acked: &[i64]) -> Vec<i64> {
+        let store = CheckpointStore::open(dir.join("checkpoints")).unwrap();
+        let mut source = FileSource::new(path.to_string()).with_checkpoints(store);
+        let (tx, mut rx) = mpsc::channel(16);
+        source.start(tx).await.unwrap();
+
+        let mut read = vec![];
+        let mut messages = vec![];
+        while let Ok(msg) = rx.try_recv() {
+            let n = msg.parsed.as_ref().unwrap()["n"].as_i64().unwrap();
+            read.push(n);
+            if acked.contains(&n) {
+                messages.push(msg);
+            }
+        }
+        // Acknowledge in reverse order; the rest are dropped unarmed
+        for msg in messages.into_iter().rev() {
+            msg.arm_ack();
+        }
+        read
+    }
+
+    #[tokio::test]
+    async fn resumes_after_the_acknowledged_lines() {
+        let (dir, path) = scratch("resume", "{\"n\":1}\n{\"n\":2}\n\n{\"n\":3}\r\n{\"n\":4}\n");
+        assert_eq!(run(&dir, &path, &[1, 2]).await, vec![1, 2, 3, 4]);
+
+        // Neither re-reads 1 and 2 nor skips 3
+        assert_eq!(run(&dir, &path, &[3]).await, vec![3, 4]);
+        assert_eq!(run(&dir, &path, &[]).await, vec![4]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn resumes_at_the_first_unacknowledged_line() {
+        let (dir, path) = scratch("gap", "{\"n\":1}\n{\"n\":2}\n{\"n\":3}\n");
+        assert_eq!(run(&dir, &path, &[1, 3]).await, vec![1, 2, 3]);
+
+        // 3 was acknowledged but comes after 2, so it is read again
+        assert_eq!(run(&dir, &path, &[2, 3]).await, vec![2, 3]);
+        assert!(run(&dir, &path, &[]).await.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn progress_commits_byte_offsets_in_order() {
+        let mut progress = FileProgress::default();
+        for (line, span) in [(0, (0, 8)), (1, (8, 16)), (3, (17, 25))] {
+            progress.tracker.track(line);
+            progress.spans.insert(line, span);
+        }
+        assert!(progress.ack(1).is_none());
+        // Line 2 is blank and never tracked, so the commit moves on to line 3
+        let position = progress.ack(0).unwrap();
+        assert_eq!((position.offset, position.line), (17, 3));
+        let position = progress.ack(3).unwrap();
+        assert_eq!((position.offset, position.line), (25, 4));
+    }
+}
@@ -1,59 +1,236 @@
+use fluxmux_core::ack::{Ack, AckTracker};
+use fluxmux_core::checkpoint::{CheckpointStore, Checkpointer};
+use fluxmux_core::metrics::{Metrics, KAFKA_CONSUMER_LAG};
 use fluxmux_core::traits::Source;
 use fluxmux_core::message::{Message, Format};
 use async_trait::async_trait;
 use tokio::sync::mpsc::Sender;
-use rdkafka::consumer::{StreamConsumer, Consumer};
-use rdkafka::Message as KafkaMessage;
+use rdkafka::client::ClientContext;
+use rdkafka::consumer::{Consumer, ConsumerContext, Rebalance, StreamConsumer};
+use rdkafka::topic_partition_list::TopicPartitionList;
+use rdkafka::message::Headers;
+use rdkafka::{Message as KafkaMessage, Offset};
 use futures_util::StreamExt;
 use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::Duration;
+
+/// How often consumer lag is sampled when metrics are enabled.
+const LAG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Next offset to consume for each partition of the topic.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KafkaPosition {
+    pub partitions: BTreeMap<i32, i64>,
+}
 
 pub struct KafkaSource {
     pub brokers: String,
     pub topic: String,
     pub group_id: String,
+    checkpoints: Option<CheckpointStore>,
+    metrics: Option<Metrics>,
 }
 
 impl KafkaSource {
     pub fn new(brokers: String, topic: String, group_id: String) -> Self {
-        Self { brokers, topic, group_id }
+        Self { brokers, topic, group_id, checkpoints: None, metrics: None }
+    }
+
+    /// Resume from, and periodically persist, the last acknowledged offsets.
+    pub fn with_checkpoints(mut self, store: CheckpointStore) -> Self {
+        self.checkpoints = Some(store);
+        self
+    }
+
+    /// Report per-partition consumer lag.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    fn checkpoint_key(&self) -> String {
+        format!("kafka-{}-{}", self.topic, self.group_id)
+    }
+
+    // Sample lag until the consumer is gone
+    fn spawn_lag_reporter(&self, consumer: &Arc<KafkaConsumer>, metrics: Metrics) {
+        let consumer = Arc::downgrade(consumer);
+        let topic = self.topic.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(LAG_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(consumer) = consumer.upgrade() else { break };
+                let (topic, metrics) = (topic.clone(), metrics.clone());
+                // Watermark lookups block on the broker
+                let _ = tokio::task::spawn_blocking(move || report_lag(&consumer, &topic, &metrics)).await;
+            }
+        });
+    }
+}
+
+type KafkaConsumer = StreamConsumer<ResumeContext>;
+
+/// Starts partitions at their checkpointed offsets the first time the group
+/// assigns them, so a resumed consumer stays subscribed (and picks up
+/// partitions added later) instead of pinning an assignment.
+struct ResumeContext {
+    topic: String,
+    pending: Mutex<BTreeMap<i32, i64>>, // checkpointed partitions not yet seen assigned
+    consumer: OnceLock<Weak<KafkaConsumer>>,
+}
+
+impl ClientContext for ResumeContext {}
+
+impl ConsumerContext for ResumeContext {
+    fn post_rebalance(&self, rebalance: &Rebalance<'_>) {
+        let Rebalance::Assign(assigned) = rebalance else { return };
+        let mut seeks = TopicPartitionList::new();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            for elem in assigned.elements_for_topic(&self.topic) {
+                // Later assignments resume from the offsets committed since
+                if let Some(offset) = pending.remove(&elem.partition()) {
+                    let _ = seeks.add_partition_offset(&self.topic, elem.partition(), Offset::Offset(offset));
+                }
+            }
+        }
+        if seeks.count() == 0 {
+            return;
+        }
+        let Some(consumer) = self.consumer.get().and_then(Weak::upgrade) else { return };
+        // Asynchronous: the callback must not wait on the broker
+        match consumer.seek_partitions(seeks, Duration::ZERO) {
+            Ok(result) => {
+                for elem in result.elements() {
+                    match elem.error() {
+                        Ok(()) => tracing::info!(topic = %self.topic, partition = elem.partition(), offset = ?elem.offset(), "Resuming from checkpoint"),
+                        Err(e) => tracing::error!(topic = %self.topic, partition = elem.partition(), error = %e, "Failed to seek to checkpointed offset"),
+                    }
+                }
+            }
+            Err(e) => tracing::error!(topic = %self.topic, error = %e, "Failed to seek to checkpointed offsets"),
+        }
+    }
+}
+
+fn report_lag(consumer: &KafkaConsumer, topic: &str, metrics: &Metrics) {
+    let Ok(positions) = consumer.position() else { return };
+    for elem in positions.elements_for_topic(topic) {
+        // Partitions that have not been read yet have no position
+        let Offset::Offset(position) = elem.offset() else { continue };
+        if let Ok((_, high)) = consumer.fetch_watermarks(topic, elem.partition(), Duration::from_secs(1)) {
+            let partition = elem.partition().to_string();
+            let labels = [("topic", topic), ("partition", partition.as_str())];
+            metrics.set_gauge(KAFKA_CONSUMER_LAG, &labels, (high - position).max(0) as f64);
+        }
     }
 }
 
 #[async_trait]
 impl Source for KafkaSource {
     async fn start(&mut self, tx: Sender<Message>) -> anyhow::Result<()> {
-        let consumer: StreamConsumer = rdkafka::config::ClientConfig::new()
+        // Offsets are only stored once a message is acknowledged by the sink;
+        // auto-commit then periodically commits whatever has been stored.
+        let saved = match self.checkpoints {
+            Some(ref store) => store.load::<KafkaPosition>(&self.checkpoint_key())?,
+            None => None,
+        };
+        let context = ResumeContext {
+            topic: self.topic.clone(),
+            pending: Mutex::new(saved.as_ref().map(|p| p.partitions.clone()).unwrap_or_default()),
+            consumer: OnceLock::new(),
+        };
+        let consumer: Arc<KafkaConsumer> = Arc::new(rdkafka::config::ClientConfig::new()
             .set("bootstrap.servers", &self.brokers)
             .set("group.id", &self.group_id)
             .set("auto.offset.reset", "earliest")
-            .create()?;
-
+            .set("enable.auto.offset.store", "false")
+            .create_with_context(context)?);
+        let _ = consumer.context().consumer.set(Arc::downgrade(&consumer));
         consumer.subscribe(&[&self.topic])?;
 
+        if let Some(metrics) = self.metrics.clone() {
+            self.spawn_lag_reporter(&consumer, metrics);
+        }
+
+        let checkpointer = self.checkpoints.clone()
+            .map(|store| Checkpointer::<KafkaPosition>::start(store, self.checkpoint_key()));
+        // Partitions not consumed in this run keep their saved offsets
+        let saved_partitions = Arc::new(saved.map(|p| p.partitions).unwrap_or_default());
+        let trackers: Arc<Mutex<HashMap<i32, AckTracker>>> = Arc::new(Mutex::new(HashMap::new()));
+
         let mut stream = consumer.stream();
         while let Some(result) = stream.next().await {
             match result {
                 Ok(msg) => {
                     if let Some(payload) = msg.payload() {
+                        let partition = msg.partition();
+                        let offset = msg.offset() as u64;
+                        trackers.lock().unwrap().entry(partition).or_default().track(offset);
+
+                        let ack = {
+                            let consumer = consumer.clone();
+                            let trackers = trackers.clone();
+                            let checkpointer = checkpointer.clone();
+                            let saved_partitions = saved_partitions.clone();
+                            let topic = self.topic.clone();
+                            Ack::new(move || {
+                                let mut trackers = trackers.lock().unwrap();
+                                let committed = trackers.get_mut(&partition).and_then(|t| t.ack(offset));
+                                // store_offset takes the last processed offset, Kafka resumes after it
+                                if let Some(next) = committed.filter(|n| *n > 0) {
+                                    if let Err(e) = consumer.store_offset(&topic, partition, next as i64 - 1) {
+                                        tracing::error!(topic = %topic, partition, error = %e, "Failed to store Kafka offset");
+                                    }
+                                    if let Some(checkpointer) = checkpointer {
+                                        let mut partitions = (*saved_partitions).clone();
+                                        partitions.extend(trackers.iter()
+                                            .filter_map(|(p, t)| t.committed().map(|c| (*p, c as i64))));
+                                        checkpointer.update(KafkaPosition { partitions });
+                                    }
+                                }
+                            })
+                        };
+
+                        // JSON records are parsed so actions can read their fields
+                        let parsed: Option<serde_json::Value> = serde_json::from_slice(payload).ok();
                         let message = Message {
-                            id: None,
+                            id: Some(format!("{}:{}:{}", self.topic, partition, offset)),
+                            parents: Vec::new(),
                             key: msg.key().map(|k| k.to_vec()),
                             payload: payload.to_vec(),
-                            format: Some(Format::Binary),
-                            parsed: None,
+                            format: Some(if parsed.is_some() { Format::Json } else { Format::Binary }),
+                            parsed,
                             timestamp: Utc::now(),
-                            headers: Default::default(),
+                            headers: record_headers(&msg),
                             meta: Default::default(),
+                            ack: Some(ack),
                         };
                         tx.send(message).await?;
                     }
                 }
                 Err(e) => {
-                    eprintln!("Error processing Kafka message: {}", e);
+                    tracing::error!(error = %e, "Error processing Kafka message");
                     continue;
                 }
             }
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Record headers with UTF-8 values (e.g. `traceparent`); others are skipped.
+fn record_headers(msg: &impl KafkaMessage) -> HashMap<String, String> {
+    let Some(headers) = msg.headers() else { return HashMap::new() };
+    headers
+        .iter()
+        .filter_map(|h| {
+            let value = std::str::from_utf8(h.value?).ok()?;
+            Some((h.key.to_string(), value.to_string()))
+        })
+        .collect()
+}
@@ -5,3 +5,21 @@ pub mod pipe;
 pub use file::FileSource;
 pub use kafka::KafkaSource;
 pub use pipe::PipeSource;
+
+use fluxmux_core::endpoints::SourceType;
+use fluxmux_core::traits::Source;
+use std::str::FromStr;
+
+/// Build a source from a URI such as `file:data.ndjson`,
+/// `kafka://localhost:9092/events?group=etl` or `stdin`.
+pub fn source_from_uri(uri: &str) -> anyhow::Result<Box<dyn Source>> {
+    Ok(source_from_type(SourceType::from_str(uri)?))
+}
+
+pub fn source_from_type(source_type: SourceType) -> Box<dyn Source> {
+    match source_type {
+        SourceType::File { path } => Box::new(FileSource::new(path)),
+        SourceType::Kafka { brokers, topic, group_id } => Box::new(KafkaSource::new(brokers, topic, group_id)),
+        SourceType::Stdin => Box::new(PipeSource::new()),
+    }
+}
@@ -1,11 +1,13 @@
 use async_trait::async_trait;
 use chrono::Utc;
+use fluxmux_core::lineage;
 use fluxmux_core::message::{Format, Message};
 use fluxmux_core::traits::Source;
 use serde_json::Value;
 use tokio::io::{self, AsyncBufReadExt, AsyncReadExt, BufReader};
 use tokio::sync::mpsc::Sender;
 
+#[derive(Default)]
 pub struct PipeSource;
 
 impl PipeSource {
@@ -25,7 +27,8 @@ impl Source for PipeSource {
 		let send_value = |val: Value| async {
 			let payload = val.to_string().into_bytes();
 			let msg = Message {
-				id: None,
+				id: Some(lineage::new_id()),
+				parents: Vec::new(),
 				key: None,
 				payload,
 				format: Some(Format::Json),
@@ -33,6 +36,7 @@ impl Source for PipeSource {
 				timestamp: Utc::now(),
 				headers: Default::default(),
 				meta: Default::default(),
+				ack: None,
 			};
 			tx.send(msg).await
 		};
@@ -62,7 +66,8 @@ impl Source for PipeSource {
 			if line.trim().is_empty() { continue; }
 			let value: serde_json::Value = serde_json::from_str(&line)?;
 			let msg = Message {
-				id: None,
+				id: Some(lineage::new_id()),
+				parents: Vec::new(),
 				key: None,
 				payload: line.into_bytes(),
 				format: Some(Format::Json),
@@ -70,6 +75,7 @@ impl Source for PipeSource {
 				timestamp: Utc::now(),
 				headers: Default::default(),
 				meta: Default::default(),
+				ack: None,
 			};
 			tx.send(msg).await?;
 		}
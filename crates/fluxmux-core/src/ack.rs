@@ -0,0 +1,223 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+type AckFn = Box<dyn FnOnce() + Send + Sync>;
+
+/// Acknowledgement handle carried with a `Message` from its source to the sinks.
+///
+/// Clones share the same handle. Once the engine has taken the message off the
+/// source channel it arms the handle; from then on the source callback runs
+/// when the last clone is dropped, i.e. after every sink has durably accepted
+/// the message or a stage has deliberately discarded it. A handle that was
+/// never armed (e.g. still queued when the source was stopped) or that was
+/// `nack`ed never runs its callback, so the source keeps the message as
+/// unprocessed.
+#[derive(Clone)]
+pub struct Ack {
+    inner: Arc<AckInner>,
+}
+
+struct AckInner {
+    on_ack: Option<AckFn>,
+    armed: AtomicBool,
+    failed: AtomicBool,
+    merged: Vec<Ack>,
+}
+
+impl Ack {
+    pub fn new<F>(on_ack: F) -> Self
+    where
+        F: FnOnce() + Send + Sync + 'static,
+    {
+        Self {
+            inner: Arc::new(AckInner {
+                on_ack: Some(Box::new(on_ack)),
+                armed: AtomicBool::new(false),
+                failed: AtomicBool::new(false),
+                merged: Vec::new(),
+            }),
+        }
+    }
+
+    /// Combine the handles of several messages into one, e.g. when a batch
+    /// replaces its members. Returns `None` when there is nothing to merge.
+    pub fn merge(acks: Vec<Ack>) -> Option<Self> {
+        if acks.is_empty() {
+            return None;
+        }
+        Some(Self {
+            inner: Arc::new(AckInner {
+                on_ack: None,
+                armed: AtomicBool::new(true),
+                failed: AtomicBool::new(false),
+                merged: acks,
+            }),
+        })
+    }
+
+    /// Called by the engine once it owns the message.
+    pub fn arm(&self) {
+        self.inner.armed.store(true, Ordering::SeqCst);
+        for ack in &self.inner.merged {
+            ack.arm();
+        }
+    }
+
+    /// Mark delivery as failed for this handle and every handle merged into it.
+    pub fn nack(&self) {
+        self.inner.failed.store(true, Ordering::SeqCst);
+        for ack in &self.inner.merged {
+            ack.nack();
+        }
+    }
+
+    pub fn is_nacked(&self) -> bool {
+        self.inner.failed.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for AckInner {
+    fn drop(&mut self) {
+        if !self.armed.load(Ordering::SeqCst) || self.failed.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Some(on_ack) = self.on_ack.take() {
+            on_ack();
+        }
+    }
+}
+
+impl fmt::Debug for Ack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ack")
+            .field("armed", &self.inner.armed.load(Ordering::SeqCst))
+            .field("nacked", &self.is_nacked())
+            .field("merged", &self.inner.merged.len())
+            .finish()
+    }
+}
+
+/// Tracks in-flight source positions (offsets, line numbers) and reports the
+/// position from which a restarted source has to resume.
+///
+/// Acknowledgements may arrive out of order, so the committed position only
+/// advances past a record once everything before it is acknowledged too.
+#[derive(Debug, Default)]
+pub struct AckTracker {
+    pending: BTreeSet<u64>,
+    next: Option<u64>,
+}
+
+impl AckTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn track(&mut self, position: u64) {
+        self.pending.insert(position);
+        self.next = Some(self.next.map_or(position + 1, |n| n.max(position + 1)));
+    }
+
+    /// Acknowledge a position. Returns the new committed position if it moved.
+    pub fn ack(&mut self, position: u64) -> Option<u64> {
+        let before = self.committed();
+        self.pending.remove(&position);
+        let after = self.committed();
+        if after != before {
+            after
+        } else {
+            None
+        }
+    }
+
+    /// First position that has not been fully processed yet.
+    pub fn committed(&self) -> Option<u64> {
+        self.pending.first().copied().or(self.next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn commits_up_to_the_lowest_unacked_position() {
+        let mut tracker = AckTracker::new();
+        assert_eq!(tracker.committed(), None);
+        for position in 10..14 {
+            tracker.track(position);
+        }
+        assert_eq!(tracker.committed(), Some(10));
+
+        // Later records acknowledged first do not move the commit
+        assert_eq!(tracker.ack(12), None);
+        assert_eq!(tracker.ack(11), None);
+        assert_eq!(tracker.committed(), Some(10));
+
+        // Acknowledging the first skips past everything acknowledged after it
+        assert_eq!(tracker.ack(10), Some(13));
+        assert_eq!(tracker.ack(13), Some(14));
+        assert_eq!(tracker.committed(), Some(14));
+    }
+
+    #[test]
+    fn new_positions_wait_for_earlier_ones() {
+        let mut tracker = AckTracker::new();
+        tracker.track(0);
+        tracker.track(1);
+        assert_eq!(tracker.ack(1), None);
+        tracker.track(2);
+        assert_eq!(tracker.ack(2), None);
+        assert_eq!(tracker.ack(0), Some(3));
+        assert_eq!(tracker.ack(0), None);
+    }
+
+    fn counted(count: &Arc<AtomicUsize>) -> Ack {
+        let count = count.clone();
+        Ack::new(move || {
+            count.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+
+    #[test]
+    fn fires_once_the_last_armed_clone_drops() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let ack = counted(&count);
+        let clone = ack.clone();
+        ack.arm();
+        drop(ack);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+        drop(clone);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn unarmed_or_nacked_handles_never_fire() {
+        let count = Arc::new(AtomicUsize::new(0));
+        drop(counted(&count));
+        let nacked = counted(&count);
+        nacked.arm();
+        nacked.nack();
+        drop(nacked);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn merged_handles_arm_and_nack_their_parts() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let merged = Ack::merge(vec![counted(&count), counted(&count)]).unwrap();
+        merged.arm();
+        drop(merged);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        let merged = Ack::merge(vec![counted(&count), counted(&count)]).unwrap();
+        merged.arm();
+        merged.nack();
+        drop(merged);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert!(Ack::merge(vec![]).is_none());
+    }
+}
@@ -0,0 +1,101 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
+use tokio::time::Duration;
+
+/// How often a dirty checkpoint is written to disk while a source is running.
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Directory-backed store holding one JSON document per source.
+#[derive(Debug, Clone)]
+pub struct CheckpointStore {
+    dir: PathBuf,
+}
+
+impl CheckpointStore {
+    pub fn open(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    pub fn load<T: DeserializeOwned>(&self, key: &str) -> anyhow::Result<Option<T>> {
+        let path = self.path_for(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)?;
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    /// Write atomically (temp file + rename) so a crash never leaves a torn checkpoint.
+    pub fn save<T: Serialize>(&self, key: &str, position: &T) -> anyhow::Result<()> {
+        let path = self.path_for(key);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(position)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn path_for(&self, key: &str) -> PathBuf {
+        let name: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+            .collect();
+        self.dir.join(format!("{}.json", name))
+    }
+}
+
+/// Keeps the latest acknowledged position of one source and persists it
+/// periodically, and once more when the last reference is dropped.
+pub struct Checkpointer<T: Serialize + Send + 'static> {
+    store: CheckpointStore,
+    key: String,
+    latest: Mutex<Option<T>>,
+}
+
+impl<T: Serialize + Send + 'static> Checkpointer<T> {
+    /// Must be called from within a Tokio runtime.
+    pub fn start(store: CheckpointStore, key: String) -> Arc<Self> {
+        let checkpointer = Arc::new(Self {
+            store,
+            key,
+            latest: Mutex::new(None),
+        });
+
+        let weak: Weak<Self> = Arc::downgrade(&checkpointer);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECKPOINT_INTERVAL);
+            loop {
+                interval.tick().await;
+                match weak.upgrade() {
+                    Some(checkpointer) => checkpointer.flush(),
+                    None => break,
+                }
+            }
+        });
+
+        checkpointer
+    }
+
+    pub fn update(&self, position: T) {
+        *self.latest.lock().unwrap() = Some(position);
+    }
+
+    /// Persist the latest position if it changed since the last write.
+    pub fn flush(&self) {
+        if let Some(position) = self.latest.lock().unwrap().take() {
+            if let Err(e) = self.store.save(&self.key, &position) {
+                tracing::error!(key = %self.key, error = %e, "Failed to write checkpoint");
+            }
+        }
+    }
+}
+
+impl<T: Serialize + Send + 'static> Drop for Checkpointer<T> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
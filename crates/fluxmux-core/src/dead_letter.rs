@@ -0,0 +1,124 @@
+use crate::message::{Format, Message};
+use crate::traits::Sink;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// A message that could not be delivered, together with why and where it failed.
+pub struct DeadLetter {
+    pub message: Message,
+    pub reason: String,
+    pub stage: String,
+    pub attempts: u32,
+}
+
+impl DeadLetter {
+    /// Wrap the original message in a JSON envelope that can be replayed later.
+    /// The envelope inherits the original acknowledgement handle, so the source
+    /// only commits once the dead letter itself has been written.
+    pub fn into_message(self) -> Message {
+        let original = self.message;
+        let payload = match original.parsed {
+            Some(ref parsed) => parsed.clone(),
+            None => Value::String(String::from_utf8_lossy(&original.payload).to_string()),
+        };
+
+        let mut envelope = json!({
+            "reason": self.reason,
+            "stage": self.stage,
+            "attempts": self.attempts,
+            "failed_at": chrono::Utc::now().to_rfc3339(),
+            "headers": original.headers,
+            "payload": payload,
+        });
+        if let Some(ref key) = original.key {
+            envelope["key"] = json!(String::from_utf8_lossy(key));
+        }
+        if let Some(ref id) = original.id {
+            envelope["id"] = json!(id);
+        }
+
+        Message {
+            id: original.id,
+            parents: original.parents,
+            key: original.key,
+            payload: envelope.to_string().into_bytes(),
+            format: Some(Format::Json),
+            parsed: Some(envelope),
+            timestamp: original.timestamp,
+            headers: original.headers,
+            meta: original.meta,
+            ack: original.ack,
+        }
+    }
+}
+
+/// Cloneable handle used by the engine and by rejecting stages to hand off dead letters.
+#[derive(Clone)]
+pub struct DeadLetterSender {
+    tx: mpsc::UnboundedSender<DeadLetter>,
+}
+
+impl DeadLetterSender {
+    /// A sender without a queue: letters are nacked straight away. For
+    /// building stages that never run, e.g. to describe a pipeline.
+    pub fn discard() -> Self {
+        let (tx, _) = mpsc::unbounded_channel();
+        Self { tx }
+    }
+
+    pub fn send(&self, message: Message, stage: &str, reason: impl Into<String>, attempts: u32) {
+        let letter = DeadLetter {
+            message,
+            reason: reason.into(),
+            stage: stage.to_string(),
+            attempts,
+        };
+        if let Err(mpsc::error::SendError(letter)) = self.tx.send(letter) {
+            tracing::error!(stage = %letter.stage, "Dead-letter queue closed, dropping message");
+            letter.message.nack();
+        }
+    }
+}
+
+/// Background writer that owns the dead-letter sink.
+pub struct DeadLetterQueue {
+    sender: DeadLetterSender,
+    handle: JoinHandle<()>,
+}
+
+impl DeadLetterQueue {
+    pub fn spawn(mut sink: Box<dyn Sink>) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<DeadLetter>();
+
+        let handle = tokio::spawn(async move {
+            while let Some(letter) = rx.recv().await {
+                let msg = letter.into_message();
+                if let Err(e) = sink.send(msg.clone()).await {
+                    tracing::error!(error = %e, "Failed to write dead letter");
+                    msg.nack();
+                }
+            }
+            if let Err(e) = sink.flush().await {
+                tracing::error!(error = %e, "Dead-letter flush failed");
+            }
+        });
+
+        Self {
+            sender: DeadLetterSender { tx },
+            handle,
+        }
+    }
+
+    pub fn sender(&self) -> DeadLetterSender {
+        self.sender.clone()
+    }
+
+    /// Wait until every outstanding dead letter is written and the sink is flushed.
+    /// All senders handed out must have been dropped for this to return.
+    pub async fn close(self) -> anyhow::Result<()> {
+        drop(self.sender);
+        self.handle.await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+use crate::message::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use tokio::time::{Duration, Instant};
+
+/// Keys remembered when neither a capacity nor a TTL is configured.
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+/// What makes two messages duplicates.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum DedupKey {
+    /// `Key` for messages with a record key, `Content` for the others
+    #[default]
+    Auto,
+    /// Hash of the payload (parsed JSON when available, so key order and
+    /// whitespace do not matter)
+    Content,
+    /// `Message.key`, e.g. the Kafka record key
+    Key,
+    /// Values of these payload fields; dotted paths reach into nested objects
+    Fields(Vec<String>),
+}
+
+impl std::str::FromStr for DedupKey {
+    type Err = anyhow::Error;
+
+    /// `auto`, `content`, `key`, or a comma-separated list of field paths.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "auto" => Ok(Self::Auto),
+            "content" => Ok(Self::Content),
+            "key" => Ok(Self::Key),
+            "" => Err(anyhow::anyhow!("Empty dedup key, expected auto, content, key or field paths")),
+            fields => Ok(Self::Fields(fields.split(',').map(|f| f.trim().to_string()).collect())),
+        }
+    }
+}
+
+impl TryFrom<String> for DedupKey {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::fmt::Display for DedupKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Content => write!(f, "content"),
+            Self::Key => write!(f, "key"),
+            Self::Fields(fields) => write!(f, "{}", fields.join(",")),
+        }
+    }
+}
+
+impl From<DedupKey> for String {
+    fn from(key: DedupKey) -> Self {
+        key.to_string()
+    }
+}
+
+impl DedupKey {
+    /// Hash identifying the message, or `None` when it has nothing to compare
+    /// (no record key, or none of the fields present).
+    pub fn hash(&self, msg: &Message) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        match self {
+            Self::Auto if msg.key.is_some() => return Self::Key.hash(msg),
+            Self::Auto | Self::Content => match msg.parsed {
+                Some(ref value) => value.to_string().hash(&mut hasher),
+                None => msg.payload.hash(&mut hasher),
+            },
+            Self::Key => msg.key.as_ref()?.hash(&mut hasher),
+            Self::Fields(fields) => {
+                let parsed = msg.parsed.as_ref()?;
+                let values: Vec<_> = fields.iter().map(|f| lookup(parsed, f)).collect();
+                if values.iter().all(Option::is_none) {
+                    return None;
+                }
+                for value in values {
+                    value.map(|v| v.to_string()).hash(&mut hasher);
+                }
+            }
+        }
+        Some(hasher.finish())
+    }
+}
+
+fn lookup<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.').try_fold(value, |v, part| v.get(part))
+}
+
+/// Recently seen keys, bounded by a least-recently-seen capacity and/or a
+/// time-to-live measured from the last sighting.
+pub struct SeenSet {
+    capacity: Option<usize>,
+    ttl: Option<Duration>,
+    entries: HashMap<u64, (u64, Instant)>, // key -> (generation, last seen)
+    order: VecDeque<(u64, u64)>,           // (key, generation), oldest first; stale generations are skipped
+    next_generation: u64,
+}
+
+impl SeenSet {
+    pub fn new(capacity: Option<usize>, ttl: Option<Duration>) -> Self {
+        let capacity = match (capacity, ttl) {
+            (None, None) => Some(DEFAULT_CAPACITY),
+            (capacity, _) => capacity.map(|c| c.max(1)),
+        };
+        Self { capacity, ttl, entries: HashMap::new(), order: VecDeque::new(), next_generation: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Record a sighting of `key`; returns true when it was already present.
+    pub fn insert(&mut self, key: u64) -> bool {
+        let now = Instant::now();
+        self.expire(now);
+
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        let seen = self.entries.insert(key, (generation, now)).is_some();
+        self.order.push_back((key, generation));
+
+        if let Some(capacity) = self.capacity {
+            while self.entries.len() > capacity {
+                let Some((old, old_generation)) = self.order.pop_front() else { break };
+                if self.is_current(old, old_generation) {
+                    self.entries.remove(&old);
+                }
+            }
+        }
+        // Repeated sightings leave stale entries behind; drop them once they dominate
+        if self.order.len() > 2 * self.entries.len() + 1024 {
+            let entries = &self.entries;
+            self.order.retain(|&(k, g)| entries.get(&k).is_some_and(|&(current, _)| current == g));
+        }
+        seen
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let Some(ttl) = self.ttl else { return };
+        while let Some(&(key, generation)) = self.order.front() {
+            match self.entries.get(&key) {
+                Some(&(current, seen)) if current == generation => {
+                    if now.duration_since(seen) < ttl {
+                        break;
+                    }
+                    self.entries.remove(&key);
+                }
+                _ => {}
+            }
+            self.order.pop_front();
+        }
+    }
+
+    fn is_current(&self, key: u64, generation: u64) -> bool {
+        self.entries.get(&key).is_some_and(|&(current, _)| current == generation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(key: Option<&str>, payload: &str) -> Message {
+        Message {
+            id: None,
+            parents: Vec::new(),
+            key: key.map(|k| k.as_bytes().to_vec()),
+            payload: payload.as_bytes().to_vec(),
+            format: None,
+            parsed: serde_json::from_str(payload).ok(),
+            timestamp: chrono::Utc::now(),
+            headers: Default::default(),
+            meta: Default::default(),
+            ack: None,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn least_recently_seen_keys_go_first() {
+        let mut seen = SeenSet::new(Some(2), None);
+        assert!(!seen.insert(1));
+        assert!(!seen.insert(2));
+        // Seeing 1 again makes 2 the oldest
+        assert!(seen.insert(1));
+        assert!(!seen.insert(3));
+        assert_eq!(seen.len(), 2);
+        assert!(seen.insert(1));
+        assert!(!seen.insert(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keys_expire_after_the_ttl() {
+        let mut seen = SeenSet::new(None, Some(Duration::from_secs(10)));
+        seen.insert(1);
+        tokio::time::advance(Duration::from_secs(5)).await;
+        seen.insert(2);
+
+        // The TTL runs from the last sighting
+        tokio::time::advance(Duration::from_secs(4)).await;
+        assert!(seen.insert(1));
+        tokio::time::advance(Duration::from_secs(6)).await;
+        assert!(!seen.insert(2));
+        assert!(seen.insert(1));
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(!seen.insert(3));
+        assert_eq!(seen.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unbounded_sets_get_the_default_capacity() {
+        let mut seen = SeenSet::new(None, None);
+        for key in 0..=DEFAULT_CAPACITY as u64 {
+            seen.insert(key);
+        }
+        assert_eq!(seen.len(), DEFAULT_CAPACITY);
+        assert!(!seen.insert(0));
+    }
+
+    #[test]
+    fn auto_uses_the_record_key_when_there_is_one() {
+        let (a, b) = (message(Some("k"), r#"{"n":1}"#), message(Some("k"), r#"{"n":2}"#));
+        assert_eq!(DedupKey::Auto.hash(&a), DedupKey::Key.hash(&b));
+
+        // Without one it falls back to the content
+        let (c, d) = (message(None, r#"{"n":1}"#), message(None, r#"{ "n": 1 }"#));
+        assert_eq!(DedupKey::Auto.hash(&c), DedupKey::Content.hash(&d));
+        assert_ne!(DedupKey::Auto.hash(&c), DedupKey::Auto.hash(&message(None, r#"{"n":2}"#)));
+        assert!(DedupKey::Auto.hash(&message(None, "not json")).is_some());
+    }
+
+    #[test]
+    fn content_ignores_the_record_key() {
+        let (a, b) = (message(Some("x"), r#"{"a":1,"b":2}"#), message(Some("y"), r#"{"b":2,"a":1}"#));
+        assert_eq!(DedupKey::Content.hash(&a), DedupKey::Content.hash(&b));
+        assert_ne!(DedupKey::Key.hash(&a), DedupKey::Key.hash(&b));
+        assert_eq!(DedupKey::Key.hash(&message(None, "{}")), None);
+    }
+
+    #[test]
+    fn fields_compare_only_the_named_values() {
+        let key: DedupKey = "id, user.name".parse().unwrap();
+        let a = message(None, r#"{"id":1,"user":{"name":"ann"},"at":1}"#);
+        let b = message(None, r#"{"id":1,"user":{"name":"ann"},"at":2}"#);
+        let c = message(None, r#"{"id":1,"user":{"name":"bob"}}"#);
+        assert_eq!(key.hash(&a), key.hash(&b));
+        assert_ne!(key.hash(&a), key.hash(&c));
+
+        // A missing field still counts; all of them missing means no key
+        assert!(key.hash(&message(None, r#"{"id":1}"#)).is_some());
+        assert_eq!(key.hash(&message(None, r#"{"other":1}"#)), None);
+        assert_eq!(key.hash(&message(None, "not json")), None);
+    }
+
+    #[test]
+    fn keys_parse_and_print() {
+        assert_eq!("auto".parse::<DedupKey>().unwrap(), DedupKey::Auto);
+        assert_eq!(" key ".parse::<DedupKey>().unwrap(), DedupKey::Key);
+        assert_eq!("a, b.c".parse::<DedupKey>().unwrap().to_string(), "a,b.c");
+        assert!("".parse::<DedupKey>().is_err());
+    }
+}
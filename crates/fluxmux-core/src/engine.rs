@@ -1,97 +1,535 @@
-use crate::middleware::{MiddlewareChain, Deduplicator, Throttler, Batcher, RetryHandler, SchemaValidator};
-use serde::Deserialize;
+use crate::dead_letter::DeadLetterSender;
+use crate::metrics::{
+    Metrics, CHANNEL_DEPTH, CIRCUIT_OPEN, MESSAGES_RECEIVED, SINK_ERRORS, SINK_MESSAGES, SINK_RETRIES, SINK_SEND_SECONDS,
+};
+use crate::retry::{failed_records, is_transient, CircuitBreaker, RetryPolicy};
+use crate::shutdown::Shutdown;
+use crate::middleware::{MiddlewareChain, Deduplicator, Throttler, Batcher, RateLimit, SchemaValidator};
+use crate::rate_limit::{OverLimit, RateLimitConfig, RateLimiter};
+use crate::reload::ChainReloader;
+use crate::dedup::DedupKey;
+use crate::trace;
+use crate::lineage::{self, Lineage, LineageHeaders};
+use crate::event_time::EventTime;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
 pub struct MiddlewareConfig {
     pub batch_size: Option<usize>,
     pub batch_timeout_ms: Option<u64>,
     pub deduplicate: Option<bool>,
+    pub dedup_by: Option<DedupKey>, // auto (default: key, else content), content, key, or comma-separated field paths
+    pub dedup_capacity: Option<usize>, // most keys remembered
+    pub dedup_ttl_ms: Option<u64>, // forget keys not seen for this long
     pub throttle_per_sec: Option<u64>,
+    pub rate_limit: Option<RateLimitConfig>,
     pub retry_max_attempts: Option<u32>,
     pub retry_delay_ms: Option<u64>,
+    pub retry_max_delay_ms: Option<u64>,
+    pub retry_backoff_multiplier: Option<f64>,
+    pub retry_jitter: Option<f64>, // 0.0..=1.0
+    pub circuit_breaker_threshold: Option<u32>, // consecutive transient sink failures
+    pub circuit_breaker_reset_ms: Option<u64>,
     pub schema_path: Option<String>,
+    pub dead_letter: Option<String>, // sink URI for rejected/undeliverable messages
+    pub shutdown_timeout_ms: Option<u64>,
+    pub checkpoint_dir: Option<String>,
+    pub metrics_addr: Option<String>, // host:port for the Prometheus endpoint
+    pub reload_interval_ms: Option<u64>, // how often config and schema files are checked; 0 disables reloading
+    pub lineage: Option<LineageHeaders>, // lineage headers stamped on messages, e.g. id,source,ingest_time
+    pub pipeline_name: Option<String>, // value of the pipeline lineage header (default: bridge)
+    pub event_time: Option<EventTime>, // payload field that sets Message.timestamp
 }
 
-pub fn build_middleware_chain(cfg: &MiddlewareConfig) -> MiddlewareChain {
+impl MiddlewareConfig {
+    /// Settings from `overrides` (e.g. CLI flags) win over those in `self`.
+    pub fn with_overrides(self, overrides: &MiddlewareConfig) -> Self {
+        let o = overrides.clone();
+        Self {
+            batch_size: o.batch_size.or(self.batch_size),
+            batch_timeout_ms: o.batch_timeout_ms.or(self.batch_timeout_ms),
+            deduplicate: o.deduplicate.or(self.deduplicate),
+            dedup_by: o.dedup_by.or(self.dedup_by),
+            dedup_capacity: o.dedup_capacity.or(self.dedup_capacity),
+            dedup_ttl_ms: o.dedup_ttl_ms.or(self.dedup_ttl_ms),
+            throttle_per_sec: o.throttle_per_sec.or(self.throttle_per_sec),
+            rate_limit: o.rate_limit.or(self.rate_limit),
+            retry_max_attempts: o.retry_max_attempts.or(self.retry_max_attempts),
+            retry_delay_ms: o.retry_delay_ms.or(self.retry_delay_ms),
+            retry_max_delay_ms: o.retry_max_delay_ms.or(self.retry_max_delay_ms),
+            retry_backoff_multiplier: o.retry_backoff_multiplier.or(self.retry_backoff_multiplier),
+            retry_jitter: o.retry_jitter.or(self.retry_jitter),
+            circuit_breaker_threshold: o.circuit_breaker_threshold.or(self.circuit_breaker_threshold),
+            circuit_breaker_reset_ms: o.circuit_breaker_reset_ms.or(self.circuit_breaker_reset_ms),
+            schema_path: o.schema_path.or(self.schema_path),
+            dead_letter: o.dead_letter.or(self.dead_letter),
+            shutdown_timeout_ms: o.shutdown_timeout_ms.or(self.shutdown_timeout_ms),
+            checkpoint_dir: o.checkpoint_dir.or(self.checkpoint_dir),
+            metrics_addr: o.metrics_addr.or(self.metrics_addr),
+            reload_interval_ms: o.reload_interval_ms.or(self.reload_interval_ms),
+            lineage: o.lineage.or(self.lineage),
+            pipeline_name: o.pipeline_name.or(self.pipeline_name),
+            event_time: o.event_time.or(self.event_time),
+        }
+    }
+}
+
+/// How often `run_pipeline` ticks the middleware chain.
+pub const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runtime options shared by `run_pipeline` and `run_pipe`.
+#[derive(Default, Clone)]
+pub struct PipelineOptions {
+    pub dead_letter: Option<DeadLetterSender>,
+    pub shutdown: Shutdown,
+    pub metrics: Option<Metrics>,
+    /// Sink retry policy; without one, failed sends are not retried.
+    pub retry: Option<RetryPolicy>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    /// Source of replacement middleware chains for `run_pipeline`.
+    pub reload: Option<ChainReloader>,
+    /// Lineage headers stamped on every message; without it messages still
+    /// get IDs, but no headers.
+    pub lineage: Option<Lineage>,
+    /// Sets each message's timestamp from its payload; without it the
+    /// timestamp is the ingest time.
+    pub event_time: Option<EventTime>,
+}
+
+pub fn build_retry_policy(cfg: &MiddlewareConfig) -> Option<RetryPolicy> {
+    let max_retries = cfg.retry_max_attempts?;
+    let mut policy = RetryPolicy::new(max_retries, Duration::from_millis(cfg.retry_delay_ms.unwrap_or(1000)));
+    if let Some(max_delay) = cfg.retry_max_delay_ms {
+        policy = policy.with_max_delay(Duration::from_millis(max_delay));
+    }
+    if let Some(multiplier) = cfg.retry_backoff_multiplier {
+        policy = policy.with_multiplier(multiplier);
+    }
+    if let Some(jitter) = cfg.retry_jitter {
+        policy = policy.with_jitter(jitter);
+    }
+    Some(policy)
+}
+
+pub fn build_circuit_breaker(cfg: &MiddlewareConfig) -> Option<CircuitBreaker> {
+    let threshold = cfg.circuit_breaker_threshold?;
+    let reset = Duration::from_millis(cfg.circuit_breaker_reset_ms.unwrap_or(30_000));
+    Some(CircuitBreaker::new(threshold, reset))
+}
+
+/// `overflow` receives messages over the rate limit in `route` mode; it
+/// defaults to the dead-letter queue.
+///
+/// Fails on settings the chain cannot run with (unreadable schema, zero
+/// rates), so a bad config is rejected instead of half-applied. Stages with
+/// state are added with their settings, so a reload that leaves those
+/// unchanged keeps the running stage (see `MiddlewareChain::replace`).
+pub fn build_middleware_chain(
+    cfg: &MiddlewareConfig,
+    dead_letter: Option<&DeadLetterSender>,
+    overflow: Option<&DeadLetterSender>,
+) -> anyhow::Result<MiddlewareChain> {
     let mut chain = MiddlewareChain::new();
     
     // Add SchemaValidator first to validate incoming messages
     if let Some(ref schema_path) = cfg.schema_path {
-        chain.add(Box::new(SchemaValidator::new(Some(PathBuf::from(schema_path)))));
+        let mut validator = SchemaValidator::load(PathBuf::from(schema_path))?;
+        if let Some(dlq) = dead_letter {
+            validator = validator.with_dead_letter(dlq.clone());
+        }
+        chain.add(Box::new(validator));
     }
     
-    // Add Deduplicator
-    if cfg.deduplicate.unwrap_or(false) {
-        chain.add(Box::new(Deduplicator::new()));
+    // Add Deduplicator; choosing a key implies deduplication
+    if cfg.deduplicate.unwrap_or(cfg.dedup_by.is_some()) {
+        let dedup = Deduplicator::with_key(cfg.dedup_by.clone().unwrap_or_default())
+            .bounded(cfg.dedup_capacity, cfg.dedup_ttl_ms.map(Duration::from_millis));
+        let settings = format!("{:?}", (&cfg.dedup_by, cfg.dedup_capacity, cfg.dedup_ttl_ms));
+        chain.add_stateful(Box::new(dedup), settings);
     }
     
-    // Add RetryHandler
-    if let Some(max_retries) = cfg.retry_max_attempts {
-        let delay = cfg.retry_delay_ms.unwrap_or(1000);
-        chain.add(Box::new(RetryHandler::new(max_retries, delay)));
+    // Sink retries are configured through build_retry_policy
+    
+    // Add RateLimit before batching so it paces individual messages
+    if let Some(ref rate_limit) = cfg.rate_limit {
+        if !(rate_limit.rate_per_sec.is_finite() && rate_limit.rate_per_sec > 0.0) {
+            anyhow::bail!("rate_limit.rate_per_sec must be positive");
+        }
+        if rate_limit.mode == OverLimit::Route && overflow.or(dead_letter).is_none() {
+            anyhow::bail!("rate_limit mode 'route' requires route_to or dead_letter");
+        }
+        let mut limiter = RateLimit::new(RateLimiter::from_config(rate_limit), rate_limit.mode);
+        if let Some(route) = overflow.or(dead_letter) {
+            limiter = limiter.with_route(route.clone());
+        }
+        chain.add_stateful(Box::new(limiter), format!("{:?}", rate_limit));
     }
     
     // Add Batcher
     if let Some(batch_size) = cfg.batch_size {
+        if batch_size == 0 {
+            anyhow::bail!("batch_size must be positive");
+        }
         let timeout = cfg.batch_timeout_ms.unwrap_or(5000);
-        chain.add(Box::new(Batcher::new(batch_size, timeout)));
+        chain.add_stateful(Box::new(Batcher::new(batch_size, timeout)), format!("{:?}", (batch_size, timeout)));
     }
     
     // Add Throttler last to control output rate
     if let Some(rate) = cfg.throttle_per_sec {
-        chain.add(Box::new(Throttler::new(rate)));
+        if rate == 0 {
+            anyhow::bail!("throttle_per_sec must be positive");
+        }
+        chain.add_stateful(Box::new(Throttler::new(rate)), rate.to_string());
     }
     
-    chain
+    Ok(chain)
 }
 
 use crate::traits::{Source, Sink};
 use crate::message::Message;
 use tokio::sync::mpsc;
+use tracing::Instrument;
 
 pub async fn run_pipeline(
     mut source: Box<dyn Source>,
     mut middlewares: MiddlewareChain,
     mut sink: Box<dyn Sink>,
+    options: PipelineOptions,
 ) -> anyhow::Result<()> {
     let (tx, mut rx) = mpsc::channel::<Message>(1024);
 
     let source_handle = tokio::spawn(async move {
         if let Err(e) = source.start(tx).await {
-            eprintln!("Source stopped with error: {:?}", e);
-        }
-    });
-
-    while let Some(msg) = rx.recv().await {
-        if let Some(processed_msg) = middlewares.process(msg).await {
-            // Retry logic based on message metadata
-            let max_retries = processed_msg.meta.get("max_retries")
-                .and_then(|s| s.parse::<u32>().ok())
-                .unwrap_or(0);
-            let retry_delay_ms = processed_msg.meta.get("retry_delay_ms")
-                .and_then(|s| s.parse::<u64>().ok())
-                .unwrap_or(1000);
-            
-            let mut attempt = 0;
-            loop {
-                match sink.send(processed_msg.clone()).await {
-                    Ok(_) => break,
-                    Err(e) => {
-                        if attempt >= max_retries {
-                            eprintln!("Failed to send message after {} attempts: {}", max_retries + 1, e);
-                            break;
+            tracing::error!(error = format!("{:#}", e), "Source stopped with error");
+        }
+    }.in_current_span());
+
+    let shutdown = options.shutdown.clone();
+    let mut drain_deadline = None;
+    let mut breaker = options.circuit_breaker.clone();
+    middlewares.set_metrics(options.metrics.clone());
+    let mut ticker = tokio::time::interval(TICK_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        let msg = tokio::select! {
+            msg = rx.recv() => msg,
+            _ = shutdown.wait(), if drain_deadline.is_none() => {
+                // Stop the source; dropping its sender lets the channel drain and close
+                source_handle.abort();
+                drain_deadline = Some(shutdown.drain_deadline());
+                continue;
+            }
+            _ = sleep_until_deadline(drain_deadline) => {
+                // Unreceived messages are never armed, so they stay uncommitted
+                tracing::warn!(unprocessed = rx.len(), "Drain deadline reached, queued messages left unprocessed");
+                break;
+            }
+            _ = ticker.tick() => {
+                // Let time-based middlewares (e.g. batch timeouts) release what they hold
+                deliver(sink.as_mut(), middlewares.tick().await, &options, &mut breaker).await;
+                continue;
+            }
+            reloaded = next_chain(options.reload.as_ref()) => {
+                // Unchanged stages carry on with their state; replaced ones release what they hold
+                deliver(sink.as_mut(), middlewares.replace(reloaded).await, &options, &mut breaker).await;
+                continue;
+            }
+        };
+        let Some(mut msg) = msg else { break };
+        msg.arm_ack();
+        record_received(&options, &rx);
+        identify(&options, &mut msg);
+        trace::receive(&mut msg);
+
+        // Whatever one input releases (e.g. a full batch) is written together
+        deliver(sink.as_mut(), middlewares.process(msg).await, &options, &mut breaker).await;
+    }
+
+    // Release whatever the middlewares still hold (e.g. a partial batch)
+    let held = middlewares.finalize().await;
+    let acks: Vec<_> = held.iter().filter_map(|m| m.ack.clone()).collect();
+    let undelivered = held.len();
+    let delivered = until_drained(&shutdown, drain_deadline, deliver(sink.as_mut(), held, &options, &mut breaker)).await;
+    match delivered {
+        Some(()) => match until_drained(&shutdown, drain_deadline, sink.flush()).await {
+            Some(flushed) => flushed?,
+            // An interrupted run exits as such, so a slow flush is only reported
+            None => tracing::warn!(sink = sink.name(), "Sink flush did not finish before the drain deadline"),
+        },
+        None => {
+            // Cut off mid-retry: keep the source from committing past them
+            acks.iter().for_each(|ack| ack.nack());
+            tracing::warn!(undelivered, "Drain deadline reached, held messages left undelivered");
+        }
+    }
+
+    if let Err(e) = source_handle.await {
+        if !e.is_cancelled() {
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+/// Send messages to the sink as one batch, retrying transient failures.
+pub(crate) async fn deliver(
+    sink: &mut dyn Sink,
+    mut batch: Vec<Message>,
+    options: &PipelineOptions,
+    breaker: &mut Option<CircuitBreaker>,
+) {
+    if batch.is_empty() {
+        return;
+    }
+    let policy = options.retry.clone().unwrap_or_default();
+    
+    let mut attempt = 0;
+    loop {
+        // An open breaker holds delivery, and with it the source, until it may be probed
+        if let Some(until) = breaker.as_ref().and_then(|b| b.open_until()) {
+            let resumed = tokio::select! {
+                _ = tokio::time::sleep_until(until) => true,
+                _ = options.shutdown.wait() => false,
+            };
+            if !resumed {
+                tracing::warn!(sink = sink.name(), undelivered = batch.len(), "Circuit open at shutdown, messages left undelivered");
+                // Nacked rather than dead-lettered: the sink failed, not the messages
+                batch.iter().for_each(Message::nack);
+                return;
+            }
+        }
+
+        match send_timed(sink, batch.clone(), options).await {
+            Ok(_) => {
+                if let Some(b) = breaker.as_mut() {
+                    b.record_success();
+                    record_circuit(options, sink.name(), false);
+                }
+                break;
+            }
+            Err(e) => {
+                // Records the sink did accept are done with; only the rest are retried
+                if let Some(failed) = failed_records(&e) {
+                    let mut index = 0;
+                    batch.retain(|_| {
+                        index += 1;
+                        failed.contains(&(index - 1))
+                    });
+                }
+                if is_transient(&e) && breaker.as_mut().is_some_and(|b| b.record_failure()) {
+                    // An outage is not charged against this batch's retries
+                    tracing::warn!(sink = sink.name(), error = %e, "Circuit opened after repeated failures");
+                    record_circuit(options, sink.name(), true);
+                    continue;
+                }
+                if !policy.should_retry(attempt, &e) {
+                    tracing::error!(sink = sink.name(), messages = batch.len(), attempts = attempt + 1, error = %e, "Failed to send messages");
+                    for msg in batch {
+                        match options.dead_letter {
+                            Some(ref dlq) => dlq.send(msg, "sink", e.to_string(), attempt + 1),
+                            // Keep the source from committing past this message
+                            None => msg.nack(),
                         }
-                        attempt += 1;
-                        eprintln!("Retry attempt {}/{} after error: {}", attempt, max_retries, e);
-                        tokio::time::sleep(tokio::time::Duration::from_millis(retry_delay_ms)).await;
                     }
+                    break;
+                }
+                attempt += 1;
+                if let Some(ref metrics) = options.metrics {
+                    metrics.inc(SINK_RETRIES, &[("sink", sink.name())], 1);
                 }
+                let delay = policy.delay(attempt);
+                tracing::warn!(sink = sink.name(), attempt, max_retries = policy.max_retries, delay = ?delay, error = %e, "Retrying send");
+                tokio::time::sleep(delay).await;
             }
         }
     }
-    
-    sink.flush().await?;
-    source_handle.await?;
-    Ok(())
+}
+
+fn record_circuit(options: &PipelineOptions, sink: &str, open: bool) {
+    if let Some(ref metrics) = options.metrics {
+        metrics.set_gauge(CIRCUIT_OPEN, &[("sink", sink)], if open { 1.0 } else { 0.0 });
+    }
+}
+
+/// Send messages (a single one through `send`, several through `send_batch`),
+/// recording latency and outcome when metrics are enabled.
+pub(crate) async fn send_timed(sink: &mut dyn Sink, mut batch: Vec<Message>, options: &PipelineOptions) -> anyhow::Result<()> {
+    if let Some(ref lineage) = options.lineage {
+        batch.iter_mut().for_each(|msg| lineage.send(msg));
+    }
+    let started = tokio::time::Instant::now();
+    let count = batch.len() as u64;
+    let span = tracing::debug_span!("send", sink = sink.name(), messages = count);
+    trace::follow_batch(&span, &batch);
+    let result = match batch.len() {
+        1 => sink.send(batch.remove(0)).instrument(span).await,
+        _ => sink.send_batch(batch).instrument(span).await,
+    };
+    let Some(ref metrics) = options.metrics else {
+        return result;
+    };
+    let labels = [("sink", sink.name())];
+    metrics.observe(SINK_SEND_SECONDS, &labels, started.elapsed().as_secs_f64());
+    match result {
+        Ok(_) => metrics.inc(SINK_MESSAGES, &labels, count),
+        Err(_) => metrics.inc(SINK_ERRORS, &labels, 1),
+    }
+    result
+}
+
+/// Count a message taken from the source channel and sample the channel depth.
+pub(crate) fn record_received(options: &PipelineOptions, rx: &mpsc::Receiver<Message>) {
+    if let Some(ref metrics) = options.metrics {
+        metrics.inc(MESSAGES_RECEIVED, &[], 1);
+        metrics.set_gauge(CHANNEL_DEPTH, &[], rx.len() as f64);
+    }
+}
+
+/// Give a message taken from the source an ID if it has none, set its event
+/// time, and stamp its lineage headers.
+pub(crate) fn identify(options: &PipelineOptions, msg: &mut Message) {
+    if msg.id.is_none() {
+        msg.id = Some(lineage::new_id());
+    }
+    if let Some(ref event_time) = options.event_time {
+        if !event_time.apply(msg) {
+            tracing::warn!(id = ?msg.id, field = %event_time.field, "Message has no valid event time, keeping its ingest time");
+        }
+    }
+    if let Some(ref lineage) = options.lineage {
+        lineage.receive(msg);
+    }
+}
+
+/// Resolves with a reloaded chain, or never without a reloader.
+async fn next_chain(reload: Option<&ChainReloader>) -> MiddlewareChain {
+    match reload {
+        Some(reload) => reload.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Run `fut` to completion, or until the drain deadline: `deadline` if
+/// shutdown already began, otherwise one starting when it is requested.
+/// None when the deadline came first.
+pub(crate) async fn until_drained<F: std::future::Future>(
+    shutdown: &Shutdown,
+    deadline: Option<tokio::time::Instant>,
+    fut: F,
+) -> Option<F::Output> {
+    let drained = async {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => {
+                shutdown.wait().await;
+                tokio::time::sleep_until(shutdown.drain_deadline()).await;
+            }
+        }
+    };
+    tokio::select! {
+        output = fut => Some(output),
+        _ = drained => None,
+    }
+}
+
+/// Resolves at the drain deadline, or never while no shutdown is in progress.
+pub(crate) async fn sleep_until_deadline(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ack::Ack;
+    use crate::retry::{partial_failure, transient};
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+
+    /// Records every batch it is sent and fails the records whose payload is
+    /// `fail`: permanently, or only on the first attempt when `transient`.
+    struct PartialSink {
+        batches: Arc<Mutex<Vec<Vec<String>>>>,
+        transient: bool,
+    }
+
+    #[async_trait]
+    impl Sink for PartialSink {
+        async fn send(&mut self, msg: Message) -> anyhow::Result<()> {
+            self.send_batch(vec![msg]).await
+        }
+        async fn send_batch(&mut self, batch: Vec<Message>) -> anyhow::Result<()> {
+            let payloads: Vec<String> = batch.iter().map(|m| String::from_utf8_lossy(&m.payload).into_owned()).collect();
+            let attempt = self.batches.lock().unwrap().len();
+            self.batches.lock().unwrap().push(payloads.clone());
+            let failed: Vec<usize> = payloads.iter().enumerate().filter(|(_, p)| *p == "fail").map(|(i, _)| i).collect();
+            match failed.is_empty() {
+                true => Ok(()),
+                false if self.transient && attempt == 0 => Err(partial_failure(failed, transient(anyhow::anyhow!("queue full")))),
+                false if self.transient => Ok(()),
+                false => Err(partial_failure(failed, anyhow::anyhow!("message too large"))),
+            }
+        }
+        fn name(&self) -> &'static str {
+            "partial"
+        }
+        async fn flush(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn message(payload: &str, acked: &Arc<Mutex<Vec<String>>>) -> Message {
+        let (acked, name) = (acked.clone(), payload.to_string());
+        Message {
+            id: None,
+            parents: Vec::new(),
+            key: None,
+            payload: payload.as_bytes().to_vec(),
+            format: None,
+            parsed: None,
+            timestamp: chrono::Utc::now(),
+            headers: Default::default(),
+            meta: Default::default(),
+            ack: Some(Ack::new(move || acked.lock().unwrap().push(name))),
+        }
+    }
+
+    fn batch(acked: &Arc<Mutex<Vec<String>>>) -> Vec<Message> {
+        let batch: Vec<Message> = ["a", "fail", "c"].iter().map(|p| message(p, acked)).collect();
+        batch.iter().for_each(Message::arm_ack);
+        batch
+    }
+
+    #[tokio::test]
+    async fn only_failed_records_are_retried() {
+        let (batches, acked) = (Arc::default(), Arc::default());
+        let mut sink = PartialSink { batches: Arc::clone(&batches), transient: true };
+        let options = PipelineOptions { retry: Some(RetryPolicy::new(3, Duration::from_millis(1))), ..Default::default() };
+        deliver(&mut sink, batch(&acked), &options, &mut None).await;
+
+        assert_eq!(*batches.lock().unwrap(), vec![vec!["a", "fail", "c"], vec!["fail"]]);
+        let mut acked = acked.lock().unwrap().clone();
+        acked.sort();
+        assert_eq!(acked, vec!["a", "c", "fail"]);
+    }
+
+    #[tokio::test]
+    async fn only_failed_records_are_given_up_on() {
+        let (batches, acked) = (Arc::default(), Arc::default());
+        let mut sink = PartialSink { batches: Arc::clone(&batches), transient: false };
+        let options = PipelineOptions { retry: Some(RetryPolicy::new(3, Duration::from_millis(1))), ..Default::default() };
+        deliver(&mut sink, batch(&acked), &options, &mut None).await;
+
+        // Permanent failures are not retried, and without a dead-letter queue
+        // only the failed record is nacked
+        assert_eq!(batches.lock().unwrap().len(), 1);
+        let mut acked = acked.lock().unwrap().clone();
+        acked.sort();
+        assert_eq!(acked, vec!["a", "c"]);
+    }
 }
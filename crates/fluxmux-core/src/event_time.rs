@@ -0,0 +1,220 @@
+//! Event time: when a record says it happened, as opposed to when it was read.
+//!
+//! Sources stamp `Message.timestamp` with the ingest time. An `EventTime`
+//! extractor replaces it with a time read from the payload, so windows can be
+//! placed on event time. A `Watermark` tracks how far event time has
+//! progressed, holding windows open for an allowed lateness so out-of-order
+//! records still land in the right window; records later than that are
+//! handled by a `LatePolicy`.
+
+use crate::message::Message;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+
+/// How an event time field is written.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TimeFormat {
+    /// Epoch milliseconds (number or numeric string) or RFC 3339
+    #[default]
+    Auto,
+    Rfc3339,
+    EpochMillis,
+    EpochSeconds,
+    /// A chrono `strftime` pattern, e.g. `%Y-%m-%d %H:%M:%S`; times without
+    /// an offset are taken as UTC
+    Pattern(String),
+}
+
+impl std::str::FromStr for TimeFormat {
+    type Err = anyhow::Error;
+
+    /// `auto`, `rfc3339`, `epoch_ms`, `epoch_s`, or a pattern containing `%`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "rfc3339" => Ok(Self::Rfc3339),
+            "epoch_ms" => Ok(Self::EpochMillis),
+            "epoch_s" => Ok(Self::EpochSeconds),
+            pattern if pattern.contains('%') => Ok(Self::Pattern(pattern.to_string())),
+            other => Err(anyhow::anyhow!(
+                "Unknown time format '{}', expected auto, rfc3339, epoch_ms, epoch_s or a strftime pattern",
+                other
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for TimeFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::fmt::Display for TimeFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Rfc3339 => write!(f, "rfc3339"),
+            Self::EpochMillis => write!(f, "epoch_ms"),
+            Self::EpochSeconds => write!(f, "epoch_s"),
+            Self::Pattern(pattern) => write!(f, "{}", pattern),
+        }
+    }
+}
+
+impl From<TimeFormat> for String {
+    fn from(format: TimeFormat) -> Self {
+        format.to_string()
+    }
+}
+
+/// Reads the event time of a message from a payload field.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventTime {
+    /// Dotted path into the parsed payload, e.g. `meta.ts`
+    pub field: String,
+    #[serde(default)]
+    pub format: TimeFormat,
+}
+
+impl EventTime {
+    pub fn new(field: impl Into<String>, format: TimeFormat) -> Self {
+        Self { field: field.into(), format }
+    }
+
+    /// The message's event time; `None` when the field is missing or does not
+    /// match the format. Unparsed (e.g. Kafka) payloads are read as JSON.
+    pub fn extract(&self, msg: &Message) -> Option<DateTime<Utc>> {
+        let payload;
+        let root = match msg.parsed {
+            Some(ref parsed) => parsed,
+            None => {
+                payload = serde_json::from_slice::<Value>(&msg.payload).ok()?;
+                &payload
+            }
+        };
+        let value = self.field.split('.').try_fold(root, |v, part| v.get(part))?;
+        parse(value, &self.format)
+    }
+
+    /// Set `msg.timestamp` to the event time; false (timestamp unchanged) when
+    /// the message has none.
+    pub fn apply(&self, msg: &mut Message) -> bool {
+        match self.extract(msg) {
+            Some(time) => {
+                msg.timestamp = time;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl std::fmt::Display for EventTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "field={} format={}", self.field, self.format)
+    }
+}
+
+/// Parse a timestamp written in `format`.
+pub fn parse(value: &Value, format: &TimeFormat) -> Option<DateTime<Utc>> {
+    let millis = |n: f64| Utc.timestamp_millis_opt(n as i64).single();
+    let number = || match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    };
+    match format {
+        TimeFormat::Auto => match value {
+            Value::String(s) if s.parse::<f64>().is_err() => parse(value, &TimeFormat::Rfc3339),
+            _ => millis(number()?),
+        },
+        TimeFormat::Rfc3339 => DateTime::parse_from_rfc3339(value.as_str()?).ok().map(|t| t.with_timezone(&Utc)),
+        TimeFormat::EpochMillis => millis(number()?),
+        TimeFormat::EpochSeconds => millis(number()? * 1000.0),
+        TimeFormat::Pattern(pattern) => {
+            let s = value.as_str()?;
+            match DateTime::parse_from_str(s, pattern) {
+                Ok(t) => Some(t.with_timezone(&Utc)),
+                Err(_) => NaiveDateTime::parse_from_str(s, pattern).ok().map(|t| t.and_utc()),
+            }
+        }
+    }
+}
+
+/// How far event time has progressed, in epoch milliseconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Watermark {
+    max_time: i64,
+    allowed_lateness: i64,
+}
+
+impl Watermark {
+    /// Windows stay open until the latest event time seen is
+    /// `allowed_lateness` past their end.
+    pub fn new(allowed_lateness: Duration) -> Self {
+        Self { max_time: i64::MIN, allowed_lateness: allowed_lateness.as_millis() as i64 }
+    }
+
+    pub fn observe(&mut self, time: i64) {
+        self.max_time = self.max_time.max(time);
+    }
+
+    /// Event time up to which input is taken to be complete.
+    pub fn current(&self) -> i64 {
+        self.max_time.saturating_sub(self.allowed_lateness)
+    }
+
+    pub fn allowed_lateness(&self) -> i64 {
+        self.allowed_lateness
+    }
+}
+
+impl Default for Watermark {
+    fn default() -> Self {
+        Self::new(Duration::ZERO)
+    }
+}
+
+/// What happens to a record whose windows have all been emitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LatePolicy {
+    /// Discard the record
+    #[default]
+    Drop,
+    /// Hand the record to a side sink
+    Route,
+    /// Add the record to its emitted window and emit the window again; windows
+    /// are kept for another allowed lateness after they close
+    Update,
+}
+
+impl std::str::FromStr for LatePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(Self::Drop),
+            "route" => Ok(Self::Route),
+            "update" => Ok(Self::Update),
+            other => Err(anyhow::anyhow!("Unknown late policy '{}', expected drop, route or update", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for LatePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Drop => write!(f, "drop"),
+            Self::Route => write!(f, "route"),
+            Self::Update => write!(f, "update"),
+        }
+    }
+}
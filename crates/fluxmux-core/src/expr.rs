@@ -0,0 +1,843 @@
+//! Expressions for `filter`, `transform` and `route`.
+//!
+//! An expression is parsed once into an `Expr` tree and evaluated against
+//! each message's parsed payload:
+//!
+//! ```text
+//! temp > 30 && (site == "lab" || site in ["roof", "yard"])
+//! if temp >= 30 then "hot" else "ok"
+//! case when level == "error" then 3 when level == "warn" then 2 else 1 end
+//! ```
+//!
+//! From loosest to tightest binding: `||` (`or`), `&&` (`and`), `!` (`not`),
+//! comparisons (`== != < <= > >=`, `in`, `not in`), `+ -`, `* / %`, unary
+//! `-`. Fields are dotted paths (`reading.temp`, `tags.0`) and evaluate to
+//! `null` when missing; backticks quote names that are not plain words, such
+//! as `` `user-id` `` or `` `order.item-count` ``. A name with a `-` must be
+//! quoted: `user-id` is an error rather than a subtraction, which needs spaces
+//! (`total - discount`). A bare word on the right of `==`, `!=` or in an `in`
+//! list that names no field is read as a string, so `level == error` works
+//! like `level == "error"`. Expressions nest at most 100 levels deep, each
+//! parenthesis, `!`, `if` or operator in a chain counting as a level.
+//!
+//! Values keep their JSON types: `==` is true only for equal values of the
+//! same type (numbers compare by value, `1 == 1.0`), `<` and friends compare
+//! two numbers or two strings and are false otherwise, arithmetic on anything
+//! but numbers gives `null`, except `+` with a string, which concatenates.
+//! `null` and `false` are false as conditions; every other value is true.
+
+use serde_json::{Map, Number, Value};
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    /// Dotted path into the payload
+    Field(Vec<String>),
+    /// A bare word compared against: the field of that name, or else the
+    /// word as a string
+    Word(String),
+    List(Vec<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    /// Element of a list, or substring of a string
+    In(Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `when` conditions and their results, then the `else` result
+    Case(Vec<(Expr, Expr)>, Option<Box<Expr>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut parser = Parser::new(input)?;
+        if parser.at_end() {
+            anyhow::bail!("expression is empty");
+        }
+        let expr = parser.expr()?;
+        parser.finish()?;
+        Ok(expr)
+    }
+
+    /// The value of the expression for payload `root`.
+    pub fn eval(&self, root: &Value) -> Value {
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Field(path) => lookup(root, path).cloned().unwrap_or(Value::Null),
+            Expr::Word(word) => root.get(word).cloned().unwrap_or_else(|| Value::String(word.clone())),
+            Expr::List(items) => Value::Array(items.iter().map(|item| item.eval(root)).collect()),
+            Expr::Not(inner) => Value::Bool(!inner.matches(root)),
+            Expr::Neg(inner) => arithmetic(BinOp::Sub, &Value::from(0), &inner.eval(root)),
+            Expr::Binary(BinOp::Or, a, b) => Value::Bool(a.matches(root) || b.matches(root)),
+            Expr::Binary(BinOp::And, a, b) => Value::Bool(a.matches(root) && b.matches(root)),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(root), b.eval(root));
+                match op {
+                    BinOp::Eq => Value::Bool(equal(&a, &b)),
+                    BinOp::Ne => Value::Bool(!equal(&a, &b)),
+                    BinOp::Lt => Value::Bool(compare(&a, &b) == Some(Ordering::Less)),
+                    BinOp::Le => Value::Bool(matches!(compare(&a, &b), Some(Ordering::Less | Ordering::Equal))),
+                    BinOp::Gt => Value::Bool(compare(&a, &b) == Some(Ordering::Greater)),
+                    BinOp::Ge => Value::Bool(matches!(compare(&a, &b), Some(Ordering::Greater | Ordering::Equal))),
+                    op => arithmetic(*op, &a, &b),
+                }
+            }
+            Expr::In(needle, haystack) => {
+                let needle = needle.eval(root);
+                Value::Bool(match haystack.eval(root) {
+                    Value::Array(items) => items.iter().any(|item| equal(&needle, item)),
+                    Value::String(s) => needle.as_str().is_some_and(|n| s.contains(n)),
+                    _ => false,
+                })
+            }
+            Expr::If(cond, then, otherwise) => match cond.matches(root) {
+                true => then.eval(root),
+                false => otherwise.eval(root),
+            },
+            Expr::Case(arms, otherwise) => arms
+                .iter()
+                .find(|(cond, _)| cond.matches(root))
+                .map(|(_, result)| result.eval(root))
+                .or_else(|| otherwise.as_ref().map(|e| e.eval(root)))
+                .unwrap_or(Value::Null),
+        }
+    }
+
+    /// Whether the expression holds for payload `root`.
+    pub fn matches(&self, root: &Value) -> bool {
+        truthy(&self.eval(root))
+    }
+}
+
+impl std::str::FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Parse `field=expr, field2=expr2, ...`, as `transform` takes them. Fields
+/// are dotted paths.
+pub fn assignments(input: &str) -> anyhow::Result<Vec<(Vec<String>, Expr)>> {
+    let mut parser = Parser::new(input)?;
+    if parser.at_end() {
+        anyhow::bail!("expression is empty");
+    }
+    let mut assignments = Vec::new();
+    loop {
+        let field = match parser.next() {
+            Some(Token::Ident(name) | Token::Quoted(name)) => name.split('.').map(String::from).collect(),
+            _ => return Err(parser.error_at_previous("expected a field name")),
+        };
+        parser.expect(&Token::Assign, "'=' after the field name")?;
+        assignments.push((field, parser.expr()?));
+        if !parser.eat(&Token::Comma) {
+            break;
+        }
+    }
+    parser.finish()?;
+    Ok(assignments)
+}
+
+/// `null` and `false` are false, everything else is true.
+pub fn truthy(value: &Value) -> bool {
+    !matches!(value, Value::Null | Value::Bool(false))
+}
+
+/// Set the value at a dotted path, creating missing objects on the way.
+/// False when `root` or a value on the path is not an object.
+pub fn assign(root: &mut Value, path: &[String], value: Value) -> bool {
+    let Some((last, parents)) = path.split_last() else { return false };
+    let mut target = root;
+    for part in parents {
+        let Value::Object(obj) = target else { return false };
+        target = obj.entry(part.clone()).or_insert_with(|| Value::Object(Map::new()));
+    }
+    match target {
+        Value::Object(obj) => {
+            obj.insert(last.clone(), value);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn lookup<'a>(root: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(root, |value, part| match value {
+        Value::Array(items) => items.get(part.parse::<usize>().ok()?),
+        _ => value.get(part),
+    })
+}
+
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        (Value::Array(x), Value::Array(y)) => x.len() == y.len() && x.iter().zip(y).all(|(x, y)| equal(x, y)),
+        _ => a == b,
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64()?.partial_cmp(&y.as_f64()?),
+        (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+        _ => None,
+    }
+}
+
+fn arithmetic(op: BinOp, a: &Value, b: &Value) -> Value {
+    let (x, y) = match (a, b) {
+        (Value::Number(x), Value::Number(y)) => (x, y),
+        (Value::String(_), Value::Null) | (Value::Null, Value::String(_)) => return Value::Null,
+        (Value::String(_), _) | (_, Value::String(_)) if op == BinOp::Add => {
+            return Value::String(format!("{}{}", text(a), text(b)));
+        }
+        _ => return Value::Null,
+    };
+    // Whole numbers stay whole where the result is
+    if let (Some(x), Some(y)) = (x.as_i64(), y.as_i64()) {
+        let whole = match op {
+            BinOp::Add => x.checked_add(y),
+            BinOp::Sub => x.checked_sub(y),
+            BinOp::Mul => x.checked_mul(y),
+            BinOp::Div if x.checked_rem(y) == Some(0) => x.checked_div(y),
+            BinOp::Rem => x.checked_rem(y),
+            _ => None,
+        };
+        if let Some(n) = whole {
+            return Value::from(n);
+        }
+    }
+    let (Some(x), Some(y)) = (x.as_f64(), y.as_f64()) else { return Value::Null };
+    let result = match op {
+        BinOp::Add => x + y,
+        BinOp::Sub => x - y,
+        BinOp::Mul => x * y,
+        BinOp::Div => x / y,
+        _ => x % y,
+    };
+    Number::from_f64(result).map_or(Value::Null, Value::Number)
+}
+
+/// A value as concatenated by `+`: strings without quotes.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Number),
+    Str(String),
+    Ident(String),
+    /// A backtick-quoted field path, never a keyword
+    Quoted(String),
+    Op(&'static str),
+    Assign,
+    Comma,
+    Open(char),
+    Close(char),
+}
+
+const OPERATORS: &[&str] = &["==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "*", "/", "%"];
+
+fn tokenize(input: &str) -> anyhow::Result<Vec<(usize, Token)>> {
+    let chars: Vec<(usize, char)> = input.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while let Some(&(pos, c)) = chars.get(i) {
+        let rest = &input[pos..];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = if c.is_ascii_digit() || (c == '.' && rest[1..].starts_with(|d: char| d.is_ascii_digit())) {
+            let len = rest
+                .char_indices()
+                .find(|&(j, d)| {
+                    let exponent_sign = (d == '-' || d == '+') && j > 0 && rest[..j].ends_with(['e', 'E']);
+                    !(d.is_ascii_digit() || d == '.' || d == 'e' || d == 'E' || exponent_sign)
+                })
+                .map_or(rest.len(), |(j, _)| j);
+            let literal = &rest[..len];
+            let number = match literal.parse::<i64>() {
+                Ok(n) => Number::from(n),
+                Err(_) => literal
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(Number::from_f64)
+                    .ok_or_else(|| anyhow::anyhow!("invalid number '{}' at column {}", literal, pos + 1))?,
+            };
+            i += literal.chars().count();
+            Token::Number(number)
+        } else if c == '"' || c == '\'' {
+            let mut s = String::new();
+            let mut closed = false;
+            i += 1;
+            while let Some(&(_, d)) = chars.get(i) {
+                i += 1;
+                match d {
+                    '\\' => {
+                        let Some(&(_, escaped)) = chars.get(i) else { break };
+                        i += 1;
+                        s.push(match escaped {
+                            'n' => '\n',
+                            't' => '\t',
+                            other => other,
+                        });
+                    }
+                    d if d == c => {
+                        closed = true;
+                        break;
+                    }
+                    d => s.push(d),
+                }
+            }
+            if !closed {
+                anyhow::bail!("unterminated string starting at column {}", pos + 1);
+            }
+            Token::Str(s)
+        } else if c == '`' {
+            let Some(len) = rest[1..].find('`') else {
+                anyhow::bail!("unterminated field name starting at column {}", pos + 1);
+            };
+            if len == 0 {
+                anyhow::bail!("empty field name at column {}", pos + 1);
+            }
+            i += rest[..len + 2].chars().count();
+            Token::Quoted(rest[1..len + 1].to_string())
+        } else if c.is_alphabetic() || c == '_' {
+            // A dotted path; segments after the first may be array indices
+            let len = rest
+                .char_indices()
+                .find(|&(j, d)| {
+                    let dot = d == '.' && rest[j + 1..].starts_with(|e: char| e.is_alphanumeric() || e == '_');
+                    !(d.is_alphanumeric() || d == '_' || dot)
+                })
+                .map_or(rest.len(), |(j, _)| j);
+            // `user-id` is far more likely a field name than a subtraction
+            if rest[len..].starts_with('-') && rest[len + 1..].starts_with(|e: char| e.is_alphabetic() || e == '_') {
+                let name = rest[len..].find(|e: char| !(e.is_alphanumeric() || e == '_' || e == '-' || e == '.'));
+                let name = &rest[..name.map_or(rest.len(), |end| len + end)];
+                anyhow::bail!(
+                    "'{}' at column {} is not a field name; quote it as `{}`, or put spaces around '-' to subtract",
+                    name, pos + 1, name
+                );
+            }
+            i += rest[..len].chars().count();
+            Token::Ident(rest[..len].to_string())
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            i += op.len();
+            Token::Op(op)
+        } else {
+            i += 1;
+            match c {
+                '=' => Token::Assign,
+                ',' => Token::Comma,
+                '(' | '[' => Token::Open(c),
+                ')' | ']' => Token::Close(c),
+                other => anyhow::bail!("unexpected '{}' at column {}", other, pos + 1),
+            }
+        };
+        tokens.push((pos, token));
+    }
+    Ok(tokens)
+}
+
+const KEYWORDS: &[&str] = &["and", "or", "not", "in", "if", "then", "else", "case", "when", "end", "true", "false", "null"];
+
+/// How deep parentheses, operators and `if`/`case` may nest, so that parsing
+/// and evaluating stay well within the stack.
+const MAX_DEPTH: usize = 100;
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    len: usize, // input length, for errors at the end
+    depth: usize, // nesting of the expression being parsed
+}
+
+impl Parser {
+    fn new(input: &str) -> anyhow::Result<Self> {
+        Ok(Self { tokens: tokenize(input)?, pos: 0, len: input.len(), depth: 0 })
+    }
+
+    /// Go one level deeper; callers restore `depth` when done.
+    fn deeper(&mut self) -> anyhow::Result<()> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH {
+            true => Err(self.error(&format!("expression nested more than {} levels deep", MAX_DEPTH))),
+            false => Ok(()),
+        }
+    }
+
+    /// Parse with `parse`; a lone bare word may then stand for itself (see `word`).
+    fn operand(&mut self, parse: fn(&mut Self) -> anyhow::Result<Expr>) -> anyhow::Result<Expr> {
+        let start = self.pos;
+        let expr = parse(self)?;
+        Ok(match self.tokens.get(start) {
+            Some((_, Token::Ident(_))) if self.pos == start + 1 => word(expr),
+            _ => expr,
+        })
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek()?.clone();
+        self.pos += 1;
+        Some(token)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        self.eat(&Token::Op(OPERATORS.iter().find(|o| **o == op).copied().unwrap_or("")))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Ident(word)) if word == keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &Token, what: &str) -> anyhow::Result<()> {
+        match self.eat(token) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected {}", what))),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> anyhow::Result<()> {
+        match self.eat_keyword(keyword) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected '{}'", keyword))),
+        }
+    }
+
+    fn finish(&self) -> anyhow::Result<()> {
+        match self.tokens.get(self.pos) {
+            None => Ok(()),
+            Some((column, token)) => Err(anyhow::anyhow!("unexpected {} at column {}", describe(token), column + 1)),
+        }
+    }
+
+    /// An error at the current token, e.g. "expected ')' at column 7 ('x')".
+    fn error(&self, message: &str) -> anyhow::Error {
+        match self.tokens.get(self.pos) {
+            Some((column, token)) => anyhow::anyhow!("{} at column {} ({})", message, column + 1, describe(token)),
+            None => anyhow::anyhow!("{} at the end (column {})", message, self.len + 1),
+        }
+    }
+
+    fn error_at_previous(&mut self, message: &str) -> anyhow::Error {
+        self.pos = self.pos.saturating_sub(1);
+        self.error(message)
+    }
+
+    fn expr(&mut self) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        self.deeper()?;
+        let expr = self.or()?;
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    // Each operator in a chain nests the tree one level deeper
+    fn or(&mut self) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        let mut left = self.and()?;
+        while self.eat_op("||") || self.eat_keyword("or") {
+            self.deeper()?;
+            left = Expr::Binary(BinOp::Or, Box::new(left), Box::new(self.and()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        let mut left = self.not()?;
+        while self.eat_op("&&") || self.eat_keyword("and") {
+            self.deeper()?;
+            left = Expr::Binary(BinOp::And, Box::new(left), Box::new(self.not()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn not(&mut self) -> anyhow::Result<Expr> {
+        if self.eat_op("!") || self.eat_keyword("not") {
+            let depth = self.depth;
+            self.deeper()?;
+            let inner = self.not()?;
+            self.depth = depth;
+            return Ok(Expr::Not(Box::new(inner)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> anyhow::Result<Expr> {
+        let left = self.sum()?;
+        for (op, bin) in [("==", BinOp::Eq), ("!=", BinOp::Ne), ("<=", BinOp::Le), (">=", BinOp::Ge), ("<", BinOp::Lt), (">", BinOp::Gt)] {
+            if self.eat_op(op) {
+                let right = match bin {
+                    BinOp::Eq | BinOp::Ne => self.operand(Self::sum)?,
+                    _ => self.sum()?,
+                };
+                return Ok(Expr::Binary(bin, Box::new(left), Box::new(right)));
+            }
+        }
+        if self.eat_keyword("in") {
+            return Ok(Expr::In(Box::new(left), Box::new(self.in_list()?)));
+        }
+        // `not in`; a lone `not` here is left for the caller to reject
+        if matches!(self.peek(), Some(Token::Ident(w)) if w == "not")
+            && matches!(self.tokens.get(self.pos + 1), Some((_, Token::Ident(w))) if w == "in")
+        {
+            self.pos += 2;
+            return Ok(Expr::Not(Box::new(Expr::In(Box::new(left), Box::new(self.in_list()?)))));
+        }
+        Ok(left)
+    }
+
+    /// `[a, b]`, `(a, b)`, or any value (a list or string field).
+    fn in_list(&mut self) -> anyhow::Result<Expr> {
+        for (open, close) in [('(', ')'), ('[', ']')] {
+            if self.eat(&Token::Open(open)) {
+                return Ok(Expr::List(self.items(close, true)?));
+            }
+        }
+        self.sum()
+    }
+
+    /// Items up to `close`; with `words`, bare words may stand for themselves.
+    fn items(&mut self, close: char, words: bool) -> anyhow::Result<Vec<Expr>> {
+        let mut items = Vec::new();
+        if self.eat(&Token::Close(close)) {
+            return Ok(items);
+        }
+        loop {
+            items.push(match words {
+                true => self.operand(Self::expr)?,
+                false => self.expr()?,
+            });
+            if self.eat(&Token::Close(close)) {
+                return Ok(items);
+            }
+            self.expect(&Token::Comma, &format!("',' or '{}'", close))?;
+        }
+    }
+
+    fn sum(&mut self) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        let mut left = self.product()?;
+        loop {
+            let op = if self.eat_op("+") {
+                BinOp::Add
+            } else if self.eat_op("-") {
+                BinOp::Sub
+            } else {
+                self.depth = depth;
+                return Ok(left);
+            };
+            self.deeper()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> anyhow::Result<Expr> {
+        let depth = self.depth;
+        let mut left = self.unary()?;
+        loop {
+            let op = if self.eat_op("*") {
+                BinOp::Mul
+            } else if self.eat_op("/") {
+                BinOp::Div
+            } else if self.eat_op("%") {
+                BinOp::Rem
+            } else {
+                self.depth = depth;
+                return Ok(left);
+            };
+            self.deeper()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Expr> {
+        if self.eat_op("-") {
+            let depth = self.depth;
+            self.deeper()?;
+            let inner = self.unary()?;
+            self.depth = depth;
+            return Ok(match inner {
+                Expr::Literal(Value::Number(n)) => Expr::Literal(arithmetic(BinOp::Sub, &Value::from(0), &Value::Number(n))),
+                other => Expr::Neg(Box::new(other)),
+            });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> anyhow::Result<Expr> {
+        let Some(token) = self.next() else {
+            return Err(self.error("expected a value"));
+        };
+        Ok(match token {
+            Token::Number(n) => Expr::Literal(Value::Number(n)),
+            Token::Str(s) => Expr::Literal(Value::String(s)),
+            Token::Open('(') => {
+                let inner = self.expr()?;
+                self.expect(&Token::Close(')'), "')'")?;
+                inner
+            }
+            Token::Open('[') => Expr::List(self.items(']', false)?),
+            Token::Quoted(path) => Expr::Field(path.split('.').map(String::from).collect()),
+            Token::Ident(word) => match word.as_str() {
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                "null" => Expr::Literal(Value::Null),
+                "if" => {
+                    let cond = self.expr()?;
+                    self.expect_keyword("then")?;
+                    let then = self.expr()?;
+                    self.expect_keyword("else")?;
+                    Expr::If(Box::new(cond), Box::new(then), Box::new(self.expr()?))
+                }
+                "case" => {
+                    let mut arms = Vec::new();
+                    while self.eat_keyword("when") {
+                        let cond = self.expr()?;
+                        self.expect_keyword("then")?;
+                        arms.push((cond, self.expr()?));
+                    }
+                    if arms.is_empty() {
+                        return Err(self.error("expected 'when'"));
+                    }
+                    let otherwise = match self.eat_keyword("else") {
+                        true => Some(Box::new(self.expr()?)),
+                        false => None,
+                    };
+                    self.expect_keyword("end")?;
+                    Expr::Case(arms, otherwise)
+                }
+                keyword if KEYWORDS.contains(&keyword) => return Err(self.error_at_previous("expected a value")),
+                path => Expr::Field(path.split('.').map(String::from).collect()),
+            },
+            _ => return Err(self.error_at_previous("expected a value")),
+        })
+    }
+}
+
+/// A single-word field compared against may also stand for that word.
+fn word(expr: Expr) -> Expr {
+    match expr {
+        Expr::Field(mut path) if path.len() == 1 => Expr::Word(path.remove(0)),
+        other => other,
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(n) => format!("'{}'", n),
+        Token::Str(s) => format!("{:?}", s),
+        Token::Ident(word) => format!("'{}'", word),
+        Token::Quoted(path) => format!("`{}`", path),
+        Token::Op(op) => format!("'{}'", op),
+        Token::Assign => "'='".to_string(),
+        Token::Comma => "','".to_string(),
+        Token::Open(c) | Token::Close(c) => format!("'{}'", c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn eval(expr: &str, root: &Value) -> Value {
+        Expr::parse(expr).unwrap().eval(root)
+    }
+
+    fn error(expr: &str) -> String {
+        Expr::parse(expr).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn operators_bind_by_tier() {
+        let root = json!({ "a": 1, "b": 2, "c": 3 });
+        assert_eq!(eval("1 + 2 * 3", &root), json!(7));
+        assert_eq!(eval("(1 + 2) * 3", &root), json!(9));
+        assert_eq!(eval("10 - 4 - 3", &root), json!(3));
+        assert_eq!(eval("-a * 2 + 7 % 4", &root), json!(1));
+        assert_eq!(eval("a + b == c", &root), json!(true));
+        assert_eq!(eval("a == 1 && b == 2 || c == 0", &root), json!(true));
+        assert_eq!(eval("a == 0 && b == 2 || c == 3", &root), json!(true));
+        assert_eq!(eval("a == 0 and (b == 2 or c == 3)", &root), json!(false));
+        assert_eq!(eval("a + 1 in [2, 3]", &root), json!(true));
+        assert_eq!(eval("c not in (1, 2)", &root), json!(true));
+    }
+
+    #[test]
+    fn not_binds_looser_than_comparisons() {
+        let root = json!({ "a": 1 });
+        assert_eq!(Expr::parse("!a == 2").unwrap(), Expr::parse("!(a == 2)").unwrap());
+        assert_eq!(eval("!a == 2", &root), json!(true));
+        assert_eq!(eval("not a == 1", &root), json!(false));
+        assert_eq!(eval("!a == 2 && a == 1", &root), json!(true));
+        assert_eq!(eval("!!a", &root), json!(true));
+    }
+
+    #[test]
+    fn values_keep_their_types() {
+        let root = json!({ "n": 1, "s": "1", "tags": ["x", "y"], "r": { "t": 4.5 } });
+        assert_eq!(eval("n == 1.0", &root), json!(true));
+        assert_eq!(eval("n == s", &root), json!(false));
+        assert_eq!(eval("n < s", &root), json!(false));
+        assert_eq!(eval("\"b\" > \"a\"", &root), json!(true));
+        assert_eq!(eval("s + n", &root), json!("11"));
+        assert_eq!(eval("s + missing", &root), Value::Null);
+        assert_eq!(eval("tags.1", &root), json!("y"));
+        assert_eq!(eval("r.t * 2", &root), json!(9.0));
+        assert_eq!(eval("7 / 2", &root), json!(3.5));
+        assert_eq!(eval("6 / 2", &root), json!(3));
+        assert_eq!(eval("\"x\" in tags && \"ell\" in \"hello\"", &root), json!(true));
+    }
+
+    #[test]
+    fn division_by_zero_is_null() {
+        let root = json!({ "zero": 0 });
+        assert_eq!(eval("1 / 0", &root), Value::Null);
+        assert_eq!(eval("1 % 0", &root), Value::Null);
+        assert_eq!(eval("1.5 / zero", &root), Value::Null);
+        assert_eq!(eval("1.5 % 0.0", &root), Value::Null);
+        assert!(!Expr::parse("1 / 0 > 0").unwrap().matches(&root));
+    }
+
+    #[test]
+    fn conditionals() {
+        let expr = "case when level == error then 3 when level == warn then 2 else 1 end";
+        assert_eq!(eval(expr, &json!({ "level": "warn" })), json!(2));
+        assert_eq!(eval(expr, &json!({})), json!(1));
+        assert_eq!(eval("case when false then 1 end", &json!({})), Value::Null);
+        assert_eq!(eval("if t >= 30 then \"hot\" else \"ok\"", &json!({ "t": 31 })), json!("hot"));
+    }
+
+    #[test]
+    fn bare_words_stand_for_themselves_when_compared() {
+        assert_eq!(eval("level == error", &json!({ "level": "error" })), json!(true));
+        assert_eq!(eval("level != error", &json!({ "level": "warn" })), json!(true));
+        assert_eq!(eval("level in (warn, error)", &json!({ "level": "warn" })), json!(true));
+        assert_eq!(eval("level in [warn, error]", &json!({ "level": "error" })), json!(true));
+
+        // A field of that name wins; elsewhere a missing field is null
+        assert_eq!(eval("level == other", &json!({ "level": "x", "other": "x" })), json!(true));
+        assert_eq!(eval("error", &json!({})), Value::Null);
+        assert_eq!(eval("level == error.code", &json!({ "level": "error" })), json!(false));
+        assert_eq!(eval("level == `error`", &json!({ "level": "error" })), json!(false));
+    }
+
+    #[test]
+    fn quoted_field_names() {
+        let root = json!({ "user-id": 7, "if": true, "order": { "item-count": 2 } });
+        assert_eq!(eval("`user-id` > 5", &root), json!(true));
+        assert_eq!(eval("`if` && `order.item-count` == 2", &root), json!(true));
+        assert_eq!(eval("`user id`", &root), Value::Null);
+        assert!(error("`user-id").contains("unterminated field name starting at column 1"));
+        assert!(error("a == ``").contains("empty field name at column 6"));
+    }
+
+    #[test]
+    fn hyphenated_names_must_be_quoted() {
+        let message = error("user-id>5");
+        assert!(message.contains("'user-id' at column 1 is not a field name"), "{message}");
+        assert!(message.contains("`user-id`"), "{message}");
+        assert!(error("a.b-c.d == 1").contains("'a.b-c.d'"));
+
+        let root = json!({ "hi": 5, "lo": 2 });
+        assert_eq!(eval("hi - lo", &root), json!(3));
+        assert_eq!(eval("hi -lo", &root), json!(3));
+        assert_eq!(eval("hi-1", &root), json!(4));
+    }
+
+    #[test]
+    fn errors_point_at_the_column() {
+        assert_eq!(error(""), "expression is empty");
+        assert_eq!(error("a == "), "expected a value at the end (column 6)");
+        assert_eq!(error("(a == 1"), "expected ')' at the end (column 8)");
+        assert_eq!(error("a == 1 b"), "unexpected 'b' at column 8");
+        assert_eq!(error("a == 1 && then"), "expected a value at column 11 ('then')");
+        assert_eq!(error("a ? b"), "unexpected '?' at column 3");
+        assert_eq!(error("\"open"), "unterminated string starting at column 1");
+        assert_eq!(error("if a then 1"), "expected 'else' at the end (column 12)");
+        assert_eq!(error("case else 1 end"), "expected 'when' at column 6 ('else')");
+        assert_eq!(error("[1, 2 3]"), "expected ',' or ']' at column 7 ('3')");
+    }
+
+    #[test]
+    fn nesting_is_capped() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&nested(MAX_DEPTH - 1), &json!({})), json!(1));
+        assert!(error(&nested(20_000)).contains("nested more than 100 levels deep"));
+        assert!(error(&"!".repeat(20_000)).contains("nested more than 100 levels deep"));
+        assert!(error(&"-".repeat(20_000)).contains("nested more than 100 levels deep"));
+        assert!(error(&vec!["1"; 20_000].join(" + ")).contains("nested more than 100 levels deep"));
+        assert!(error(&vec!["a"; 20_000].join(" || ")).contains("nested more than 100 levels deep"));
+        assert!(Expr::parse(&vec!["a == 1"; MAX_DEPTH].join(" || ")).is_ok());
+    }
+
+    #[test]
+    fn assignments_parse_fields_and_expressions() {
+        let parsed = assignments("total = price * qty, meta.tag = \"x\", `user-id` = id").unwrap();
+        let fields: Vec<_> = parsed.iter().map(|(field, _)| field.join(".")).collect();
+        assert_eq!(fields, vec!["total", "meta.tag", "user-id"]);
+        assert_eq!(parsed[0].1.eval(&json!({ "price": 2, "qty": 3 })), json!(6));
+
+        assert_eq!(assignments("").unwrap_err().to_string(), "expression is empty");
+        assert_eq!(assignments("1 = 2").unwrap_err().to_string(), "expected a field name at column 1 ('1')");
+        assert_eq!(assignments("a 2").unwrap_err().to_string(), "expected '=' after the field name at column 3 ('2')");
+        assert_eq!(assignments("a = 1,").unwrap_err().to_string(), "expected a field name at column 6 (',')");
+    }
+
+    #[test]
+    fn assign_creates_objects_on_the_path() {
+        let mut root = json!({ "a": 1 });
+        assert!(assign(&mut root, &["b".to_string(), "c".to_string()], json!(2)));
+        assert_eq!(root, json!({ "a": 1, "b": { "c": 2 } }));
+        assert!(!assign(&mut root, &["a".to_string(), "x".to_string()], json!(3)));
+    }
+}
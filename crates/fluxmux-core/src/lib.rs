@@ -3,4 +3,23 @@ pub mod traits;
 pub mod engine;
 pub mod middleware;
 pub mod pipe_actions;
-pub mod pipe_engine;
\ No newline at end of file
+pub mod pipe_engine;
+pub mod ack;
+pub mod dead_letter;
+pub mod shutdown;
+pub mod checkpoint;
+pub mod metrics;
+pub mod retry;
+pub mod rate_limit;
+pub mod dedup;
+pub mod reload;
+pub mod endpoints;
+pub mod pipeline;
+pub mod trace;
+pub mod lineage;
+pub mod event_time;
+pub mod window;
+pub mod lookup;
+pub mod stream_join;
+pub mod union;
+pub mod expr;
@@ -0,0 +1,240 @@
+//! Message identity and lineage.
+//!
+//! Every message gets an ID: sources assign stable ones (Kafka
+//! `topic:partition:offset`, `path:line` for files), the engines give a random
+//! UUID to messages that arrive without one, and messages derived from others
+//! (e.g. an aggregate) get a fresh ID plus the IDs of their parents.
+//!
+//! With a `Lineage` configured, the engines also stamp the selected lineage
+//! headers, which sinks carry along with the message: the Kafka sink as record
+//! headers, the Postgres sink as columns.
+
+use crate::message::Message;
+use crate::union::SOURCE_META;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const ID_HEADER: &str = "fluxmux-id";
+pub const PARENTS_HEADER: &str = "fluxmux-parent-ids";
+pub const SOURCE_HEADER: &str = "fluxmux-source";
+pub const INGEST_TIME_HEADER: &str = "fluxmux-ingest-time";
+pub const PIPELINE_HEADER: &str = "fluxmux-pipeline";
+
+/// Prefix shared by all lineage headers.
+pub const HEADER_PREFIX: &str = "fluxmux-";
+
+/// One piece of lineage that can be stamped as a header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineageHeader {
+    /// The message ID
+    Id,
+    /// Comma-separated IDs of the messages a derived message was built from
+    Parents,
+    /// URI of the source the message was read from
+    Source,
+    /// When the message was taken from the source (RFC 3339)
+    IngestTime,
+    /// Name of the pipeline that processed the message
+    Pipeline,
+}
+
+impl LineageHeader {
+    pub const ALL: [LineageHeader; 5] = [Self::Id, Self::Parents, Self::Source, Self::IngestTime, Self::Pipeline];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Parents => "parents",
+            Self::Source => "source",
+            Self::IngestTime => "ingest_time",
+            Self::Pipeline => "pipeline",
+        }
+    }
+
+    pub fn header(self) -> &'static str {
+        match self {
+            Self::Id => ID_HEADER,
+            Self::Parents => PARENTS_HEADER,
+            Self::Source => SOURCE_HEADER,
+            Self::IngestTime => INGEST_TIME_HEADER,
+            Self::Pipeline => PIPELINE_HEADER,
+        }
+    }
+}
+
+/// The lineage headers to stamp, e.g. `id,parents,source`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct LineageHeaders(Vec<LineageHeader>);
+
+impl LineageHeaders {
+    pub fn all() -> Self {
+        Self(LineageHeader::ALL.to_vec())
+    }
+
+    pub fn contains(&self, header: LineageHeader) -> bool {
+        self.0.contains(&header)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = LineageHeader> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+impl std::str::FromStr for LineageHeaders {
+    type Err = anyhow::Error;
+
+    /// `all`, or a comma-separated list of `id`, `parents`, `source`,
+    /// `ingest_time` and `pipeline`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "all" {
+            return Ok(Self::all());
+        }
+        let mut headers = Vec::new();
+        for name in s.split(',').map(str::trim) {
+            let header = LineageHeader::ALL
+                .into_iter()
+                .find(|h| h.name() == name.replace('-', "_"))
+                .ok_or_else(|| anyhow::anyhow!(
+                    "Unknown lineage header '{}', expected all or id, parents, source, ingest_time, pipeline",
+                    name
+                ))?;
+            if !headers.contains(&header) {
+                headers.push(header);
+            }
+        }
+        Ok(Self(headers))
+    }
+}
+
+impl TryFrom<String> for LineageHeaders {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::fmt::Display for LineageHeaders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<_> = self.0.iter().map(|h| h.name()).collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+impl From<LineageHeaders> for String {
+    fn from(headers: LineageHeaders) -> Self {
+        headers.to_string()
+    }
+}
+
+/// Random (version 4) UUID, for messages without a natural ID.
+pub fn new_id() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Which lineage headers a pipeline stamps, and the values it stamps.
+#[derive(Debug, Clone)]
+pub struct Lineage {
+    headers: LineageHeaders,
+    source: String,
+    pipeline: String,
+}
+
+impl Lineage {
+    pub fn new(headers: LineageHeaders, source: impl Into<String>, pipeline: impl Into<String>) -> Self {
+        Self { headers, source: source.into(), pipeline: pipeline.into() }
+    }
+
+    /// Stamp a message taken from the source: its source, ingest time and
+    /// pipeline. Messages derived from it inherit these.
+    pub fn receive(&self, msg: &mut Message) {
+        if self.headers.contains(LineageHeader::Source) {
+            // A union tags each message with the input it came from
+            let source = msg.meta.get(SOURCE_META).unwrap_or(&self.source).clone();
+            msg.headers.insert(SOURCE_HEADER.to_string(), source);
+        }
+        if self.headers.contains(LineageHeader::IngestTime) {
+            let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+            msg.headers.insert(INGEST_TIME_HEADER.to_string(), now);
+        }
+        if self.headers.contains(LineageHeader::Pipeline) {
+            msg.headers.insert(PIPELINE_HEADER.to_string(), self.pipeline.clone());
+        }
+    }
+
+    /// Stamp a message about to be written: its ID and parents, which stages
+    /// may have changed since it was received.
+    pub fn send(&self, msg: &mut Message) {
+        if self.headers.contains(LineageHeader::Id) {
+            if let Some(ref id) = msg.id {
+                msg.headers.insert(ID_HEADER.to_string(), id.clone());
+            }
+        }
+        if self.headers.contains(LineageHeader::Parents) && !msg.parents.is_empty() {
+            msg.headers.insert(PARENTS_HEADER.to_string(), msg.parents.join(","));
+        }
+    }
+}
+
+/// Provenance of the messages a derived message is built from.
+#[derive(Debug, Default)]
+pub struct Parents {
+    ids: Vec<String>,
+    /// Headers all parents agree on; `None` before the first parent
+    headers: Option<HashMap<String, String>>,
+    earliest_ingest: Option<String>,
+}
+
+impl Parents {
+    pub fn add(&mut self, msg: &Message) {
+        self.ids.extend(msg.id.clone());
+        match self.headers {
+            Some(ref mut common) => common.retain(|k, v| msg.headers.get(k) == Some(v)),
+            None => self.headers = Some(msg.headers.clone()),
+        }
+        // Fixed-width RFC 3339 timestamps in UTC compare in time order
+        if let Some(ingest) = msg.headers.get(INGEST_TIME_HEADER) {
+            if self.earliest_ingest.as_ref().is_none_or(|earliest| ingest < earliest) {
+                self.earliest_ingest = Some(ingest.clone());
+            }
+        }
+    }
+
+    /// Combine with the parents of another group, e.g. when sessions merge.
+    pub fn merge(&mut self, other: Parents) {
+        self.ids.extend(other.ids);
+        match (&mut self.headers, other.headers) {
+            (Some(common), Some(theirs)) => common.retain(|k, v| theirs.get(k) == Some(v)),
+            (None, theirs) => self.headers = theirs,
+            (Some(_), None) => {}
+        }
+        if let Some(ingest) = other.earliest_ingest {
+            if self.earliest_ingest.as_ref().is_none_or(|earliest| &ingest < earliest) {
+                self.earliest_ingest = Some(ingest);
+            }
+        }
+    }
+
+    /// Give `msg` a new ID, these parents, and the headers they share; the
+    /// ingest time is the earliest of theirs.
+    pub fn derive(&self, msg: &mut Message) {
+        msg.id = Some(new_id());
+        msg.parents = self.ids.clone();
+        let mut headers = self.headers.clone().unwrap_or_default();
+        headers.remove(ID_HEADER);
+        headers.remove(PARENTS_HEADER);
+        if let Some(ref ingest) = self.earliest_ingest {
+            headers.insert(INGEST_TIME_HEADER.to_string(), ingest.clone());
+        }
+        msg.headers.extend(headers);
+    }
+}
@@ -0,0 +1,140 @@
+//! Lookup tables for enriching messages with reference data.
+//!
+//! A table is a list of rows (JSON objects) indexed by one column. Rows come
+//! from a `TableLoader`, which the CLI backs with its file importers (CSV,
+//! JSON, Parquet, ...), so the table can be reloaded when its file changes.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Reads the rows of a table: a JSON array of objects.
+pub type TableLoader = Arc<dyn Fn() -> anyhow::Result<Value> + Send + Sync>;
+
+/// What happens to a message without a matching row.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinKind {
+    /// Drop the message
+    Inner,
+    /// Keep the message; selected columns are set to null
+    #[default]
+    Left,
+}
+
+impl std::str::FromStr for JoinKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inner" => Ok(Self::Inner),
+            "left" => Ok(Self::Left),
+            other => Err(anyhow::anyhow!("Unknown join kind '{}', expected inner or left", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for JoinKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Inner => write!(f, "inner"),
+            Self::Left => write!(f, "left"),
+        }
+    }
+}
+
+/// Rows indexed by the value of their key column.
+#[derive(Debug, Default)]
+pub struct LookupTable {
+    rows: HashMap<String, Map<String, Value>>,
+}
+
+impl LookupTable {
+    /// Index `rows` by `key`. Rows without the key are skipped; of rows with
+    /// the same key, the last one wins. Fails when no row has the key.
+    pub fn from_rows(rows: Value, key: &str) -> anyhow::Result<Self> {
+        let Value::Array(rows) = rows else {
+            anyhow::bail!("expected a list of rows");
+        };
+        let total = rows.len();
+        let mut table = HashMap::with_capacity(total);
+        for (i, row) in rows.into_iter().enumerate() {
+            let Value::Object(row) = row else {
+                anyhow::bail!("row {} is not an object", i);
+            };
+            if let Some(k) = row.get(key).and_then(key_string) {
+                table.insert(k, row);
+            }
+        }
+        if table.is_empty() && total > 0 {
+            anyhow::bail!("no row has the key column '{}'", key);
+        }
+        if table.len() < total {
+            tracing::warn!(rows = total, keys = table.len(), key, "Lookup table has rows without a key or with duplicate keys");
+        }
+        Ok(Self { rows: table })
+    }
+
+    /// The row whose key equals `key`; numbers match their string form, so
+    /// `7` finds the CSV row `"7"`.
+    pub fn get(&self, key: &Value) -> Option<&Map<String, Value>> {
+        self.rows.get(&key_string(key)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+pub(crate) fn key_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn rows_are_indexed_by_their_key() {
+        let rows = json!([
+            { "id": "1", "name": "ann" },
+            { "id": 2, "name": "bob" },
+            { "name": "no key" },
+            { "id": "1", "name": "ann again" },
+        ]);
+        let table = LookupTable::from_rows(rows, "id").unwrap();
+        assert_eq!(table.len(), 2);
+        // The last row with a key wins, and numbers match their string form
+        assert_eq!(table.get(&json!(1)).unwrap()["name"], "ann again");
+        assert_eq!(table.get(&json!("2")).unwrap()["name"], "bob");
+        assert!(table.get(&json!("3")).is_none());
+        assert!(table.get(&json!(null)).is_none());
+    }
+
+    #[test]
+    fn malformed_tables_are_rejected() {
+        assert!(LookupTable::from_rows(json!({ "id": 1 }), "id").is_err());
+        assert!(LookupTable::from_rows(json!([{ "id": 1 }, 2]), "id").is_err());
+        let error = LookupTable::from_rows(json!([{ "code": 1 }]), "id").unwrap_err();
+        assert!(error.to_string().contains("'id'"), "{error}");
+        assert!(LookupTable::from_rows(json!([]), "id").unwrap().is_empty());
+    }
+
+    #[test]
+    fn join_kinds_parse() {
+        assert_eq!("inner".parse::<JoinKind>().unwrap(), JoinKind::Inner);
+        assert_eq!(JoinKind::default().to_string(), "left");
+        assert!("outer".parse::<JoinKind>().is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use chrono::{DateTime, Utc};
+use crate::ack::Ack;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Format {
@@ -15,7 +16,9 @@ pub enum Format {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
-    pub id: Option<String>,
+    pub id: Option<String>,            // stable where the source has one, see `lineage`
+    #[serde(default)]
+    pub parents: Vec<String>,          // IDs of the messages this one was derived from
     pub key: Option<Vec<u8>>,
     pub payload: Vec<u8>,              // raw bytes
     pub format: Option<Format>,
@@ -23,4 +26,22 @@ pub struct Message {
     pub timestamp: DateTime<Utc>,
     pub headers: HashMap<String, String>,
     pub meta: HashMap<String, String>, // connector-specific metadata
+    #[serde(skip)]
+    pub ack: Option<Ack>,              // released once the message is fully handled
+}
+
+impl Message {
+    /// Arm the acknowledgement handle; done by the engine when it receives the message.
+    pub fn arm_ack(&self) {
+        if let Some(ref ack) = self.ack {
+            ack.arm();
+        }
+    }
+
+    /// Mark this message as failed so its source does not treat it as processed.
+    pub fn nack(&self) {
+        if let Some(ref ack) = self.ack {
+            ack.nack();
+        }
+    }
 }
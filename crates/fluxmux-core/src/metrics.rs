@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+pub const MESSAGES_RECEIVED: &str = "fluxmux_messages_received_total";
+pub const STAGE_IN: &str = "fluxmux_stage_messages_in_total";
+pub const STAGE_OUT: &str = "fluxmux_stage_messages_out_total";
+pub const STAGE_DROPPED: &str = "fluxmux_stage_messages_dropped_total";
+pub const SINK_MESSAGES: &str = "fluxmux_sink_messages_total";
+pub const SINK_SEND_SECONDS: &str = "fluxmux_sink_send_duration_seconds";
+pub const SINK_ERRORS: &str = "fluxmux_sink_errors_total";
+pub const SINK_RETRIES: &str = "fluxmux_sink_retries_total";
+pub const CIRCUIT_OPEN: &str = "fluxmux_sink_circuit_open";
+pub const CHANNEL_DEPTH: &str = "fluxmux_channel_depth";
+pub const KAFKA_CONSUMER_LAG: &str = "fluxmux_kafka_consumer_lag";
+
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+// Every metric family that can be exported, in output order
+const FAMILIES: &[(&str, Kind, &str)] = &[
+    (MESSAGES_RECEIVED, Kind::Counter, "Messages received from the source"),
+    (STAGE_IN, Kind::Counter, "Messages handed to a middleware or pipe action"),
+    (STAGE_OUT, Kind::Counter, "Messages emitted by a middleware or pipe action"),
+    (STAGE_DROPPED, Kind::Counter, "Messages a stage discarded (filtered, deduplicated, rejected)"),
+    (SINK_MESSAGES, Kind::Counter, "Messages written by a sink"),
+    (SINK_SEND_SECONDS, Kind::Histogram, "Time spent in a single sink send attempt"),
+    (SINK_ERRORS, Kind::Counter, "Failed sink send attempts"),
+    (SINK_RETRIES, Kind::Counter, "Sink sends retried by the engine"),
+    (CIRCUIT_OPEN, Kind::Gauge, "1 while the sink's circuit breaker holds delivery"),
+    (CHANNEL_DEPTH, Kind::Gauge, "Messages waiting between the source and the engine"),
+    (KAFKA_CONSUMER_LAG, Kind::Gauge, "High watermark minus consumer position per partition"),
+];
+
+const BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+type SeriesKey = (&'static str, String);
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<SeriesKey, u64>,
+    gauges: BTreeMap<SeriesKey, f64>,
+    histograms: BTreeMap<SeriesKey, Histogram>,
+}
+
+/// In-process metrics registry rendered in the Prometheus text format.
+/// Cheap to clone; all clones share the same series.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn inc(&self, name: &'static str, labels: &[(&str, &str)], by: u64) {
+        let key = (name, format_labels(labels));
+        *self.registry.lock().unwrap().counters.entry(key).or_default() += by;
+    }
+
+    pub fn set_gauge(&self, name: &'static str, labels: &[(&str, &str)], value: f64) {
+        let key = (name, format_labels(labels));
+        self.registry.lock().unwrap().gauges.insert(key, value);
+    }
+
+    pub fn observe(&self, name: &'static str, labels: &[(&str, &str)], value: f64) {
+        let key = (name, format_labels(labels));
+        let mut registry = self.registry.lock().unwrap();
+        let histogram = registry.histograms.entry(key).or_default();
+        if histogram.buckets.is_empty() {
+            histogram.buckets = vec![0; BUCKETS.len()];
+        }
+        for (i, bound) in BUCKETS.iter().enumerate() {
+            if value <= *bound {
+                histogram.buckets[i] += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+
+    /// Sum of a counter over all its label sets.
+    pub fn total(&self, name: &'static str) -> u64 {
+        self.registry.lock().unwrap().counters.range(family_range(name)).map(|(_, v)| v).sum()
+    }
+
+    /// Record one message entering a stage and what came out of it.
+    pub fn record_stage(&self, stage: &str, index: usize, emitted: usize, buffers: bool) {
+        let index = index.to_string();
+        let labels = [("stage", stage), ("index", index.as_str())];
+        self.inc(STAGE_IN, &labels, 1);
+        self.inc(STAGE_OUT, &labels, emitted as u64);
+        if emitted == 0 && !buffers {
+            self.inc(STAGE_DROPPED, &labels, 1);
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+        for (name, kind, help) in FAMILIES {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            match kind {
+                Kind::Counter => {
+                    let _ = writeln!(out, "# TYPE {} counter", name);
+                    for ((_, labels), value) in registry.counters.range(family_range(name)) {
+                        let _ = writeln!(out, "{}{} {}", name, wrap(labels), value);
+                    }
+                }
+                Kind::Gauge => {
+                    let _ = writeln!(out, "# TYPE {} gauge", name);
+                    for ((_, labels), value) in registry.gauges.range(family_range(name)) {
+                        let _ = writeln!(out, "{}{} {}", name, wrap(labels), value);
+                    }
+                }
+                Kind::Histogram => {
+                    let _ = writeln!(out, "# TYPE {} histogram", name);
+                    for ((_, labels), h) in registry.histograms.range(family_range(name)) {
+                        for (bound, count) in BUCKETS.iter().zip(&h.buckets) {
+                            let le = format!("le=\"{}\"", bound);
+                            let _ = writeln!(out, "{}_bucket{} {}", name, wrap(&join(labels, &le)), count);
+                        }
+                        let _ = writeln!(out, "{}_bucket{} {}", name, wrap(&join(labels, "le=\"+Inf\"")), h.count);
+                        let _ = writeln!(out, "{}_sum{} {}", name, wrap(labels), h.sum);
+                        let _ = writeln!(out, "{}_count{} {}", name, wrap(labels), h.count);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Serve `render()` over HTTP on `addr` until the process exits.
+    pub async fn serve(&self, addr: SocketAddr) -> anyhow::Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(addr).await?;
+        let metrics = self.clone();
+        Ok(tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "Metrics endpoint accept error");
+                        continue;
+                    }
+                };
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    // Only the request line matters; read the head and answer
+                    let mut buf = [0u8; 4096];
+                    let n = stream.read(&mut buf).await.unwrap_or(0);
+                    let request = String::from_utf8_lossy(&buf[..n]);
+                    let path = request.split_whitespace().nth(1).unwrap_or("/");
+                    let response = if path == "/metrics" || path == "/" {
+                        let body = metrics.render();
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            body.len(),
+                            body
+                        )
+                    } else {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+                    };
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        }))
+    }
+}
+
+fn family_range(name: &'static str) -> std::ops::RangeInclusive<SeriesKey> {
+    (name, String::new())..=(name, "\u{10FFFF}".to_string())
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(k, v)| {
+            let escaped = v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", k, escaped)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn join(labels: &str, extra: &str) -> String {
+    if labels.is_empty() {
+        extra.to_string()
+    } else {
+        format!("{},{}", labels, extra)
+    }
+}
+
+fn wrap(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
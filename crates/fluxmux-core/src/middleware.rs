@@ -1,53 +1,218 @@
 use crate::message::Message;
+use crate::metrics::{Metrics, STAGE_OUT};
+use crate::trace;
 use async_trait::async_trait;
+use tracing::Instrument;
 
 #[async_trait]
 pub trait Middleware: Send + Sync {
-    async fn handle(&mut self, msg: Message) -> Option<Message>;
+    /// Returns every message to pass on: none to drop or hold the input,
+    /// several to split or re-emit it.
+    async fn handle(&mut self, msg: Message) -> Vec<Message>;
+    /// Stage label used in metrics.
+    fn name(&self) -> &'static str {
+        "middleware"
+    }
+    /// True for stages that hold messages back (e.g. batching), so swallowing
+    /// a message is not reported as a drop.
+    fn buffers(&self) -> bool {
+        false
+    }
+    /// Called periodically by the engine, even while no messages arrive, so
+    /// time-based stages can release buffered state.
+    async fn tick(&mut self) -> Vec<Message> {
+        vec![]
+    }
+    /// Called once when the stream ends or shuts down, to release buffered state.
+    async fn finalize(&mut self) -> Vec<Message> {
+        vec![]
+    }
+}
+
+#[async_trait]
+impl<M: Middleware + ?Sized> Middleware for Box<M> {
+    async fn handle(&mut self, msg: Message) -> Vec<Message> {
+        (**self).handle(msg).await
+    }
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+    fn buffers(&self) -> bool {
+        (**self).buffers()
+    }
+    async fn tick(&mut self) -> Vec<Message> {
+        (**self).tick().await
+    }
+    async fn finalize(&mut self) -> Vec<Message> {
+        (**self).finalize().await
+    }
 }
 
+#[derive(Default)]
 pub struct MiddlewareChain {
     middlewares: Vec<Box<dyn Middleware>>,
+    settings: Vec<Option<String>>, // per stage, see `add_stateful`
+    metrics: Option<Metrics>,
 }
 
 impl MiddlewareChain {
     pub fn new() -> Self {
-        Self { middlewares: Vec::new() }
+        Self { middlewares: Vec::new(), settings: Vec::new(), metrics: None }
+    }
+    pub fn set_metrics(&mut self, metrics: Option<Metrics>) {
+        self.metrics = metrics;
     }
     pub fn add(&mut self, mw: Box<dyn Middleware>) {
         self.middlewares.push(mw);
+        self.settings.push(None);
+    }
+    /// Add a stage whose state (seen keys, token buckets, held messages)
+    /// outlives a reload as long as the stage's `settings` do not change;
+    /// see `replace`.
+    pub fn add_stateful(&mut self, mw: Box<dyn Middleware>, settings: impl Into<String>) {
+        self.middlewares.push(mw);
+        self.settings.push(Some(settings.into()));
+    }
+    /// Switch to the stages of `next`, keeping each running stateful stage
+    /// that `next` has with the same name and settings in place of its new
+    /// one. The other running stages are finalized; returns what they
+    /// release, after it passed the running stages that follow them.
+    pub async fn replace(&mut self, mut next: MiddlewareChain) -> Vec<Message> {
+        // Identical stages pair up in order, each new stage taking at most one
+        let mut taken = vec![false; next.middlewares.len()];
+        let kept: Vec<Option<usize>> = (0..self.middlewares.len())
+            .map(|i| {
+                let settings = self.settings[i].as_ref()?;
+                let name = self.middlewares[i].name();
+                let j = (0..next.middlewares.len()).find(|&j| {
+                    !taken[j] && next.middlewares[j].name() == name && next.settings[j].as_ref() == Some(settings)
+                })?;
+                taken[j] = true;
+                Some(j)
+            })
+            .collect();
+        let mut out = Vec::new();
+        for (i, kept) in kept.iter().enumerate() {
+            if kept.is_none() {
+                out.extend(self.release_from(i, true).await);
+            }
+        }
+        // Running stages move over in order, so each lands in its own slot
+        for (mw, kept) in self.middlewares.drain(..).zip(kept) {
+            if let Some(j) = kept {
+                next.middlewares[j] = mw;
+            }
+        }
+        self.middlewares = next.middlewares;
+        self.settings = next.settings;
+        out
+    }
+    /// Middleware names in the order messages pass through them.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.middlewares.iter().map(|mw| mw.name()).collect()
+    }
+    pub async fn process(&mut self, msg: Message) -> Vec<Message> {
+        Self::process_from(&mut self.middlewares, 0, &self.metrics, vec![msg]).await
+    }
+    /// Tick every middleware in order; whatever one releases still passes
+    /// through the middlewares after it.
+    pub async fn tick(&mut self) -> Vec<Message> {
+        self.release(false).await
+    }
+    /// Finalize every middleware in order, like `tick`.
+    pub async fn finalize(&mut self) -> Vec<Message> {
+        self.release(true).await
+    }
+    async fn release(&mut self, finalize: bool) -> Vec<Message> {
+        let mut out = Vec::new();
+        for i in 0..self.middlewares.len() {
+            out.extend(self.release_from(i, finalize).await);
+        }
+        out
     }
-    pub async fn process(&mut self, mut msg: Message) -> Option<Message> {
-        for mw in self.middlewares.iter_mut() {
-            match mw.handle(msg).await {
-                Some(m) => msg = m,
-                None => return None,
+    // Tick or finalize stage `i`, passing what it releases through the stages after it
+    async fn release_from(&mut self, i: usize, finalize: bool) -> Vec<Message> {
+        let released = if finalize {
+            self.middlewares[i].finalize().await
+        } else {
+            self.middlewares[i].tick().await
+        };
+        if released.is_empty() {
+            return released;
+        }
+        if let Some(ref metrics) = self.metrics {
+            let index = i.to_string();
+            let labels = [("stage", self.middlewares[i].name()), ("index", index.as_str())];
+            metrics.inc(STAGE_OUT, &labels, released.len() as u64);
+        }
+        Self::process_from(&mut self.middlewares[i + 1..], i + 1, &self.metrics, released).await
+    }
+    async fn process_from(
+        middlewares: &mut [Box<dyn Middleware>],
+        offset: usize,
+        metrics: &Option<Metrics>,
+        mut messages: Vec<Message>,
+    ) -> Vec<Message> {
+        for (i, mw) in middlewares.iter_mut().enumerate() {
+            let mut next = Vec::new();
+            for msg in messages {
+                let span = tracing::debug_span!("stage", stage = mw.name(), index = offset + i);
+                trace::follow(&span, &msg);
+                let out = mw.handle(msg).instrument(span).await;
+                if let Some(ref metrics) = metrics {
+                    metrics.record_stage(mw.name(), offset + i, out.len(), mw.buffers());
+                }
+                next.extend(out);
             }
+            if next.is_empty() {
+                return next;
+            }
+            messages = next;
         }
-        Some(msg)
+        messages
     }
 }
 
 // Deduplicator Middleware
+use crate::dedup::{DedupKey, SeenSet};
 use std::collections::HashSet;
+/// Drops messages whose key was seen recently. Messages without a key (see
+/// `DedupKey::hash`) always pass.
 pub struct Deduplicator {
-    seen: HashSet<Vec<u8>>,
+    key: DedupKey,
+    seen: SeenSet,
 }
 impl Deduplicator {
     pub fn new() -> Self {
-        Self { seen: HashSet::new() }
+        Self::with_key(DedupKey::default())
+    }
+    pub fn with_key(key: DedupKey) -> Self {
+        Self { key, seen: SeenSet::new(None, None) }
+    }
+    /// Bound the remembered keys by count (least recently seen go first)
+    /// and/or by time since a key was last seen.
+    pub fn bounded(mut self, capacity: Option<usize>, ttl: Option<Duration>) -> Self {
+        self.seen = SeenSet::new(capacity, ttl);
+        self
+    }
+}
+impl Default for Deduplicator {
+    fn default() -> Self {
+        Self::new()
     }
 }
 #[async_trait]
 impl Middleware for Deduplicator {
-    async fn handle(&mut self, msg: Message) -> Option<Message> {
-        if let Some(ref key) = msg.key {
-            if self.seen.contains(key) {
-                return None;
+    fn name(&self) -> &'static str {
+        "deduplicator"
+    }
+    async fn handle(&mut self, msg: Message) -> Vec<Message> {
+        if let Some(hash) = self.key.hash(&msg) {
+            if self.seen.insert(hash) {
+                return vec![];
             }
-            self.seen.insert(key.clone());
         }
-        Some(msg)
+        vec![msg]
     }
 }
 
@@ -67,18 +232,143 @@ impl Throttler {
 }
 #[async_trait]
 impl Middleware for Throttler {
-    async fn handle(&mut self, msg: Message) -> Option<Message> {
+    fn name(&self) -> &'static str {
+        "throttler"
+    }
+    async fn handle(&mut self, msg: Message) -> Vec<Message> {
         let now = Instant::now();
         let elapsed = now.duration_since(self.last_sent);
         if elapsed < self.min_interval {
             sleep(self.min_interval - elapsed).await;
         }
         self.last_sent = Instant::now();
-        Some(msg)
+        vec![msg]
     }
 }
 
-// Batcher Middleware with timeout support
+// RateLimit Middleware: token buckets with bursts, optionally per key or field.
+// Over-limit messages are held and released on tick (delay), discarded (drop)
+// or handed to a side sink (route), so one busy key never stalls the chain.
+use crate::rate_limit::{OverLimit, RateLimiter};
+use std::collections::{HashMap, VecDeque};
+
+/// Held messages beyond which `RateLimit` waits for tokens inline, slowing the source.
+const MAX_HELD: usize = 10_000;
+
+pub struct RateLimit {
+    limiter: RateLimiter,
+    mode: OverLimit,
+    route: Option<DeadLetterSender>,
+    stage: &'static str,
+    held: VecDeque<(String, Message)>,
+    held_per_key: HashMap<String, usize>,
+}
+impl RateLimit {
+    pub fn new(limiter: RateLimiter, mode: OverLimit) -> Self {
+        Self {
+            limiter,
+            mode,
+            route: None,
+            stage: "rate_limit",
+            held: VecDeque::new(),
+            held_per_key: HashMap::new(),
+        }
+    }
+
+    /// Stage named in the dead-letter envelope of routed messages
+    /// (default `rate_limit`).
+    pub fn with_stage(mut self, stage: &'static str) -> Self {
+        self.stage = stage;
+        self
+    }
+
+    /// Destination for over-limit messages in `route` mode.
+    pub fn with_route(mut self, route: DeadLetterSender) -> Self {
+        self.route = Some(route);
+        self
+    }
+
+    /// Whether over-limit messages are waiting for a token.
+    pub fn holding(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    // Release held messages whose bucket has a token again, oldest first per key
+    fn release(&mut self) -> Vec<Message> {
+        let mut out = Vec::new();
+        let mut blocked = HashSet::new();
+        let mut kept = VecDeque::new();
+        while let Some((key, msg)) = self.held.pop_front() {
+            if !blocked.contains(&key) && self.limiter.try_acquire(&key).is_ok() {
+                self.unhold(&key);
+                out.push(msg);
+            } else {
+                blocked.insert(key.clone());
+                kept.push_back((key, msg));
+            }
+        }
+        self.held = kept;
+        out
+    }
+
+    fn unhold(&mut self, key: &str) {
+        if let Some(count) = self.held_per_key.get_mut(key) {
+            *count -= 1;
+            if *count == 0 {
+                self.held_per_key.remove(key);
+            }
+        }
+    }
+}
+#[async_trait]
+impl Middleware for RateLimit {
+    fn name(&self) -> &'static str {
+        "rate_limit"
+    }
+    fn buffers(&self) -> bool {
+        self.mode == OverLimit::Delay
+    }
+    async fn handle(&mut self, msg: Message) -> Vec<Message> {
+        let key = self.limiter.key_for(&msg);
+        // Messages queue behind earlier held ones with the same key to keep their order
+        if !self.held_per_key.contains_key(&key) && self.limiter.try_acquire(&key).is_ok() {
+            return vec![msg];
+        }
+        match self.mode {
+            OverLimit::Drop => vec![],
+            OverLimit::Route => {
+                match self.route {
+                    Some(ref route) => route.send(msg, self.stage, "rate limit exceeded", 1),
+                    None => tracing::warn!("Rate limit exceeded and no route configured, dropping message"),
+                }
+                vec![]
+            }
+            OverLimit::Delay => {
+                *self.held_per_key.entry(key.clone()).or_default() += 1;
+                self.held.push_back((key, msg));
+                let mut out = Vec::new();
+                while self.held.len() > MAX_HELD {
+                    sleep(Duration::from_secs_f64(1.0 / self.limiter.rate()).min(Duration::from_millis(100))).await;
+                    out.extend(self.release());
+                }
+                out
+            }
+        }
+    }
+
+    async fn tick(&mut self) -> Vec<Message> {
+        self.release()
+    }
+
+    async fn finalize(&mut self) -> Vec<Message> {
+        // Whatever is still held goes out when the stream ends
+        self.held_per_key.clear();
+        self.held.drain(..).map(|(_, msg)| msg).collect()
+    }
+}
+
+// Batcher Middleware with timeout support; a released batch is handed to
+// the sink's `send_batch` as-is
 pub struct Batcher {
     batch: Vec<Message>,
     batch_size: usize,
@@ -108,72 +398,49 @@ impl Batcher {
 }
 #[async_trait]
 impl Middleware for Batcher {
-    async fn handle(&mut self, msg: Message) -> Option<Message> {
+    fn name(&self) -> &'static str {
+        "batcher"
+    }
+    fn buffers(&self) -> bool {
+        true
+    }
+    async fn handle(&mut self, msg: Message) -> Vec<Message> {
+        // The timeout runs from the first message of the batch
+        if self.batch.is_empty() {
+            self.last_flush = Instant::now();
+        }
         self.batch.push(msg);
         
         if self.should_flush() {
-            // Combine all messages in batch into a single message
-            let messages = self.get_batch();
-            if messages.is_empty() {
-                return None;
-            }
-            
-            // Create a combined message with array payload
-            let combined_payload: Vec<serde_json::Value> = messages
-                .iter()
-                .filter_map(|m| m.parsed.clone())
-                .collect();
-            
-            let payload_json = serde_json::to_vec(&combined_payload).ok()?;
-            
-            Some(Message {
-                id: None,
-                key: None,
-                payload: payload_json.clone(),
-                format: Some(crate::message::Format::Json),
-                parsed: serde_json::from_slice(&payload_json).ok(),
-                timestamp: chrono::Utc::now(),
-                headers: Default::default(),
-                meta: Default::default(),
-            })
+            self.get_batch()
         } else {
-            None
+            vec![]
         }
     }
-}
 
-// RetryHandler Middleware
-pub struct RetryHandler {
-    max_retries: u32,
-    retry_delay: Duration,
-}
-impl RetryHandler {
-    pub fn new(max_retries: u32, delay_ms: u64) -> Self {
-        Self {
-            max_retries,
-            retry_delay: Duration::from_millis(delay_ms),
+    async fn tick(&mut self) -> Vec<Message> {
+        // Emit a partial batch once it times out, even with no new traffic
+        if self.should_flush() {
+            self.get_batch()
+        } else {
+            vec![]
         }
     }
-}
-#[async_trait]
-impl Middleware for RetryHandler {
-    async fn handle(&mut self, msg: Message) -> Option<Message> {
-        // RetryHandler is a pass-through middleware
-        // Actual retry logic should be in the sink layer
-        // This marks the message with retry metadata
-        let mut msg = msg;
-        msg.meta.insert("max_retries".to_string(), self.max_retries.to_string());
-        msg.meta.insert("retry_delay_ms".to_string(), self.retry_delay.as_millis().to_string());
-        Some(msg)
+
+    async fn finalize(&mut self) -> Vec<Message> {
+        // Emit the last partial batch
+        self.get_batch()
     }
 }
 
 // SchemaValidator Middleware
 use std::path::PathBuf;
 use std::fs;
+use crate::dead_letter::DeadLetterSender;
 
 pub struct SchemaValidator {
     schema: Option<serde_json::Value>,
+    dead_letter: Option<DeadLetterSender>,
 }
 impl SchemaValidator {
     pub fn new(schema_path: Option<PathBuf>) -> Self {
@@ -181,7 +448,31 @@ impl SchemaValidator {
             fs::read_to_string(path).ok()
                 .and_then(|content| serde_json::from_str(&content).ok())
         });
-        Self { schema }
+        Self { schema, dead_letter: None }
+    }
+
+    /// Like `new`, but fails when the schema cannot be read or is malformed
+    /// instead of passing every message.
+    pub fn load(schema_path: PathBuf) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(&schema_path)
+            .map_err(|e| anyhow::anyhow!("Cannot read schema {}: {}", schema_path.display(), e))?;
+        let schema: serde_json::Value = serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid schema {}: {}", schema_path.display(), e))?;
+        let required_ok = match schema.get("required") {
+            None => true,
+            Some(serde_json::Value::Array(fields)) => fields.iter().all(|f| f.is_string()),
+            Some(_) => false,
+        };
+        if !schema.is_object() || !required_ok {
+            anyhow::bail!("Invalid schema {}: expected an object whose `required` is a list of field names", schema_path.display());
+        }
+        Ok(Self { schema: Some(schema), dead_letter: None })
+    }
+
+    /// Route rejected messages to a dead-letter queue instead of dropping them.
+    pub fn with_dead_letter(mut self, dead_letter: DeadLetterSender) -> Self {
+        self.dead_letter = Some(dead_letter);
+        self
     }
     
     fn validate_against_schema(&self, value: &serde_json::Value) -> Result<(), String> {
@@ -207,20 +498,211 @@ impl SchemaValidator {
 }
 #[async_trait]
 impl Middleware for SchemaValidator {
-    async fn handle(&mut self, msg: Message) -> Option<Message> {
+    fn name(&self) -> &'static str {
+        "schema_validator"
+    }
+    async fn handle(&mut self, msg: Message) -> Vec<Message> {
         if let Some(ref parsed) = msg.parsed {
             match self.validate_against_schema(parsed) {
-                Ok(_) => Some(msg),
+                Ok(_) => vec![msg],
                 Err(err) => {
-                    eprintln!("Schema validation failed: {} | Message: {}", 
-                        err, 
-                        serde_json::to_string(parsed).unwrap_or_else(|_| "unable to serialize".to_string())
-                    );
-                    None
+                    tracing::warn!(error = %err, payload = %parsed, "Schema validation failed");
+                    if let Some(ref dlq) = self.dead_letter {
+                        dlq.send(msg, "schema_validator", err, 1);
+                    }
+                    vec![]
                 }
             }
         } else {
-            Some(msg)
+            vec![msg]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(payload: &str) -> Message {
+        Message {
+            id: None,
+            parents: Vec::new(),
+            key: None,
+            payload: payload.as_bytes().to_vec(),
+            format: None,
+            parsed: serde_json::from_str(payload).ok(),
+            timestamp: chrono::Utc::now(),
+            headers: Default::default(),
+            meta: Default::default(),
+            ack: None,
+        }
+    }
+
+    fn chain(batch_size: usize) -> MiddlewareChain {
+        let mut chain = MiddlewareChain::new();
+        chain.add_stateful(Box::new(Deduplicator::new()), "dedup");
+        chain.add_stateful(Box::new(Batcher::new(batch_size, 60_000)), batch_size.to_string());
+        chain
+    }
+
+    #[tokio::test]
+    async fn replace_keeps_unchanged_stages() {
+        let mut running = chain(10);
+        assert!(running.process(message(r#"{"a":1}"#)).await.is_empty());
+
+        // Same settings: the seen keys and the held batch carry over
+        assert!(running.replace(chain(10)).await.is_empty());
+        assert!(running.process(message(r#"{"a":1}"#)).await.is_empty());
+        assert!(running.process(message(r#"{"a":2}"#)).await.is_empty());
+        assert_eq!(running.finalize().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn replace_releases_changed_stages() {
+        let mut running = chain(10);
+        running.process(message(r#"{"a":1}"#)).await;
+
+        // The batcher changed: its batch is released, the dedup state stays
+        assert_eq!(running.replace(chain(5)).await.len(), 1);
+        assert!(running.process(message(r#"{"a":1}"#)).await.is_empty());
+        assert!(running.finalize().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn replace_passes_released_messages_through_later_stages() {
+        let mut running = MiddlewareChain::new();
+        running.add(Box::new(Batcher::new(10, 60_000)));
+        running.add_stateful(Box::new(Batcher::new(3, 60_000)), "3");
+        running.process(message(r#"{"a":1}"#)).await;
+
+        // The first batcher is rebuilt; its message joins the kept second batch
+        let mut next = MiddlewareChain::new();
+        next.add(Box::new(Batcher::new(10, 60_000)));
+        next.add_stateful(Box::new(Batcher::new(3, 60_000)), "3");
+        assert!(running.replace(next).await.is_empty());
+        assert_eq!(running.finalize().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn replace_keeps_identical_stages_apart() {
+        // Each batcher ends up holding a different message
+        let chain = || {
+            let mut chain = MiddlewareChain::new();
+            chain.add_stateful(Box::new(Batcher::new(2, 60_000)), "2");
+            chain.add(Box::new(Deduplicator::new()));
+            chain.add_stateful(Box::new(Batcher::new(2, 60_000)), "2");
+            chain
+        };
+        let mut running = chain();
+        running.process(message(r#"{"a":1}"#)).await;
+        running.process(message(r#"{"a":1}"#)).await;
+        running.process(message(r#"{"a":2}"#)).await;
+
+        assert!(running.replace(chain()).await.is_empty());
+        let mut released: Vec<Vec<u8>> = running.finalize().await.into_iter().map(|m| m.payload).collect();
+        released.sort();
+        assert_eq!(released, vec![br#"{"a":1}"#.to_vec(), br#"{"a":2}"#.to_vec()]);
+    }
+
+    fn keyed(key: &str, n: u32) -> Message {
+        let mut msg = message(&format!(r#"{{"n":{}}}"#, n));
+        msg.key = Some(key.as_bytes().to_vec());
+        msg
+    }
+
+    fn numbers(messages: &[Message]) -> Vec<u64> {
+        messages.iter().map(|m| m.parsed.as_ref().unwrap()["n"].as_u64().unwrap()).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_delays_over_limit_messages() {
+        let mut limit = RateLimit::new(RateLimiter::new(10.0, 2), OverLimit::Delay);
+        assert_eq!(limit.handle(keyed("a", 1)).await.len(), 1);
+        assert_eq!(limit.handle(keyed("a", 2)).await.len(), 1);
+        assert!(limit.handle(keyed("a", 3)).await.is_empty());
+        assert!(limit.holding());
+        assert!(limit.tick().await.is_empty());
+
+        // A token is back, but a later message still queues behind the held one
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert!(limit.handle(keyed("a", 4)).await.is_empty());
+        assert_eq!(numbers(&limit.tick().await), vec![3]);
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert_eq!(numbers(&limit.tick().await), vec![4]);
+        assert!(!limit.holding());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_holds_keys_apart() {
+        let mut limit = RateLimit::new(RateLimiter::new(1.0, 1).per_key(), OverLimit::Delay);
+        assert_eq!(limit.handle(keyed("a", 1)).await.len(), 1);
+        assert!(limit.handle(keyed("a", 2)).await.is_empty());
+        assert_eq!(limit.handle(keyed("b", 3)).await.len(), 1);
+
+        // Whatever is still held goes out at the end
+        assert_eq!(numbers(&limit.finalize().await), vec![2]);
+        assert!(!limit.holding());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_drops_over_limit_messages() {
+        let mut limit = RateLimit::new(RateLimiter::new(1.0, 1), OverLimit::Drop);
+        assert!(!limit.buffers());
+        assert_eq!(limit.handle(keyed("a", 1)).await.len(), 1);
+        assert!(limit.handle(keyed("a", 2)).await.is_empty());
+        assert!(!limit.holding());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(limit.tick().await.is_empty());
+        assert_eq!(limit.handle(keyed("a", 3)).await.len(), 1);
+    }
+
+    /// Keeps whatever it is sent.
+    struct Collect(std::sync::Arc<std::sync::Mutex<Vec<Message>>>);
+
+    #[async_trait]
+    impl crate::traits::Sink for Collect {
+        async fn send(&mut self, msg: Message) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(msg);
+            Ok(())
         }
+        async fn flush(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_routes_over_limit_messages() {
+        let routed = std::sync::Arc::default();
+        let queue = crate::dead_letter::DeadLetterQueue::spawn(Box::new(Collect(std::sync::Arc::clone(&routed))));
+        let mut limit = RateLimit::new(RateLimiter::new(1.0, 1), OverLimit::Route)
+            .with_route(queue.sender())
+            .with_stage("limit");
+        assert_eq!(limit.handle(keyed("a", 1)).await.len(), 1);
+        assert!(limit.handle(keyed("a", 2)).await.is_empty());
+        assert!(!limit.holding());
+        drop(limit);
+        queue.close().await.unwrap();
+
+        let routed = routed.lock().unwrap();
+        assert_eq!(routed.len(), 1);
+        let envelope = routed[0].parsed.as_ref().unwrap();
+        assert_eq!(envelope["stage"], "limit");
+        assert_eq!(envelope["reason"], "rate limit exceeded");
+        assert_eq!(envelope["payload"]["n"], 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_waits_inline_once_too_much_is_held() {
+        let mut limit = RateLimit::new(RateLimiter::new(1000.0, 1), OverLimit::Delay);
+        assert_eq!(limit.handle(keyed("a", 0)).await.len(), 1);
+        for n in 1..=MAX_HELD as u32 {
+            assert!(limit.handle(keyed("a", n)).await.is_empty());
+        }
+
+        // One more is over the cap: the stage sleeps until the oldest can go
+        let start = Instant::now();
+        assert_eq!(numbers(&limit.handle(keyed("a", MAX_HELD as u32 + 1)).await), vec![1]);
+        assert_eq!(start.elapsed(), Duration::from_millis(1));
+        assert_eq!(limit.finalize().await.len(), MAX_HELD);
     }
 }
@@ -1,78 +1,99 @@
 use async_trait::async_trait;
+use crate::ack::Ack;
+use crate::dead_letter::DeadLetterSender;
+use crate::event_time::{LatePolicy, Watermark};
+use crate::expr::Expr;
+use crate::lineage::Parents;
+use crate::lookup::{JoinKind, LookupTable, TableLoader};
+use crate::window::{Bounds, Window, WindowKind};
 use crate::message::{Message, Format};
+use crate::middleware::{Middleware, RateLimit};
+use crate::rate_limit::{OverLimit, RateLimiter};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
 
 /// Trait for pipeline actions that transform messages
 #[async_trait]
 pub trait PipeAction: Send + Sync {
     async fn execute(&mut self, msg: Message) -> anyhow::Result<Vec<Message>>;
+    /// Stage label used in metrics.
+    fn name(&self) -> &'static str {
+        "action"
+    }
+    /// True for actions that hold messages until `finalize` (e.g. aggregation),
+    /// so an empty result is not reported as a drop.
+    fn buffers(&self) -> bool {
+        false
+    }
+    /// Indices of the pipeline's sinks this action routes messages to; those
+    /// sinks only receive messages routed to them (see `ROUTE_META`).
+    fn routes(&self) -> Vec<usize> {
+        vec![]
+    }
+    /// True while the action holds messages that `tick` will release in
+    /// time (e.g. over a rate limit); `run_pipe` keeps ticking after the
+    /// source ends until none do, rather than finalizing them at once.
+    fn pending(&self) -> bool {
+        false
+    }
+    /// Called periodically by `run_pipe`, even while no messages arrive, so
+    /// time-based actions can release buffered state.
+    async fn tick(&mut self) -> anyhow::Result<Vec<Message>> {
+        Ok(vec![])
+    }
     async fn finalize(&mut self) -> anyhow::Result<Vec<Message>> {
         Ok(vec![])
     }
 }
 
-/// Filter action - keeps messages matching expression
+#[async_trait]
+impl<A: PipeAction + ?Sized> PipeAction for Box<A> {
+    async fn execute(&mut self, msg: Message) -> anyhow::Result<Vec<Message>> {
+        (**self).execute(msg).await
+    }
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+    fn buffers(&self) -> bool {
+        (**self).buffers()
+    }
+    fn routes(&self) -> Vec<usize> {
+        (**self).routes()
+    }
+    fn pending(&self) -> bool {
+        (**self).pending()
+    }
+    async fn tick(&mut self) -> anyhow::Result<Vec<Message>> {
+        (**self).tick().await
+    }
+    async fn finalize(&mut self) -> anyhow::Result<Vec<Message>> {
+        (**self).finalize().await
+    }
+}
+
+/// Filter action - keeps messages for which the expression holds (see
+/// `crate::expr`)
 pub struct FilterAction {
-    expression: String,
+    expr: Expr,
 }
 
 impl FilterAction {
-    pub fn new(expression: String) -> Self {
-        Self { expression }
-    }
-
-    fn evaluate(&self, value: &Value) -> bool {
-        // Simple expression parser: "field>value", "field==value", "field<value"
-        let expr = self.expression.trim();
-        
-        if let Some((field, rest)) = expr.split_once(">=") {
-            return self.compare_field(value, field.trim(), rest.trim(), |a, b| a >= b);
-        }
-        if let Some((field, rest)) = expr.split_once("<=") {
-            return self.compare_field(value, field.trim(), rest.trim(), |a, b| a <= b);
-        }
-        if let Some((field, rest)) = expr.split_once('>') {
-            return self.compare_field(value, field.trim(), rest.trim(), |a, b| a > b);
-        }
-        if let Some((field, rest)) = expr.split_once('<') {
-            return self.compare_field(value, field.trim(), rest.trim(), |a, b| a < b);
-        }
-        if let Some((field, rest)) = expr.split_once("==") {
-            return self.compare_field(value, field.trim(), rest.trim(), |a, b| a == b);
-        }
-        if let Some((field, rest)) = expr.split_once("!=") {
-            return self.compare_field(value, field.trim(), rest.trim(), |a, b| a != b);
-        }
-        
-        false
-    }
-
-    fn compare_field<F>(&self, value: &Value, field: &str, expected: &str, cmp: F) -> bool
-    where
-        F: Fn(f64, f64) -> bool,
-    {
-        if let Some(val) = value.get(field) {
-            if let (Some(a), Ok(b)) = (val.as_f64(), expected.parse::<f64>()) {
-                return cmp(a, b);
-            }
-            // String comparison
-            if let Some(a) = val.as_str() {
-                return match expected.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
-                    Some(b) => a == b,
-                    None => a == expected,
-                };
-            }
-        }
-        false
+    pub fn new(expression: String) -> anyhow::Result<Self> {
+        Ok(Self { expr: Expr::parse(&expression)? })
     }
 }
 
 #[async_trait]
 impl PipeAction for FilterAction {
+    fn name(&self) -> &'static str {
+        "filter"
+    }
     async fn execute(&mut self, msg: Message) -> anyhow::Result<Vec<Message>> {
         if let Some(ref parsed) = msg.parsed {
-            if self.evaluate(parsed) {
+            if self.expr.matches(parsed) {
                 Ok(vec![msg])
             } else {
                 Ok(vec![])
@@ -83,128 +104,87 @@ impl PipeAction for FilterAction {
     }
 }
 
-/// Transform action - adds/modifies fields based on expressions
+/// Transform action - sets fields to the values of expressions, in order, so
+/// each sees the fields set before it
 pub struct TransformAction {
-    transformations: Vec<(String, String)>, // (field, expression)
+    transformations: Vec<(Vec<String>, Expr)>, // (field path, expression)
 }
 
 impl TransformAction {
-    pub fn new(expr: String) -> Self {
-        let mut transformations = vec![];
-        // Parse "field=expr" or "field=expr,field2=expr2"
-        for part in expr.split(',') {
-            if let Some((field, value_expr)) = part.split_once('=') {
-                transformations.push((field.trim().to_string(), value_expr.trim().to_string()));
-            }
-        }
-        Self { transformations }
-    }
-
-    fn evaluate_expr(&self, value: &Value, expr: &str) -> Option<Value> {
-        // Simple math expressions like "temp*1.8+32"
-        let expr = expr.trim();
-        
-        // Try to evaluate as a simple math expression with field references
-        if let Some(result) = self.eval_math(value, expr) {
-            return Some(json!(result));
-        }
-        
-        // String literal
-        if let Some(s) = expr.strip_prefix('"').and_then(|e| e.strip_suffix('"')) {
-            return Some(json!(s));
-        }
-        
-        // Field reference
-        if let Some(v) = value.get(expr) {
-            return Some(v.clone());
-        }
-        
-        None
-    }
-
-    fn eval_math(&self, value: &Value, expr: &str) -> Option<f64> {
-        // Very simple evaluator for expressions like "temp*1.8+32"
-        let mut result = 0.0;
-        let mut current_op = '+';
-        let mut current_num = String::new();
-        
-        for ch in expr.chars() {
-            match ch {
-                '+' | '-' | '*' | '/' => {
-                    if !current_num.is_empty() {
-                        let num = if let Ok(n) = current_num.parse::<f64>() {
-                            n
-                        } else if let Some(v) = value.get(current_num.trim()).and_then(|v| v.as_f64()) {
-                            v
-                        } else {
-                            return None;
-                        };
-                        
-                        result = match current_op {
-                            '+' => result + num,
-                            '-' => result - num,
-                            '*' => if result == 0.0 { num } else { result * num },
-                            '/' => if result == 0.0 { num } else { result / num },
-                            _ => num,
-                        };
-                        current_num.clear();
-                    }
-                    current_op = ch;
-                }
-                ' ' => {}
-                _ => current_num.push(ch),
-            }
-        }
-        
-        if !current_num.is_empty() {
-            let num = if let Ok(n) = current_num.parse::<f64>() {
-                n
-            } else if let Some(v) = value.get(current_num.trim()).and_then(|v| v.as_f64()) {
-                v
-            } else {
-                return None;
-            };
-            
-            result = match current_op {
-                '+' => result + num,
-                '-' => result - num,
-                '*' => if result == 0.0 { num } else { result * num },
-                '/' => if result == 0.0 { num } else { result / num },
-                _ => num,
-            };
-        }
-        
-        Some(result)
+    /// `expr` is "field=expr" or "field=expr,field2=expr2"
+    pub fn new(expr: String) -> anyhow::Result<Self> {
+        Ok(Self { transformations: crate::expr::assignments(&expr)? })
     }
 }
 
 #[async_trait]
 impl PipeAction for TransformAction {
+    fn name(&self) -> &'static str {
+        "transform"
+    }
     async fn execute(&mut self, mut msg: Message) -> anyhow::Result<Vec<Message>> {
-        if let Some(parsed) = msg.parsed.clone() {
-            let mut new_value = parsed.clone();
-            
+        if let Some(mut new_value) = msg.parsed.take() {
             for (field, expr) in &self.transformations {
-                if let Some(result) = self.evaluate_expr(&parsed, expr) {
-                    if let Some(obj) = new_value.as_object_mut() {
-                        obj.insert(field.clone(), result);
-                    }
+                let result = expr.eval(&new_value);
+                if !crate::expr::assign(&mut new_value, field, result) {
+                    tracing::debug!(field = %field.join("."), "Transform target is not inside an object");
                 }
             }
-            
-            msg.parsed = Some(new_value.clone());
+
             msg.payload = new_value.to_string().into_bytes();
+            msg.parsed = Some(new_value);
         }
         
         Ok(vec![msg])
     }
 }
 
-/// Aggregate action - groups and aggregates messages
+/// Aggregate action - groups and aggregates messages, either over the whole
+/// stream (emitted at the end) or per time window (emitted as windows close)
 pub struct AggregateAction {
     group_by: Option<String>,
     operations: Vec<(String, String)>, // (operation, field)
-    groups: HashMap<String, Vec<Value>>,
+    window: Option<Window>,
+    groups: BTreeMap<GroupKey, Group>,
+    closed: BTreeMap<GroupKey, Group>, // emitted windows kept for late updates
+    watermark: Watermark,
+    late: LatePolicy,
+    late_route: Option<DeadLetterSender>,
+}
+
+/// (window end, window start, group); ordered so the next window to close comes first
+type GroupKey = (i64, i64, String);
+
+/// Without a window every group lives in one window that never closes.
+const UNBOUNDED: Bounds = Bounds { start: i64::MIN, end: i64::MAX };
+
+/// Inputs of one group in one window.
+#[derive(Default)]
+struct Group {
+    values: Vec<Value>,
+    acks: Vec<Ack>, // released when the group result is emitted
+    parents: Parents, // IDs and shared headers of the inputs
+}
+
+impl Group {
+    fn add(&mut self, value: &Value, msg: &Message) {
+        self.values.push(value.clone());
+        // A message in several overlapping windows is acknowledged once all are emitted
+        self.acks.extend(msg.ack.clone());
+        self.parents.add(msg);
+    }
+
+    fn merge(&mut self, other: Group) {
+        self.values.extend(other.values);
+        self.acks.extend(other.acks);
+        self.parents.merge(other.parents);
+    }
+}
+
+/// Where a windowed message went.
+struct Placement {
+    added: bool, // joined at least one open window
+    missed: Vec<Bounds>, // closed windows it belongs in
 }
 
 impl AggregateAction {
@@ -212,10 +192,35 @@ impl AggregateAction {
         Self {
             group_by,
             operations: ops,
-            groups: HashMap::new(),
+            window: None,
+            groups: BTreeMap::new(),
+            closed: BTreeMap::new(),
+            watermark: Watermark::default(),
+            late: LatePolicy::Drop,
+            late_route: None,
         }
     }
 
+    /// Aggregate per window instead of over the whole stream.
+    pub fn with_window(mut self, window: Window) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// For event time windows: how long windows wait for out-of-order
+    /// messages, and what happens to messages later than that.
+    pub fn with_lateness(mut self, allowed_lateness: Duration, late: LatePolicy) -> Self {
+        self.watermark = Watermark::new(allowed_lateness);
+        self.late = late;
+        self
+    }
+
+    /// Side sink for late messages under `LatePolicy::Route`.
+    pub fn with_late_route(mut self, route: DeadLetterSender) -> Self {
+        self.late_route = Some(route);
+        self
+    }
+
     fn get_group_key(&self, value: &Value) -> String {
         if let Some(ref field) = self.group_by {
             value.get(field)
@@ -226,62 +231,224 @@ impl AggregateAction {
             "_all_".to_string()
         }
     }
-}
 
-#[async_trait]
-impl PipeAction for AggregateAction {
-    async fn execute(&mut self, msg: Message) -> anyhow::Result<Vec<Message>> {
-        if let Some(ref parsed) = msg.parsed {
-            let key = self.get_group_key(parsed);
-            self.groups.entry(key).or_insert_with(Vec::new).push(parsed.clone());
+    /// Time up to which windows are complete: the clock, or for event time
+    /// the watermark.
+    fn current_watermark(&self) -> i64 {
+        match self.window {
+            Some(ref window) if !window.is_event_time() => chrono::Utc::now().timestamp_millis(),
+            _ => self.watermark.current(),
         }
-        Ok(vec![]) // Hold messages until finalize
     }
 
-    async fn finalize(&mut self) -> anyhow::Result<Vec<Message>> {
+    /// Add a message to its group in every open window it falls in.
+    fn add_windowed(&mut self, window: &Window, key: &str, time: i64, value: &Value, msg: &Message) -> Placement {
+        let watermark = self.current_watermark();
+        let WindowKind::Session { gap_ms } = window.kind() else {
+            let (open, missed): (Vec<Bounds>, Vec<Bounds>) = window.assign(time).into_iter().partition(|b| b.end > watermark);
+            for bounds in &open {
+                self.groups.entry((bounds.end, bounds.start, key.to_string())).or_default().add(value, msg);
+            }
+            return Placement { added: !open.is_empty(), missed };
+        };
+
+        let bounds = Bounds { start: time, end: time + gap_ms.max(1) as i64 };
+        if bounds.end <= watermark && touching(&self.groups, key, time, gap_ms).is_empty() {
+            return Placement { added: false, missed: vec![bounds] };
+        }
+        add_session(&mut self.groups, key, time, gap_ms, value, msg);
+        Placement { added: true, missed: vec![] }
+    }
+
+    /// Add a late message to the emitted windows it belongs in that are still
+    /// kept, and emit them again.
+    fn update_closed(&mut self, window: &Window, key: &str, time: i64, missed: &[Bounds], value: &Value, msg: &Message) -> Vec<Message> {
+        let retained = self.current_watermark().saturating_sub(self.watermark.allowed_lateness());
+        let mut updated = vec![];
+        match window.kind() {
+            WindowKind::Session { gap_ms } => {
+                if missed.iter().any(|b| b.end > retained) || !touching(&self.closed, key, time, gap_ms).is_empty() {
+                    updated.push(add_session(&mut self.closed, key, time, gap_ms, value, msg));
+                }
+            }
+            _ => {
+                for bounds in missed.iter().filter(|b| b.end > retained) {
+                    let group_key = (bounds.end, bounds.start, key.to_string());
+                    self.closed.entry(group_key.clone()).or_default().add(value, msg);
+                    updated.push(group_key);
+                }
+            }
+        }
         let mut results = vec![];
-        
-        for (group_key, values) in &self.groups {
-            let mut result_obj = serde_json::Map::new();
-            
-            if let Some(ref field) = self.group_by {
-                result_obj.insert(field.clone(), json!(group_key));
+        for (end, start, key) in updated {
+            if let Some(mut group) = self.closed.remove(&(end, start, key.clone())) {
+                let mut result = self.result(Bounds { start, end }, &key, &mut group);
+                mark_update(&mut result);
+                results.push(result);
+                self.closed.insert((end, start, key), group);
             }
-            
-            for (op, field) in &self.operations {
-                let nums: Vec<f64> = values.iter()
-                    .filter_map(|v| v.get(field).and_then(|n| n.as_f64()))
-                    .collect();
-                
-                let result = match op.as_str() {
-                    "avg" => nums.iter().sum::<f64>() / nums.len() as f64,
-                    "sum" => nums.iter().sum::<f64>(),
-                    "min" => nums.iter().cloned().fold(f64::INFINITY, f64::min),
-                    "max" => nums.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
-                    "count" => values.len() as f64,
-                    _ => 0.0,
-                };
-                
-                result_obj.insert(format!("{}_{}", op, field), json!(result));
+        }
+        results
+    }
+
+    /// Emit the groups of every window that ended at or before `watermark`.
+    fn close(&mut self, watermark: i64) -> Vec<Message> {
+        let mut results = vec![];
+        while self.groups.first_key_value().is_some_and(|((end, _, _), _)| *end <= watermark) {
+            let Some(((end, start, key), mut group)) = self.groups.pop_first() else { break };
+            results.push(self.result(Bounds { start, end }, &key, &mut group));
+            if self.late == LatePolicy::Update {
+                self.closed.insert((end, start, key), group);
             }
+        }
+        // Emitted windows are kept for another allowed lateness
+        let retained = watermark.saturating_sub(self.watermark.allowed_lateness());
+        while self.closed.first_key_value().is_some_and(|((end, _, _), _)| *end <= retained) {
+            self.closed.pop_first();
+        }
+        results
+    }
+
+    fn result(&self, bounds: Bounds, group_key: &str, group: &mut Group) -> Message {
+        let mut result_obj = serde_json::Map::new();
+        
+        if let Some(ref field) = self.group_by {
+            result_obj.insert(field.clone(), json!(group_key));
+        }
+        let windowed = bounds != UNBOUNDED;
+        if windowed {
+            result_obj.insert("window_start".to_string(), json!(bounds.start_time().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)));
+            result_obj.insert("window_end".to_string(), json!(bounds.end_time().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)));
+        }
+        
+        for (op, field) in &self.operations {
+            let nums: Vec<f64> = group.values.iter()
+                .filter_map(|v| v.get(field).and_then(|n| n.as_f64()))
+                .collect();
             
-            let result_value = Value::Object(result_obj);
-            let payload = result_value.to_string().into_bytes();
+            let result = match op.as_str() {
+                "avg" => nums.iter().sum::<f64>() / nums.len() as f64,
+                "sum" => nums.iter().sum::<f64>(),
+                "min" => nums.iter().cloned().fold(f64::INFINITY, f64::min),
+                "max" => nums.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                "count" => group.values.len() as f64,
+                _ => 0.0,
+            };
             
-            results.push(Message {
-                id: None,
-                key: None,
-                payload,
-                format: Some(Format::Json),
-                parsed: Some(result_value),
-                timestamp: chrono::Utc::now(),
-                headers: Default::default(),
-                meta: Default::default(),
-            });
+            result_obj.insert(format!("{}_{}", op, field), json!(result));
         }
         
+        let result_value = Value::Object(result_obj);
+        let payload = result_value.to_string().into_bytes();
+        
+        let mut result = Message {
+            id: None,
+            parents: Vec::new(),
+            key: None,
+            payload,
+            format: Some(Format::Json),
+            parsed: Some(result_value),
+            timestamp: if windowed { bounds.end_time() } else { chrono::Utc::now() },
+            headers: Default::default(),
+            meta: Default::default(),
+            ack: Ack::merge(std::mem::take(&mut group.acks)),
+        };
+        group.parents.derive(&mut result);
+        result
+    }
+}
+
+/// Keys of the group's sessions a message at `time` falls in or bridges.
+fn touching(groups: &BTreeMap<GroupKey, Group>, key: &str, time: i64, gap_ms: u64) -> Vec<GroupKey> {
+    let gap = gap_ms.max(1) as i64;
+    groups.keys()
+        .filter(|(end, start, k)| k == key && start - gap <= time && time < *end)
+        .cloned()
+        .collect()
+}
+
+/// Add a message to the group's session it falls in, joining the sessions it
+/// bridges; returns the key of the resulting session.
+fn add_session(groups: &mut BTreeMap<GroupKey, Group>, key: &str, time: i64, gap_ms: u64, value: &Value, msg: &Message) -> GroupKey {
+    let mut bounds = Bounds { start: time, end: time + gap_ms.max(1) as i64 };
+    let mut group = Group::default();
+    for k in touching(groups, key, time, gap_ms) {
+        if let Some(other) = groups.remove(&k) {
+            bounds = Bounds { start: bounds.start.min(k.1), end: bounds.end.max(k.0) };
+            group.merge(other);
+        }
+    }
+    group.add(value, msg);
+    let group_key = (bounds.end, bounds.start, key.to_string());
+    groups.insert(group_key.clone(), group);
+    group_key
+}
+
+/// Flag a result that replaces one emitted before for the same window.
+fn mark_update(result: &mut Message) {
+    if let Some(Value::Object(ref mut obj)) = result.parsed {
+        obj.insert("late_update".to_string(), json!(true));
+        result.payload = Value::Object(obj.clone()).to_string().into_bytes();
+    }
+}
+
+#[async_trait]
+impl PipeAction for AggregateAction {
+    fn name(&self) -> &'static str {
+        "aggregate"
+    }
+    fn buffers(&self) -> bool {
+        true
+    }
+    async fn execute(&mut self, msg: Message) -> anyhow::Result<Vec<Message>> {
+        let Some(ref parsed) = msg.parsed else { return Ok(vec![]) };
+        let key = self.get_group_key(parsed);
+        let Some(window) = self.window.clone() else {
+            self.groups.entry((UNBOUNDED.end, UNBOUNDED.start, key)).or_default().add(parsed, &msg);
+            return Ok(vec![]); // Hold messages until finalize
+        };
+
+        let Some(time) = window.time_of(&msg) else {
+            tracing::warn!(id = ?msg.id, "Message has no valid event time, not aggregated");
+            return Ok(vec![]);
+        };
+        let placement = self.add_windowed(&window, &key, time, parsed, &msg);
+        let mut results = vec![];
+        match self.late {
+            LatePolicy::Update if !placement.missed.is_empty() => {
+                results = self.update_closed(&window, &key, time, &placement.missed, parsed, &msg);
+                if results.is_empty() && !placement.added {
+                    tracing::warn!(id = ?msg.id, time, "Message too late to update its window, dropped");
+                }
+            }
+            _ if placement.added => {}
+            LatePolicy::Route if self.late_route.is_some() => {
+                if let Some(ref route) = self.late_route {
+                    route.send(msg, "aggregate", "late for a closed window", 1);
+                }
+            }
+            _ => {
+                tracing::warn!(id = ?msg.id, time, watermark = self.current_watermark(), "Late message for a closed window, dropped");
+            }
+        }
+        if window.is_event_time() {
+            self.watermark.observe(time);
+        }
+        results.extend(self.close(self.current_watermark()));
         Ok(results)
     }
+
+    async fn tick(&mut self) -> anyhow::Result<Vec<Message>> {
+        if self.window.is_none() {
+            return Ok(vec![]);
+        }
+        Ok(self.close(self.current_watermark()))
+    }
+
+    async fn finalize(&mut self) -> anyhow::Result<Vec<Message>> {
+        // Emit every open window, complete or not
+        Ok(self.close(i64::MAX))
+    }
 }
 
 /// Normalize action - ensures schema conformance
@@ -301,6 +468,9 @@ impl NormalizeAction {
 
 #[async_trait]
 impl PipeAction for NormalizeAction {
+    fn name(&self) -> &'static str {
+        "normalize"
+    }
     async fn execute(&mut self, mut msg: Message) -> anyhow::Result<Vec<Message>> {
         if let (Some(schema), Some(parsed)) = (&self.schema, &msg.parsed) {
             if let Some(props) = schema.get("properties").and_then(|p| p.as_object()) {
@@ -324,6 +494,7 @@ impl PipeAction for NormalizeAction {
 /// Validate action - filters messages that don't match schema
 pub struct ValidateAction {
     schema: Option<Value>,
+    dead_letter: Option<DeadLetterSender>,
 }
 
 impl ValidateAction {
@@ -332,7 +503,13 @@ impl ValidateAction {
             std::fs::read_to_string(path).ok()
                 .and_then(|content| serde_json::from_str(&content).ok())
         });
-        Self { schema }
+        Self { schema, dead_letter: None }
+    }
+
+    /// Route rejected messages to a dead-letter queue instead of dropping them.
+    pub fn with_dead_letter(mut self, dead_letter: DeadLetterSender) -> Self {
+        self.dead_letter = Some(dead_letter);
+        self
     }
 
     fn validate(&self, value: &Value) -> bool {
@@ -355,12 +532,18 @@ impl ValidateAction {
 
 #[async_trait]
 impl PipeAction for ValidateAction {
+    fn name(&self) -> &'static str {
+        "validate"
+    }
     async fn execute(&mut self, msg: Message) -> anyhow::Result<Vec<Message>> {
         if let Some(ref parsed) = msg.parsed {
             if self.validate(parsed) {
                 Ok(vec![msg])
             } else {
-                eprintln!("Validation failed for message");
+                tracing::warn!("Validation failed for message");
+                if let Some(ref dlq) = self.dead_letter {
+                    dlq.send(msg, "validate", "Message does not match schema", 1);
+                }
                 Ok(vec![])
             }
         } else {
@@ -383,6 +566,9 @@ impl LimitAction {
 
 #[async_trait]
 impl PipeAction for LimitAction {
+    fn name(&self) -> &'static str {
+        "limit"
+    }
     async fn execute(&mut self, msg: Message) -> anyhow::Result<Vec<Message>> {
         if self.count < self.limit {
             self.count += 1;
@@ -393,6 +579,47 @@ impl PipeAction for LimitAction {
     }
 }
 
+/// Rate limit action - token bucket with bursts, optionally per key or field.
+/// Over-limit messages are held and released by `tick` as tokens come back
+/// (delay), are discarded (drop) or go to the dead-letter sink (route); see
+/// the `RateLimit` middleware, which it runs.
+pub struct RateLimitAction {
+    limit: RateLimit,
+}
+
+impl RateLimitAction {
+    pub fn new(limiter: RateLimiter, mode: OverLimit) -> Self {
+        Self { limit: RateLimit::new(limiter, mode).with_stage("ratelimit") }
+    }
+
+    pub fn with_route(mut self, route: DeadLetterSender) -> Self {
+        self.limit = self.limit.with_route(route);
+        self
+    }
+}
+
+#[async_trait]
+impl PipeAction for RateLimitAction {
+    fn name(&self) -> &'static str {
+        "ratelimit"
+    }
+    fn buffers(&self) -> bool {
+        self.limit.buffers()
+    }
+    fn pending(&self) -> bool {
+        self.limit.holding()
+    }
+    async fn execute(&mut self, msg: Message) -> anyhow::Result<Vec<Message>> {
+        Ok(self.limit.handle(msg).await)
+    }
+    async fn tick(&mut self) -> anyhow::Result<Vec<Message>> {
+        Ok(self.limit.tick().await)
+    }
+    async fn finalize(&mut self) -> anyhow::Result<Vec<Message>> {
+        Ok(self.limit.finalize().await)
+    }
+}
+
 /// Sample action - passes every Nth message
 pub struct SampleAction {
     rate: usize,
@@ -407,12 +634,558 @@ impl SampleAction {
 
 #[async_trait]
 impl PipeAction for SampleAction {
+    fn name(&self) -> &'static str {
+        "sample"
+    }
     async fn execute(&mut self, msg: Message) -> anyhow::Result<Vec<Message>> {
         self.count += 1;
-        if self.count % self.rate == 0 {
+        if self.count.is_multiple_of(self.rate) {
             Ok(vec![msg])
         } else {
             Ok(vec![])
         }
     }
 }
+
+/// Join action - enriches messages with the lookup table row whose key
+/// matches one of their fields
+pub struct JoinAction {
+    on: String, // dotted path into the message
+    table_key: String,
+    columns: Vec<String>, // columns merged in; all but the key when empty
+    kind: JoinKind,
+    table: LookupTable,
+    reload: Option<TableReload>,
+}
+
+/// Reloads a join's table when its file changes.
+struct TableReload {
+    path: PathBuf,
+    loader: TableLoader,
+    interval: Duration,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl JoinAction {
+    /// `table` must be indexed by `table_key`.
+    pub fn new(on: impl Into<String>, table_key: impl Into<String>, kind: JoinKind, table: LookupTable) -> Self {
+        Self { on: on.into(), table_key: table_key.into(), columns: vec![], kind, table, reload: None }
+    }
+
+    /// Merge only these columns; a left join sets them to null without a match.
+    pub fn with_columns(mut self, columns: Vec<String>) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Reload the table with `loader` when `path` changes, checked every
+    /// `interval`. A failed reload keeps the current table.
+    pub fn with_reload(mut self, path: impl Into<PathBuf>, interval: Duration, loader: TableLoader) -> Self {
+        let path = path.into();
+        let modified = modified_time(&path);
+        self.reload = Some(TableReload { path, loader, interval, modified, last_check: Instant::now() });
+        self
+    }
+
+    async fn reload_if_changed(&mut self) {
+        let Some(ref mut reload) = self.reload else { return };
+        if reload.last_check.elapsed() < reload.interval {
+            return;
+        }
+        reload.last_check = Instant::now();
+        let modified = modified_time(&reload.path);
+        if modified == reload.modified {
+            return;
+        }
+        reload.modified = modified;
+
+        let (loader, key) = (reload.loader.clone(), self.table_key.clone());
+        let loaded = tokio::task::spawn_blocking(move || LookupTable::from_rows(loader()?, &key)).await;
+        match loaded {
+            Ok(Ok(table)) => {
+                tracing::info!(path = %reload.path.display(), rows = table.len(), "Lookup table changed, reloaded");
+                self.table = table;
+            }
+            Ok(Err(e)) => tracing::warn!(path = %reload.path.display(), error = format!("{:#}", e), "Rejected lookup table change, keeping the current table"),
+            Err(e) => tracing::warn!(path = %reload.path.display(), error = %e, "Lookup table reload failed"),
+        }
+    }
+}
+
+fn modified_time(path: &std::path::Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[async_trait]
+impl PipeAction for JoinAction {
+    fn name(&self) -> &'static str {
+        "join"
+    }
+    async fn execute(&mut self, mut msg: Message) -> anyhow::Result<Vec<Message>> {
+        let key = msg.parsed.as_ref().and_then(|p| self.on.split('.').try_fold(p, |v, part| v.get(part))).cloned();
+        let row = key.and_then(|key| self.table.get(&key));
+        match (row, self.kind) {
+            (None, JoinKind::Inner) => return Ok(vec![]),
+            (None, JoinKind::Left) if self.columns.is_empty() => return Ok(vec![msg]),
+            _ => {}
+        }
+        let Some(Value::Object(ref mut obj)) = msg.parsed else { return Ok(vec![msg]) };
+        if self.columns.is_empty() {
+            for (column, value) in row.into_iter().flatten().filter(|(c, _)| **c != self.table_key) {
+                obj.insert(column.clone(), value.clone());
+            }
+        } else {
+            for column in &self.columns {
+                let value = row.and_then(|r| r.get(column)).cloned().unwrap_or(Value::Null);
+                obj.insert(column.clone(), value);
+            }
+        }
+        msg.payload = Value::Object(obj.clone()).to_string().into_bytes();
+        Ok(vec![msg])
+    }
+    async fn tick(&mut self) -> anyhow::Result<Vec<Message>> {
+        self.reload_if_changed().await;
+        Ok(vec![])
+    }
+}
+
+/// `Message.meta` key listing the sinks (comma-separated indices) a routed
+/// message goes to; messages without it go to every sink no action routes to.
+pub const ROUTE_META: &str = "route";
+
+/// Which rules of a `route` action a message follows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteMode {
+    /// The first rule that matches
+    #[default]
+    First,
+    /// Every rule that matches
+    All,
+}
+
+impl std::str::FromStr for RouteMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Self::First),
+            "all" => Ok(Self::All),
+            other => Err(anyhow::anyhow!("Unknown route mode '{}', expected first or all", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for RouteMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::First => write!(f, "first"),
+            Self::All => write!(f, "all"),
+        }
+    }
+}
+
+/// Route action - sends each message to the sinks of the rules whose
+/// predicate it matches, or to the default sinks. Without default sinks,
+/// messages matching no rule pass on unrouted.
+pub struct RouteAction {
+    rules: Vec<(Expr, Vec<usize>)>, // (predicate, sink indices)
+    default: Vec<usize>,
+    mode: RouteMode,
+}
+
+impl RouteAction {
+    pub fn new(mode: RouteMode) -> Self {
+        Self { rules: vec![], default: vec![], mode }
+    }
+
+    /// Send messages matching `predicate` to `sinks`.
+    pub fn rule(mut self, predicate: Expr, sinks: Vec<usize>) -> Self {
+        self.rules.push((predicate, sinks));
+        self
+    }
+
+    /// Send messages matching no rule to `sinks`.
+    pub fn default_to(mut self, sinks: Vec<usize>) -> Self {
+        self.default = sinks;
+        self
+    }
+}
+
+#[async_trait]
+impl PipeAction for RouteAction {
+    fn name(&self) -> &'static str {
+        "route"
+    }
+    fn routes(&self) -> Vec<usize> {
+        let mut routes: Vec<usize> = self.rules.iter().flat_map(|(_, sinks)| sinks).chain(&self.default).copied().collect();
+        routes.sort_unstable();
+        routes.dedup();
+        routes
+    }
+    async fn execute(&mut self, mut msg: Message) -> anyhow::Result<Vec<Message>> {
+        let mut sinks: Vec<usize> = vec![];
+        if let Some(ref parsed) = msg.parsed {
+            for (predicate, to) in &self.rules {
+                if predicate.matches(parsed) {
+                    sinks.extend(to);
+                    if self.mode == RouteMode::First {
+                        break;
+                    }
+                }
+            }
+        }
+        if sinks.is_empty() {
+            sinks.extend(&self.default);
+        }
+        if sinks.is_empty() {
+            return Ok(vec![msg]);
+        }
+        sinks.sort_unstable();
+        sinks.dedup();
+        let to: Vec<String> = sinks.iter().map(usize::to_string).collect();
+        msg.meta.insert(ROUTE_META.to_string(), to.join(","));
+        Ok(vec![msg])
+    }
+}
+
+/// Middleware action - runs a bridge middleware (dedup, batch, throttle) as a
+/// pipe step
+pub struct MiddlewareAction {
+    middleware: Box<dyn Middleware>,
+}
+
+impl MiddlewareAction {
+    pub fn new(middleware: Box<dyn Middleware>) -> Self {
+        Self { middleware }
+    }
+}
+
+#[async_trait]
+impl PipeAction for MiddlewareAction {
+    fn name(&self) -> &'static str {
+        self.middleware.name()
+    }
+    fn buffers(&self) -> bool {
+        self.middleware.buffers()
+    }
+    async fn execute(&mut self, msg: Message) -> anyhow::Result<Vec<Message>> {
+        Ok(self.middleware.handle(msg).await)
+    }
+    async fn tick(&mut self) -> anyhow::Result<Vec<Message>> {
+        Ok(self.middleware.tick().await)
+    }
+    async fn finalize(&mut self) -> anyhow::Result<Vec<Message>> {
+        Ok(self.middleware.finalize().await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::WindowTime;
+    use chrono::TimeZone;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn message(time: i64, value: Value) -> Message {
+        Message {
+            id: None,
+            parents: Vec::new(),
+            key: None,
+            payload: value.to_string().into_bytes(),
+            format: Some(Format::Json),
+            parsed: Some(value),
+            timestamp: chrono::Utc.timestamp_millis_opt(time).unwrap(),
+            headers: Default::default(),
+            meta: Default::default(),
+            ack: None,
+        }
+    }
+
+    fn aggregate(kind: WindowKind, lateness_ms: u64, late: LatePolicy) -> AggregateAction {
+        AggregateAction::new(Some("user".to_string()), vec![("count".to_string(), "n".to_string())])
+            .with_window(Window::new(kind, WindowTime::Timestamp))
+            .with_lateness(Duration::from_millis(lateness_ms), late)
+    }
+
+    async fn send(action: &mut AggregateAction, time: i64) -> Vec<Message> {
+        action.execute(message(time, json!({ "user": "a", "n": 1 }))).await.unwrap()
+    }
+
+    fn at(millis: i64) -> Value {
+        json!(Bounds { start: millis, end: millis }.start_time().to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+    }
+
+    /// (window_start, window_end, count, late_update) of a result.
+    fn summary(result: &Message) -> (Value, Value, f64, bool) {
+        let value = result.parsed.as_ref().unwrap();
+        (
+            value["window_start"].clone(),
+            value["window_end"].clone(),
+            value["count_n"].as_f64().unwrap(),
+            value.get("late_update").is_some(),
+        )
+    }
+
+    #[tokio::test]
+    async fn tumbling_windows_close_as_the_watermark_passes() {
+        let mut action = aggregate(WindowKind::Tumbling { size_ms: 10 }, 0, LatePolicy::Drop);
+        assert!(send(&mut action, 1).await.is_empty());
+        assert!(send(&mut action, 5).await.is_empty());
+
+        let results = send(&mut action, 12).await;
+        assert_eq!(results.iter().map(summary).collect::<Vec<_>>(), vec![(at(0), at(10), 2.0, false)]);
+        assert_eq!(results[0].timestamp.timestamp_millis(), 10);
+
+        let results = action.finalize().await.unwrap();
+        assert_eq!(results.iter().map(summary).collect::<Vec<_>>(), vec![(at(10), at(20), 1.0, false)]);
+    }
+
+    #[test]
+    fn hopping_windows_count_a_message_in_each_window() {
+        let mut action = aggregate(WindowKind::Hopping { size_ms: 10, advance_ms: 5 }, 0, LatePolicy::Drop);
+        let window = action.window.clone().unwrap();
+        let msg = message(7, json!({ "user": "a", "n": 1 }));
+        let placement = action.add_windowed(&window, "a", 7, msg.parsed.as_ref().unwrap(), &msg);
+        assert!(placement.added && placement.missed.is_empty());
+        assert_eq!(action.groups.len(), 2);
+
+        let results = action.close(i64::MAX);
+        assert_eq!(
+            results.iter().map(summary).collect::<Vec<_>>(),
+            vec![(at(0), at(10), 1.0, false), (at(5), at(15), 1.0, false)]
+        );
+    }
+
+    #[tokio::test]
+    async fn sessions_merge_when_a_message_bridges_them() {
+        let mut action = aggregate(WindowKind::Session { gap_ms: 10 }, 100, LatePolicy::Drop);
+        assert!(send(&mut action, 0).await.is_empty());
+        assert!(send(&mut action, 15).await.is_empty());
+        assert_eq!(action.groups.len(), 2);
+
+        // 7 falls in [0, 10) and is within the gap before 15
+        assert!(send(&mut action, 7).await.is_empty());
+        let results = action.finalize().await.unwrap();
+        assert_eq!(results.iter().map(summary).collect::<Vec<_>>(), vec![(at(0), at(25), 3.0, false)]);
+    }
+
+    #[test]
+    fn touching_finds_the_sessions_a_time_joins() {
+        let msg = message(0, json!({ "n": 1 }));
+        let value = msg.parsed.clone().unwrap();
+        let mut groups = BTreeMap::new();
+        add_session(&mut groups, "a", 0, 10, &value, &msg);
+        add_session(&mut groups, "a", 25, 10, &value, &msg);
+        add_session(&mut groups, "b", 12, 10, &value, &msg);
+
+        assert_eq!(touching(&groups, "a", 5, 10), vec![(10, 0, "a".to_string())]);
+        assert_eq!(touching(&groups, "a", 20, 10), vec![(35, 25, "a".to_string())]);
+        assert!(touching(&groups, "a", 12, 10).is_empty());
+
+        // 9 extends [0, 10) to [0, 19); 18 then bridges it and [25, 35)
+        assert_eq!(add_session(&mut groups, "a", 9, 10, &value, &msg), (19, 0, "a".to_string()));
+        assert_eq!(add_session(&mut groups, "a", 18, 10, &value, &msg), (35, 0, "a".to_string()));
+        assert_eq!(groups[&(35, 0, "a".to_string())].values.len(), 4);
+        assert_eq!(groups.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn late_messages_update_retained_windows() {
+        let mut action = aggregate(WindowKind::Tumbling { size_ms: 10 }, 5, LatePolicy::Update);
+        send(&mut action, 3).await;
+        let results = send(&mut action, 17).await;
+        assert_eq!(results.iter().map(summary).collect::<Vec<_>>(), vec![(at(0), at(10), 1.0, false)]);
+
+        // The watermark is 12; [0, 10) is kept until it reaches 15
+        let results = send(&mut action, 4).await;
+        assert_eq!(results.iter().map(summary).collect::<Vec<_>>(), vec![(at(0), at(10), 2.0, true)]);
+
+        let results = send(&mut action, 27).await;
+        assert_eq!(results.iter().map(summary).collect::<Vec<_>>(), vec![(at(10), at(20), 1.0, false)]);
+        assert_eq!(action.closed.keys().map(|k| k.1).collect::<Vec<_>>(), vec![10]);
+
+        // Past retention the message is dropped
+        assert!(send(&mut action, 5).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn late_messages_update_retained_sessions() {
+        let mut action = aggregate(WindowKind::Session { gap_ms: 10 }, 5, LatePolicy::Update);
+        send(&mut action, 0).await;
+        let results = send(&mut action, 16).await;
+        assert_eq!(results.iter().map(summary).collect::<Vec<_>>(), vec![(at(0), at(10), 1.0, false)]);
+
+        // [1, 11) closed at watermark 11, but joins the kept [0, 10)
+        let results = send(&mut action, 1).await;
+        assert_eq!(results.iter().map(summary).collect::<Vec<_>>(), vec![(at(0), at(11), 2.0, true)]);
+    }
+
+    #[tokio::test]
+    async fn late_messages_are_dropped_and_acknowledged() {
+        let mut action = aggregate(WindowKind::Tumbling { size_ms: 10 }, 0, LatePolicy::Drop);
+        send(&mut action, 3).await;
+        let results = send(&mut action, 15).await;
+        assert_eq!(results.len(), 1);
+        assert!(action.closed.is_empty());
+
+        let acked = Arc::new(AtomicUsize::new(0));
+        let mut late = message(4, json!({ "user": "a", "n": 1 }));
+        let counter = acked.clone();
+        late.ack = Some(Ack::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+        late.arm_ack();
+        assert!(action.execute(late).await.unwrap().is_empty());
+        assert_eq!(acked.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn results_hold_their_inputs_acks() {
+        let mut action = aggregate(WindowKind::Tumbling { size_ms: 10 }, 0, LatePolicy::Drop);
+        let acked = Arc::new(AtomicUsize::new(0));
+        for time in [1, 2] {
+            let mut msg = message(time, json!({ "user": "a", "n": 1 }));
+            let counter = acked.clone();
+            msg.ack = Some(Ack::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }));
+            msg.arm_ack();
+            action.execute(msg).await.unwrap();
+        }
+        assert_eq!(acked.load(Ordering::SeqCst), 0);
+
+        let results = action.finalize().await.unwrap();
+        assert_eq!(acked.load(Ordering::SeqCst), 0);
+        drop(results);
+        assert_eq!(acked.load(Ordering::SeqCst), 2);
+    }
+
+    fn users() -> LookupTable {
+        let rows = json!([
+            { "id": "1", "name": "ann", "tier": "gold" },
+            { "id": "2", "name": "bob", "tier": "free" },
+        ]);
+        LookupTable::from_rows(rows, "id").unwrap()
+    }
+
+    async fn join(action: &mut JoinAction, value: Value) -> Option<Value> {
+        let mut out = action.execute(message(0, value)).await.unwrap();
+        assert!(out.len() <= 1);
+        let msg = out.pop()?;
+        // The payload is rewritten to match
+        assert_eq!(serde_json::from_slice::<Value>(&msg.payload).unwrap(), *msg.parsed.as_ref().unwrap());
+        msg.parsed
+    }
+
+    #[tokio::test]
+    async fn joins_merge_the_matching_row() {
+        let mut action = JoinAction::new("user.id", "id", JoinKind::Inner, users());
+        let joined = join(&mut action, json!({ "user": { "id": 1 }, "n": 5 })).await.unwrap();
+        // Every column but the key lands at the top level of the message
+        assert_eq!(joined, json!({ "user": { "id": 1 }, "n": 5, "name": "ann", "tier": "gold" }));
+    }
+
+    #[tokio::test]
+    async fn inner_joins_drop_unmatched_messages() {
+        let mut action = JoinAction::new("user_id", "id", JoinKind::Inner, users());
+        assert!(join(&mut action, json!({ "user_id": "3" })).await.is_none());
+        assert!(join(&mut action, json!({ "other": "1" })).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn left_joins_keep_unmatched_messages() {
+        let mut action = JoinAction::new("user_id", "id", JoinKind::Left, users());
+        assert_eq!(join(&mut action, json!({ "user_id": "3" })).await.unwrap(), json!({ "user_id": "3" }));
+
+        // Selected columns are set either way, to null without a match
+        let mut action = action.with_columns(vec!["tier".to_string()]);
+        assert_eq!(join(&mut action, json!({ "user_id": "3" })).await.unwrap(), json!({ "user_id": "3", "tier": null }));
+        assert_eq!(join(&mut action, json!({ "user_id": "2" })).await.unwrap(), json!({ "user_id": "2", "tier": "free" }));
+    }
+
+    #[tokio::test]
+    async fn joined_columns_overwrite_message_fields() {
+        let mut action = JoinAction::new("user_id", "id", JoinKind::Left, users()).with_columns(vec!["name".to_string()]);
+        let joined = join(&mut action, json!({ "user_id": "1", "name": "old", "tier": "kept" })).await.unwrap();
+        assert_eq!(joined, json!({ "user_id": "1", "name": "ann", "tier": "kept" }));
+    }
+
+    #[tokio::test]
+    async fn tables_reload_when_their_file_changes() {
+        let path = std::env::temp_dir().join(format!("fluxmux-join-reload-{}.json", std::process::id()));
+        std::fs::write(&path, r#"[{ "id": "1", "name": "ann" }]"#).unwrap();
+        let file = path.clone();
+        let loader: TableLoader = Arc::new(move || Ok(serde_json::from_slice(&std::fs::read(&file)?)?));
+        let table = LookupTable::from_rows(loader().unwrap(), "id").unwrap();
+        let mut action = JoinAction::new("user_id", "id", JoinKind::Inner, table).with_reload(&path, Duration::ZERO, loader);
+
+        // Unchanged: nothing is reloaded
+        std::fs::write(&path, r#"[{ "id": "1", "name": "changed" }]"#).unwrap();
+        let modified = action.reload.as_ref().unwrap().modified.unwrap();
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        action.tick().await.unwrap();
+        assert_eq!(join(&mut action, json!({ "user_id": "1" })).await.unwrap()["name"], "ann");
+
+        let touch = |contents: &str, seconds| {
+            std::fs::write(&path, contents).unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(modified + Duration::from_secs(seconds)).unwrap();
+        };
+        touch(r#"[{ "id": "2", "name": "bob" }]"#, 1);
+        action.tick().await.unwrap();
+        assert!(join(&mut action, json!({ "user_id": "1" })).await.is_none());
+        assert_eq!(join(&mut action, json!({ "user_id": "2" })).await.unwrap()["name"], "bob");
+
+        // A broken file keeps the current table
+        touch("not json", 2);
+        action.tick().await.unwrap();
+        assert_eq!(join(&mut action, json!({ "user_id": "2" })).await.unwrap()["name"], "bob");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn levels() -> RouteAction {
+        RouteAction::new(RouteMode::First)
+            .rule(Expr::parse("level == \"error\"").unwrap(), vec![0, 2])
+            .rule(Expr::parse("code >= 500").unwrap(), vec![1])
+    }
+
+    async fn route(action: &mut RouteAction, value: Value) -> Option<String> {
+        let mut out = action.execute(message(0, value)).await.unwrap();
+        assert_eq!(out.len(), 1);
+        out.pop().unwrap().meta.remove(ROUTE_META)
+    }
+
+    #[tokio::test]
+    async fn first_match_routing_stops_at_the_first_rule() {
+        let mut action = levels();
+        assert_eq!(route(&mut action, json!({ "level": "error", "code": 503 })).await.as_deref(), Some("0,2"));
+        assert_eq!(route(&mut action, json!({ "level": "info", "code": 503 })).await.as_deref(), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn all_match_routing_follows_every_rule() {
+        let mut action = RouteAction::new(RouteMode::All)
+            .rule(Expr::parse("level == \"error\"").unwrap(), vec![2, 0])
+            .rule(Expr::parse("code >= 500").unwrap(), vec![1, 2]);
+        assert_eq!(route(&mut action, json!({ "level": "error", "code": 503 })).await.as_deref(), Some("0,1,2"));
+        assert_eq!(route(&mut action, json!({ "level": "error", "code": 200 })).await.as_deref(), Some("0,2"));
+    }
+
+    #[tokio::test]
+    async fn unmatched_messages_take_the_default_route() {
+        let mut action = levels().default_to(vec![3]);
+        assert_eq!(route(&mut action, json!({ "level": "info", "code": 200 })).await.as_deref(), Some("3"));
+        assert_eq!(action.routes(), vec![0, 1, 2, 3]);
+
+        // Without a default they pass on unrouted, as do messages that are not JSON
+        let mut action = levels();
+        assert_eq!(route(&mut action, json!({ "level": "info" })).await, None);
+        let mut raw = message(0, json!(null));
+        raw.parsed = None;
+        assert!(!action.execute(raw).await.unwrap()[0].meta.contains_key(ROUTE_META));
+        assert_eq!(action.routes(), vec![0, 1, 2]);
+    }
+}
@@ -1,71 +1,398 @@
+use crate::engine::{deliver, identify, record_received, sleep_until_deadline, until_drained, PipelineOptions, TICK_INTERVAL};
 use crate::message::Message;
-use crate::pipe_actions::PipeAction;
+use crate::metrics::STAGE_OUT;
+use crate::pipe_actions::{PipeAction, ROUTE_META};
+use crate::retry::CircuitBreaker;
+use crate::trace;
 use crate::traits::{Sink, Source};
 use tokio::sync::mpsc;
+use tracing::Instrument;
 
 pub async fn run_pipe(
     mut source: Box<dyn Source>,
     mut actions: Vec<Box<dyn PipeAction>>,
     mut sinks: Vec<Box<dyn Sink>>,
+    options: PipelineOptions,
 ) -> anyhow::Result<()> {
+    // Sinks a route action sends to get only the messages routed to them
+    let mut routed = vec![false; sinks.len()];
+    for i in actions.iter().flat_map(|a| a.routes()) {
+        match routed.get_mut(i) {
+            Some(routed) => *routed = true,
+            None => anyhow::bail!("A route refers to sink {}, but there are {} sinks", i, sinks.len()),
+        }
+    }
+
     let (tx, mut rx) = mpsc::channel::<Message>(1024);
 
     // Start source in background
     let source_handle = tokio::spawn(async move {
         source.start(tx).await
-    });
+    }.in_current_span());
+
+    let shutdown = options.shutdown.clone();
+    let mut drain_deadline = None;
+    let mut breakers = vec![options.circuit_breaker.clone(); sinks.len()];
+    let mut ticker = tokio::time::interval(TICK_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
     // Process messages through action chain
-    while let Some(msg) = rx.recv().await {
-        let mut messages = vec![msg];
-
-        // Apply each action in sequence
-        for action in &mut actions {
-            let mut next_messages = vec![];
-            for m in messages {
-                match action.execute(m).await {
-                    Ok(mut results) => next_messages.append(&mut results),
-                    Err(e) => eprintln!("Action error: {}", e),
+    loop {
+        let msg = tokio::select! {
+            msg = rx.recv() => msg,
+            _ = shutdown.wait(), if drain_deadline.is_none() => {
+                // Stop the source; dropping its sender lets the channel drain and close
+                source_handle.abort();
+                drain_deadline = Some(shutdown.drain_deadline());
+                continue;
+            }
+            _ = sleep_until_deadline(drain_deadline) => {
+                // Unreceived messages are never armed, so they stay uncommitted
+                tracing::warn!(unprocessed = rx.len(), "Drain deadline reached, queued messages left unprocessed");
+                break;
+            }
+            _ = ticker.tick() => {
+                // Let time-based actions (e.g. batch timeouts) release what they hold
+                let messages = release(&mut actions, false, &options).await;
+                deliver_all(&mut sinks, &routed, messages, &options, &mut breakers).await;
+                continue;
+            }
+        };
+        let Some(mut msg) = msg else { break };
+        msg.arm_ack();
+        record_received(&options, &rx);
+        identify(&options, &mut msg);
+        trace::receive(&mut msg);
+
+        // Everything one input produced goes to each sink as a single batch
+        let messages = apply_actions(&mut actions, 0, vec![msg], &options).await;
+        deliver_all(&mut sinks, &routed, messages, &options, &mut breakers).await;
+    }
+
+    // Let held messages out at their own pace, unless shutting down
+    if drain_deadline.is_none() {
+        while actions.iter().any(|a| a.pending()) {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let messages = release(&mut actions, false, &options).await;
+                    deliver_all(&mut sinks, &routed, messages, &options, &mut breakers).await;
                 }
+                _ = shutdown.wait() => break,
             }
-            messages = next_messages;
         }
+    }
+
+    // Finalize actions (for aggregates, etc.), delivering what they release
+    // before the drain deadline when shutting down
+    let held = release(&mut actions, true, &options).await;
+    let acks: Vec<_> = held.iter().filter_map(|m| m.ack.clone()).collect();
+    let undelivered = held.len();
+    let delivered = until_drained(&shutdown, drain_deadline, deliver_all(&mut sinks, &routed, held, &options, &mut breakers)).await;
 
-        // Send to all sinks
-        for msg in messages {
+    // Flush all sinks, bounded by the drain deadline when shutting down
+    let mut flush_error = None;
+    match delivered {
+        Some(()) => {
             for sink in &mut sinks {
-                if let Err(e) = sink.send(msg.clone()).await {
-                    eprintln!("Sink error: {}", e);
+                let flushed = until_drained(&shutdown, drain_deadline, sink.flush())
+                    .await
+                    .unwrap_or_else(|| Err(anyhow::anyhow!("flush did not finish before the drain deadline")));
+                if let Err(e) = flushed {
+                    tracing::error!(sink = sink.name(), error = %e, "Flush failed");
+                    flush_error.get_or_insert(e.context(format!("Failed to flush {} sink", sink.name())));
                 }
             }
         }
+        None => {
+            // Cut off mid-retry: keep the source from committing past them
+            acks.iter().for_each(|ack| ack.nack());
+            tracing::warn!(undelivered, "Drain deadline reached, held messages left undelivered");
+        }
     }
 
-    // Finalize actions (for aggregates, etc.)
-    for action in &mut actions {
-        match action.finalize().await {
-            Ok(final_messages) => {
-                for msg in final_messages {
-                    for sink in &mut sinks {
-                        if let Err(e) = sink.send(msg.clone()).await {
-                            eprintln!("Sink error during finalize: {}", e);
-                        }
+    // Wait for source to complete
+    match source_handle.await {
+        Ok(result) => result?,
+        Err(e) if e.is_cancelled() => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    // Unwritten data fails the run
+    if let Some(e) = flush_error {
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Run messages through `actions`, which start at chain position `offset`.
+async fn apply_actions(
+    actions: &mut [Box<dyn PipeAction>],
+    offset: usize,
+    mut messages: Vec<Message>,
+    options: &PipelineOptions,
+) -> Vec<Message> {
+    for (i, action) in actions.iter_mut().enumerate() {
+        let mut next_messages = vec![];
+        for m in messages {
+            let ack = m.ack.clone();
+            let span = tracing::debug_span!("stage", stage = action.name(), index = offset + i);
+            trace::follow(&span, &m);
+            let result = action.execute(m).instrument(span).await;
+            if let Some(ref metrics) = options.metrics {
+                let emitted = result.as_ref().map(|r| r.len()).unwrap_or(0);
+                metrics.record_stage(action.name(), offset + i, emitted, action.buffers());
+            }
+            match result {
+                Ok(mut results) => next_messages.append(&mut results),
+                Err(e) => {
+                    tracing::warn!(stage = action.name(), error = %e, "Action failed");
+                    if let Some(ack) = ack {
+                        ack.nack();
                     }
                 }
             }
-            Err(e) => eprintln!("Finalize error: {}", e),
         }
+        if next_messages.is_empty() {
+            return next_messages;
+        }
+        messages = next_messages;
     }
+    messages
+}
 
-    // Flush all sinks
-    for sink in &mut sinks {
-        if let Err(e) = sink.flush().await {
-            eprintln!("Flush error: {}", e);
+/// Tick (or finalize) every action in order; whatever one releases still
+/// passes through the actions after it.
+async fn release(actions: &mut [Box<dyn PipeAction>], finalize: bool, options: &PipelineOptions) -> Vec<Message> {
+    let mut out = vec![];
+    for i in 0..actions.len() {
+        let released = if finalize {
+            actions[i].finalize().await
+        } else {
+            actions[i].tick().await
+        };
+        let released = match released {
+            Ok(released) if !released.is_empty() => released,
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::warn!(stage = actions[i].name(), index = i, error = %e, "{} failed", if finalize { "Finalize" } else { "Tick" });
+                continue;
+            }
+        };
+        if let Some(ref metrics) = options.metrics {
+            let index = i.to_string();
+            let labels = [("stage", actions[i].name()), ("index", index.as_str())];
+            metrics.inc(STAGE_OUT, &labels, released.len() as u64);
         }
+        out.extend(apply_actions(&mut actions[i + 1..], i + 1, released, options).await);
     }
+    out
+}
 
-    // Wait for source to complete
-    source_handle.await??;
+async fn deliver_all(
+    sinks: &mut [Box<dyn Sink>],
+    routed: &[bool],
+    messages: Vec<Message>,
+    options: &PipelineOptions,
+    breakers: &mut [Option<CircuitBreaker>],
+) {
+    if messages.is_empty() {
+        return;
+    }
+    for (i, (sink, breaker)) in sinks.iter_mut().zip(breakers.iter_mut()).enumerate() {
+        let index = i.to_string();
+        let batch: Vec<Message> = messages.iter().filter(|m| match m.meta.get(ROUTE_META) {
+            Some(to) => to.split(',').any(|to| to == index),
+            None => !routed[i],
+        }).cloned().collect();
+        deliver(sink.as_mut(), batch, options, breaker).await;
+    }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ack::Ack;
+    use crate::pipe_actions::{AggregateAction, RouteAction, RouteMode};
+    use crate::retry::{transient, RetryPolicy};
+    use crate::shutdown::Shutdown;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Sends its messages, then runs until stopped.
+    struct OpenSource(Vec<Message>);
+
+    #[async_trait]
+    impl Source for OpenSource {
+        async fn start(&mut self, out: mpsc::Sender<Message>) -> anyhow::Result<()> {
+            for msg in self.0.drain(..) {
+                out.send(msg).await?;
+            }
+            std::future::pending().await
+        }
+    }
+
+    /// Fails every send, every flush, or neither.
+    struct FailingSink {
+        send: bool,
+        flush: bool,
+    }
+
+    #[async_trait]
+    impl Sink for FailingSink {
+        async fn send(&mut self, _msg: Message) -> anyhow::Result<()> {
+            match self.send {
+                true => Err(transient(anyhow::anyhow!("unavailable"))),
+                false => Ok(()),
+            }
+        }
+        async fn flush(&mut self) -> anyhow::Result<()> {
+            match self.flush {
+                true => Err(anyhow::anyhow!("disk full")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    fn message(acked: &Arc<AtomicUsize>) -> Message {
+        let acked = acked.clone();
+        Message {
+            id: None,
+            parents: Vec::new(),
+            key: None,
+            payload: br#"{"n":1}"#.to_vec(),
+            format: None,
+            parsed: Some(serde_json::json!({ "n": 1 })),
+            timestamp: chrono::Utc::now(),
+            headers: Default::default(),
+            meta: Default::default(),
+            ack: Some(Ack::new(move || {
+                acked.fetch_add(1, Ordering::SeqCst);
+            })),
+        }
+    }
+
+    fn stop_after(shutdown: &Shutdown, delay: Duration) {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            shutdown.trigger();
+        });
+    }
+
+    #[tokio::test]
+    async fn finalize_delivery_stops_at_the_drain_deadline() {
+        let acked = Arc::new(AtomicUsize::new(0));
+        let shutdown = Shutdown::new(Duration::from_millis(100));
+        let options = PipelineOptions {
+            shutdown: shutdown.clone(),
+            retry: Some(RetryPolicy::new(1000, Duration::from_millis(10))),
+            ..Default::default()
+        };
+        let aggregate = AggregateAction::new(None, vec![("count".to_string(), "n".to_string())]);
+        stop_after(&shutdown, Duration::from_millis(50));
+
+        let run = run_pipe(
+            Box::new(OpenSource(vec![message(&acked)])),
+            vec![Box::new(aggregate)],
+            vec![Box::new(FailingSink { send: true, flush: false })],
+            options,
+        );
+        let result = tokio::time::timeout(Duration::from_secs(5), run).await.expect("run past the drain deadline");
+        assert!(result.is_ok());
+        assert_eq!(acked.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn flush_failures_fail_the_run() {
+        let acked = Arc::new(AtomicUsize::new(0));
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let options = PipelineOptions { shutdown: shutdown.clone(), ..Default::default() };
+        stop_after(&shutdown, Duration::from_millis(50));
+
+        let sinks: Vec<Box<dyn Sink>> = vec![
+            Box::new(FailingSink { send: false, flush: false }),
+            Box::new(FailingSink { send: false, flush: true }),
+        ];
+        let result = run_pipe(Box::new(OpenSource(vec![message(&acked)])), vec![], sinks, options).await;
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("disk full"), "{error}");
+    }
+
+    /// Keeps the `level` of every message it is sent.
+    struct Recording(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl Sink for Recording {
+        async fn send(&mut self, msg: Message) -> anyhow::Result<()> {
+            let level = msg.parsed.as_ref().unwrap()["level"].as_str().unwrap().to_string();
+            self.0.lock().unwrap().push(level);
+            Ok(())
+        }
+        async fn flush(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn event(level: &str, acked: &Arc<AtomicUsize>) -> Message {
+        let mut msg = message(acked);
+        msg.parsed = Some(serde_json::json!({ "level": level }));
+        msg.payload = msg.parsed.as_ref().unwrap().to_string().into_bytes();
+        msg
+    }
+
+    /// Run `route` over one message of each level into `sinks` recording
+    /// sinks; returns what each received and how many messages were acked.
+    async fn run_routed(route: RouteAction, sinks: usize) -> (Vec<Vec<String>>, usize) {
+        let acked = Arc::new(AtomicUsize::new(0));
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let options = PipelineOptions { shutdown: shutdown.clone(), ..Default::default() };
+        let received: Vec<Arc<std::sync::Mutex<Vec<String>>>> = (0..sinks).map(|_| Arc::default()).collect();
+        let boxed = received.iter().map(|r| Box::new(Recording(Arc::clone(r))) as Box<dyn Sink>).collect();
+        let source = OpenSource(["error", "warn", "info"].iter().map(|level| event(level, &acked)).collect());
+        stop_after(&shutdown, Duration::from_millis(50));
+
+        run_pipe(Box::new(source), vec![Box::new(route)], boxed, options).await.unwrap();
+        let received = received.iter().map(|r| r.lock().unwrap().clone()).collect();
+        (received, acked.load(Ordering::SeqCst))
+    }
+
+    fn levels() -> RouteAction {
+        RouteAction::new(RouteMode::First)
+            .rule(crate::expr::Expr::parse("level == \"error\"").unwrap(), vec![0])
+            .rule(crate::expr::Expr::parse("level != \"info\"").unwrap(), vec![1])
+    }
+
+    #[tokio::test]
+    async fn routed_messages_reach_only_their_sinks() {
+        // Sink 2 is no route's target, so it gets the unrouted messages
+        let (received, acked) = run_routed(levels(), 3).await;
+        assert_eq!(received, vec![vec!["error"], vec!["warn"], vec!["info"]]);
+        assert_eq!(acked, 3);
+    }
+
+    #[tokio::test]
+    async fn default_routes_take_unmatched_messages() {
+        let (received, acked) = run_routed(levels().default_to(vec![0]), 3).await;
+        assert_eq!(received, vec![vec!["error", "info"], vec!["warn"], vec![]]);
+        assert_eq!(acked, 3);
+    }
+
+    #[tokio::test]
+    async fn unrouted_messages_without_a_sink_are_acknowledged() {
+        // Every sink is a route's target: the info message goes nowhere, and is done with
+        let (received, acked) = run_routed(levels(), 2).await;
+        assert_eq!(received, vec![vec!["error"], vec!["warn"]]);
+        assert_eq!(acked, 3);
+    }
+
+    #[tokio::test]
+    async fn routes_to_missing_sinks_are_rejected() {
+        let route = RouteAction::new(RouteMode::First).default_to(vec![2]);
+        let options = PipelineOptions::default();
+        let sinks: Vec<Box<dyn Sink>> = vec![Box::new(FailingSink { send: false, flush: false })];
+        let error = run_pipe(Box::new(OpenSource(vec![])), vec![Box::new(route)], sinks, options).await.unwrap_err();
+        assert!(error.to_string().contains("sink 2"), "{error}");
+    }
 }
@@ -0,0 +1,213 @@
+//! Builder API for embedding a pipeline in another program.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use fluxmux_core::middleware::Deduplicator;
+//! use fluxmux_core::pipe_actions::FilterAction;
+//! use fluxmux_core::pipeline::Pipeline;
+//! # let (source, sink): (Box<dyn fluxmux_core::traits::Source>, Box<dyn fluxmux_core::traits::Sink>) = todo!();
+//!
+//! let pipeline = Pipeline::builder()
+//!     .source(source)
+//!     .middleware(Deduplicator::new())
+//!     .action(FilterAction::new("temp>30".to_string())?)
+//!     .sink(sink)
+//!     .build()?;
+//!
+//! let handle = pipeline.clone();
+//! tokio::spawn(async move { handle.run().await });
+//! // ... later
+//! println!("{:?}", pipeline.stats());
+//! pipeline.shutdown();
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::dead_letter::DeadLetterSender;
+use crate::engine::PipelineOptions;
+use crate::lineage::Lineage;
+use crate::event_time::EventTime;
+use crate::metrics::{Metrics, MESSAGES_RECEIVED, SINK_ERRORS, SINK_MESSAGES, SINK_RETRIES, STAGE_DROPPED};
+use crate::middleware::Middleware;
+use crate::pipe_actions::{MiddlewareAction, PipeAction};
+use crate::pipe_engine::run_pipe;
+use crate::retry::{CircuitBreaker, RetryPolicy};
+use crate::shutdown::{Shutdown, DEFAULT_DRAIN_TIMEOUT_MS};
+use crate::traits::{Sink, Source};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::Instrument;
+
+/// Assembles a pipeline: one source, middleware and actions applied in the
+/// order they are added, and one or more sinks that each receive every message
+/// (except sinks a `RouteAction` sends to, which get what it routes there).
+#[derive(Default)]
+pub struct PipelineBuilder {
+    source: Option<Box<dyn Source>>,
+    stages: Vec<Box<dyn PipeAction>>,
+    sinks: Vec<Box<dyn Sink>>,
+    options: PipelineOptions,
+    drain_timeout: Option<Duration>,
+}
+
+impl PipelineBuilder {
+    pub fn source(mut self, source: impl Source + 'static) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// Add a bridge middleware (dedup, batch, throttle, ...) as the next stage.
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.stages.push(Box::new(MiddlewareAction::new(Box::new(middleware))));
+        self
+    }
+
+    /// Add a pipe action (filter, transform, aggregate, ...) as the next stage.
+    pub fn action(mut self, action: impl PipeAction + 'static) -> Self {
+        self.stages.push(Box::new(action));
+        self
+    }
+
+    pub fn sink(mut self, sink: impl Sink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Where undeliverable messages go; without one they are left unacknowledged.
+    pub fn dead_letter(mut self, dead_letter: DeadLetterSender) -> Self {
+        self.options.dead_letter = Some(dead_letter);
+        self
+    }
+
+    /// Stamp lineage headers (source, ingest time, IDs, ...) on every message.
+    pub fn lineage(mut self, lineage: Lineage) -> Self {
+        self.options.lineage = Some(lineage);
+        self
+    }
+
+    /// Set each message's timestamp from a payload field instead of the ingest time.
+    pub fn event_time(mut self, event_time: EventTime) -> Self {
+        self.options.event_time = Some(event_time);
+        self
+    }
+
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.options.retry = Some(policy);
+        self
+    }
+
+    pub fn circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.options.circuit_breaker = Some(breaker);
+        self
+    }
+
+    /// Time allowed to drain and flush after `Pipeline::shutdown`.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = Some(timeout);
+        self
+    }
+
+    /// Use an existing shutdown coordinator, e.g. one listening for signals.
+    /// Overrides `drain_timeout`.
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.options.shutdown = shutdown;
+        self.drain_timeout = None;
+        self
+    }
+
+    /// Record into an existing registry (e.g. one already served over HTTP);
+    /// otherwise the pipeline keeps its own for `Pipeline::stats`.
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.options.metrics = Some(metrics);
+        self
+    }
+
+    pub fn build(mut self) -> anyhow::Result<Pipeline> {
+        let source = self.source.ok_or_else(|| anyhow::anyhow!("Pipeline has no source"))?;
+        if self.sinks.is_empty() {
+            anyhow::bail!("Pipeline has no sink");
+        }
+        if let Some(timeout) = self.drain_timeout {
+            self.options.shutdown = Shutdown::new(timeout);
+        }
+        let metrics = self.options.metrics.get_or_insert_with(Metrics::new).clone();
+        Ok(Pipeline {
+            inner: Arc::new(Inner {
+                parts: Mutex::new(Some((source, self.stages, self.sinks))),
+                options: self.options,
+                metrics,
+            }),
+        })
+    }
+}
+
+type Parts = (Box<dyn Source>, Vec<Box<dyn PipeAction>>, Vec<Box<dyn Sink>>);
+
+struct Inner {
+    parts: Mutex<Option<Parts>>,
+    options: PipelineOptions,
+    metrics: Metrics,
+}
+
+/// Handle to a built pipeline. Clones share the same pipeline, so one task
+/// can `run` it while another calls `shutdown` or reads `stats`.
+#[derive(Clone)]
+pub struct Pipeline {
+    inner: Arc<Inner>,
+}
+
+/// Message counts since the pipeline started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    /// Taken from the source
+    pub received: u64,
+    /// Written by sinks, counted once per sink
+    pub delivered: u64,
+    /// Discarded by a stage (filtered, deduplicated, rejected)
+    pub dropped: u64,
+    /// Failed sink send attempts
+    pub sink_errors: u64,
+    /// Sink sends retried
+    pub retries: u64,
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default().drain_timeout(Duration::from_millis(DEFAULT_DRAIN_TIMEOUT_MS))
+    }
+
+    /// Run until the source is exhausted or `shutdown` is called, then
+    /// finalize stages and flush sinks. A pipeline can only run once.
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let parts = self.inner.parts.lock().unwrap().take();
+        let (source, stages, sinks) = parts.ok_or_else(|| anyhow::anyhow!("Pipeline has already been run"))?;
+        run_pipe(source, stages, sinks, self.inner.options.clone())
+            .instrument(tracing::info_span!("pipeline"))
+            .await
+    }
+
+    /// Stop reading from the source and drain what is already in flight.
+    pub fn shutdown(&self) {
+        self.inner.options.shutdown.trigger();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.inner.options.shutdown.is_triggered()
+    }
+
+    pub fn stats(&self) -> PipelineStats {
+        let metrics = &self.inner.metrics;
+        PipelineStats {
+            received: metrics.total(MESSAGES_RECEIVED),
+            delivered: metrics.total(SINK_MESSAGES),
+            dropped: metrics.total(STAGE_DROPPED),
+            sink_errors: metrics.total(SINK_ERRORS),
+            retries: metrics.total(SINK_RETRIES),
+        }
+    }
+
+    /// The registry the pipeline records into, e.g. to serve it with `Metrics::serve`.
+    pub fn metrics(&self) -> &Metrics {
+        &self.inner.metrics
+    }
+}
@@ -0,0 +1,252 @@
+use crate::message::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::time::{Duration, Instant};
+
+/// Buckets kept before idle (full) ones are evicted; a full bucket behaves
+/// exactly like a new one, so evicting it changes nothing.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+/// What happens to a message that arrives while its bucket is empty.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverLimit {
+    /// Hold the message until a token is available
+    #[default]
+    Delay,
+    /// Discard the message
+    Drop,
+    /// Hand the message to a side sink (see `RateLimitConfig::route_to`)
+    Route,
+}
+
+impl std::str::FromStr for OverLimit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delay" => Ok(Self::Delay),
+            "drop" => Ok(Self::Drop),
+            "route" => Ok(Self::Route),
+            other => Err(anyhow::anyhow!("Unknown rate limit mode '{}', expected delay, drop or route", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RateLimitConfig {
+    pub rate_per_sec: f64,
+    pub burst: Option<u32>, // defaults to one second's worth of tokens
+    #[serde(default)]
+    pub per_key: bool, // one bucket per Message.key
+    pub per_field: Option<String>, // one bucket per value of this payload field
+    #[serde(default)]
+    pub mode: OverLimit,
+    pub route_to: Option<String>, // sink URI for `route`; defaults to the dead-letter sink
+}
+
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate_per_sec: f64, burst: u32) -> Self {
+        let capacity = burst.max(1) as f64;
+        Self { capacity, tokens: capacity, rate: rate_per_sec, last: Instant::now() }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+
+    /// Take a token, or return how long until one is available.
+    pub fn try_take(&mut self) -> Result<(), Duration> {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+}
+
+/// Token-bucket limiter, optionally keeping one bucket per message key or
+/// per payload field value.
+pub struct RateLimiter {
+    rate: f64,
+    burst: u32,
+    per_key: bool,
+    per_field: Option<String>,
+    buckets: HashMap<String, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(rate_per_sec: f64, burst: u32) -> Self {
+        Self {
+            rate: rate_per_sec.max(f64::MIN_POSITIVE),
+            burst,
+            per_key: false,
+            per_field: None,
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn from_config(cfg: &RateLimitConfig) -> Self {
+        let burst = cfg.burst.unwrap_or(cfg.rate_per_sec.ceil().max(1.0) as u32);
+        let mut limiter = Self::new(cfg.rate_per_sec, burst);
+        limiter.per_key = cfg.per_key;
+        limiter.per_field = cfg.per_field.clone();
+        limiter
+    }
+
+    pub fn per_key(mut self) -> Self {
+        self.per_key = true;
+        self
+    }
+
+    pub fn per_field(mut self, field: String) -> Self {
+        self.per_field = Some(field);
+        self
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Bucket a message is counted against.
+    pub fn key_for(&self, msg: &Message) -> String {
+        if let Some(ref field) = self.per_field {
+            return match msg.parsed.as_ref().and_then(|v| v.get(field)) {
+                Some(serde_json::Value::String(s)) => s.clone(),
+                Some(other) => other.to_string(),
+                None => String::new(),
+            };
+        }
+        if self.per_key {
+            if let Some(ref key) = msg.key {
+                return String::from_utf8_lossy(key).to_string();
+            }
+        }
+        String::new()
+    }
+
+    pub fn try_acquire(&mut self, key: &str) -> Result<(), Duration> {
+        if !self.buckets.contains_key(key) && self.buckets.len() >= MAX_IDLE_BUCKETS {
+            self.buckets.retain(|_, bucket| !bucket.is_full());
+        }
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets
+            .entry(key.to_string())
+            .or_insert_with(|| TokenBucket::new(rate, burst))
+            .try_take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(key: Option<&str>, payload: serde_json::Value) -> Message {
+        Message {
+            id: None,
+            parents: Vec::new(),
+            key: key.map(|k| k.as_bytes().to_vec()),
+            payload: payload.to_string().into_bytes(),
+            format: None,
+            parsed: Some(payload),
+            timestamp: chrono::Utc::now(),
+            headers: Default::default(),
+            meta: Default::default(),
+            ack: None,
+        }
+    }
+
+    fn assert_wait(result: Result<(), Duration>, millis: u64) {
+        let wait = result.unwrap_err();
+        let expected = Duration::from_millis(millis);
+        assert!(wait.abs_diff(expected) < Duration::from_micros(10), "{wait:?} != {expected:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bucket_allows_a_burst_then_refills_at_the_rate() {
+        let mut bucket = TokenBucket::new(10.0, 3);
+        for _ in 0..3 {
+            assert!(bucket.try_take().is_ok());
+        }
+        assert_wait(bucket.try_take(), 100);
+
+        tokio::time::advance(Duration::from_millis(40)).await;
+        assert_wait(bucket.try_take(), 60);
+        tokio::time::advance(Duration::from_millis(60)).await;
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bucket_refills_no_further_than_the_burst() {
+        let mut bucket = TokenBucket::new(10.0, 2);
+        bucket.try_take().unwrap();
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_err());
+
+        // A burst of zero still lets single messages through
+        let mut bucket = TokenBucket::new(10.0, 0);
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_defaults_to_one_second_of_tokens() {
+        let cfg: RateLimitConfig = serde_json::from_value(serde_json::json!({ "rate_per_sec": 2.5 })).unwrap();
+        assert_eq!(cfg.mode, OverLimit::Delay);
+        let mut limiter = RateLimiter::from_config(&cfg);
+        for _ in 0..3 {
+            assert!(limiter.try_acquire("").is_ok());
+        }
+        assert_wait(limiter.try_acquire(""), 400);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keys_get_their_own_buckets() {
+        let mut limiter = RateLimiter::new(1.0, 1).per_key();
+        let (a, b) = (message(Some("a"), serde_json::json!({})), message(Some("b"), serde_json::json!({})));
+        assert!(limiter.try_acquire(&limiter.key_for(&a)).is_ok());
+        assert!(limiter.try_acquire(&limiter.key_for(&a)).is_err());
+        assert!(limiter.try_acquire(&limiter.key_for(&b)).is_ok());
+
+        // Messages without a key share one bucket
+        let unkeyed = message(None, serde_json::json!({}));
+        assert_eq!(limiter.key_for(&unkeyed), "");
+    }
+
+    #[test]
+    fn field_values_pick_the_bucket() {
+        let limiter = RateLimiter::new(1.0, 1).per_field("tenant".to_string()).per_key();
+        let bucket = |payload| limiter.key_for(&message(Some("k"), payload));
+        // The field wins over the message key
+        assert_eq!(bucket(serde_json::json!({ "tenant": "acme" })), "acme");
+        assert_eq!(bucket(serde_json::json!({ "tenant": 7 })), "7");
+        assert_eq!(bucket(serde_json::json!({ "other": 1 })), "");
+    }
+
+    #[test]
+    fn modes_parse() {
+        assert_eq!("drop".parse::<OverLimit>().unwrap(), OverLimit::Drop);
+        assert_eq!("route".parse::<OverLimit>().unwrap(), OverLimit::Route);
+        assert!("block".parse::<OverLimit>().is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::middleware::MiddlewareChain;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use tracing::Instrument;
+
+/// How often watched files are checked by default.
+pub const DEFAULT_RELOAD_INTERVAL_MS: u64 = 1000;
+
+/// Rebuilds the middleware chain when any of its files (config, schema)
+/// changes, for `run_pipeline` to swap in between messages.
+///
+/// Files are polled for a new modification time. A rebuild that fails keeps
+/// the running chain.
+#[derive(Clone)]
+pub struct ChainReloader {
+    pending: Arc<Mutex<Option<MiddlewareChain>>>,
+    notify: Arc<Notify>,
+    task: Arc<JoinHandle<()>>,
+}
+
+impl ChainReloader {
+    /// `build` returns the new chain and the files it was built from, which
+    /// are watched from then on.
+    pub fn watch<F>(watched: Vec<PathBuf>, interval: Duration, mut build: F) -> Self
+    where
+        F: FnMut() -> anyhow::Result<(MiddlewareChain, Vec<PathBuf>)> + Send + 'static,
+    {
+        let pending = Arc::new(Mutex::new(None));
+        let notify = Arc::new(Notify::new());
+        let (slot, wake) = (Arc::downgrade(&pending), notify.clone());
+
+        let task = tokio::spawn(async move {
+            let mut stamps = modified_times(&watched);
+            let mut watched = watched;
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let current = modified_times(&watched);
+                if current == stamps {
+                    continue;
+                }
+                stamps = current;
+                let Some(slot) = slot.upgrade() else { break };
+                match build() {
+                    Ok((chain, files)) => {
+                        tracing::info!("Configuration changed, reloading middleware chain");
+                        *slot.lock().unwrap() = Some(chain);
+                        wake.notify_one();
+                        if files != watched {
+                            stamps = modified_times(&files);
+                            watched = files;
+                        }
+                    }
+                    Err(e) => tracing::warn!(error = format!("{:#}", e), "Rejected configuration change, keeping the current one"),
+                }
+            }
+        }.in_current_span());
+
+        Self { pending, notify, task: Arc::new(task) }
+    }
+
+    /// Waits for the next successfully rebuilt chain.
+    pub async fn next(&self) -> MiddlewareChain {
+        loop {
+            if let Some(chain) = self.pending.lock().unwrap().take() {
+                return chain;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Stop watching; the build closure (and anything it holds) is dropped.
+    pub fn stop(&self) {
+        self.task.abort();
+    }
+}
+
+fn modified_times(paths: &[PathBuf]) -> HashMap<PathBuf, Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| {
+            let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+            (path.clone(), modified)
+        })
+        .collect()
+}
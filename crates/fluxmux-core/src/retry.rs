@@ -0,0 +1,251 @@
+use std::fmt;
+use tokio::time::{Duration, Instant};
+
+/// Marks a sink error as worth retrying (timeouts, lost connections, full
+/// queues). Errors not marked this way are treated as permanent.
+#[derive(Debug)]
+pub struct TransientError(pub anyhow::Error);
+
+impl fmt::Display for TransientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for TransientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+pub fn transient(err: impl Into<anyhow::Error>) -> anyhow::Error {
+    anyhow::Error::new(TransientError(err.into()))
+}
+
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| e.is::<TransientError>())
+}
+
+/// Marks a batch error that only some records of the batch ran into; the
+/// others were delivered. `failed` holds the failed records' positions in the
+/// batch, and only those are retried or dead-lettered.
+#[derive(Debug)]
+pub struct PartialFailure {
+    pub failed: Vec<usize>,
+    pub error: anyhow::Error,
+}
+
+impl fmt::Display for PartialFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} records failed: {}", self.failed.len(), self.error)
+    }
+}
+
+impl std::error::Error for PartialFailure {
+    // The underlying error stays in the chain, so it can still be transient
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+pub fn partial_failure(failed: Vec<usize>, err: impl Into<anyhow::Error>) -> anyhow::Error {
+    anyhow::Error::new(PartialFailure { failed, error: err.into() })
+}
+
+/// Positions of the failed records, when only some records of a batch failed.
+pub fn failed_records(err: &anyhow::Error) -> Option<&[usize]> {
+    err.chain().find_map(|e| e.downcast_ref::<PartialFailure>()).map(|p| p.failed.as_slice())
+}
+
+/// Exponential backoff for sink retries. Only transient errors are retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    pub jitter: f64, // fraction of each delay that is randomized, 0.0..=1.0
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_delay: Duration::from_millis(1000),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, initial_delay: Duration) -> Self {
+        Self { max_retries, initial_delay, ..Self::default() }
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn should_retry(&self, attempt: u32, err: &anyhow::Error) -> bool {
+        attempt < self.max_retries && is_transient(err)
+    }
+
+    /// Delay before retry number `attempt` (starting at 1).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(64) as i32;
+        let base = (self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_delay.as_secs_f64());
+        // Subtract a random share so concurrent retries spread out
+        let jittered = base * (1.0 - self.jitter * rand::random::<f64>());
+        Duration::from_secs_f64(jittered)
+    }
+}
+
+/// Stops delivery to a sink after repeated transient failures, so an outage
+/// pauses consumption instead of using up every message's retries.
+///
+/// After `failure_threshold` consecutive failures the breaker opens for
+/// `reset_timeout`; the next send is then a probe that either closes it again
+/// or reopens it.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    reset_timeout: Duration,
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, reset_timeout: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            reset_timeout,
+            consecutive_failures: 0,
+            open_until: None,
+        }
+    }
+
+    /// When the breaker is open, the instant at which it may be probed.
+    pub fn open_until(&self) -> Option<Instant> {
+        self.open_until.filter(|until| *until > Instant::now())
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open_until().is_some()
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.open_until = None;
+    }
+
+    /// Returns true when this failure opens the breaker.
+    pub fn record_failure(&mut self) -> bool {
+        self.consecutive_failures += 1;
+        if self.consecutive_failures >= self.failure_threshold {
+            self.open_until = Some(Instant::now() + self.reset_timeout);
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_up_to_the_cap() {
+        let policy = RetryPolicy::new(10, Duration::from_millis(100))
+            .with_max_delay(Duration::from_millis(500))
+            .with_jitter(0.0);
+        let delays: Vec<u128> = (1..=5).map(|attempt| policy.delay(attempt).as_millis()).collect();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+        assert_eq!(policy.delay(u32::MAX), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn jitter_only_shortens_delays() {
+        let policy = RetryPolicy::new(10, Duration::from_millis(1000)).with_jitter(0.5);
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_millis(1000), "{delay:?}");
+        }
+    }
+
+    #[test]
+    fn settings_are_clamped() {
+        let policy = RetryPolicy::new(1, Duration::from_millis(100)).with_multiplier(0.5).with_jitter(2.0);
+        assert_eq!(policy.multiplier, 1.0);
+        assert_eq!(policy.jitter, 1.0);
+        assert_eq!(CircuitBreaker::new(0, Duration::from_secs(1)).failure_threshold, 1);
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        let policy = RetryPolicy::new(2, Duration::from_millis(100));
+        let temporary = transient(anyhow::anyhow!("timed out"));
+        let permanent = anyhow::anyhow!("unknown topic");
+        assert!(policy.should_retry(0, &temporary));
+        assert!(policy.should_retry(1, &temporary));
+        assert!(!policy.should_retry(2, &temporary));
+        assert!(!policy.should_retry(0, &permanent));
+
+        // Context added on the way up keeps an error transient
+        assert!(is_transient(&temporary.context("Failed to send batch")));
+        assert!(is_transient(&partial_failure(vec![1], transient(anyhow::anyhow!("queue full")))));
+        assert!(!is_transient(&partial_failure(vec![1], permanent)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn breaker_opens_after_consecutive_failures() {
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(10));
+        assert!(!breaker.record_failure());
+        assert!(!breaker.record_failure());
+        breaker.record_success();
+
+        // A success resets the count
+        assert!(!breaker.record_failure());
+        assert!(!breaker.record_failure());
+        assert!(!breaker.is_open());
+        assert!(breaker.record_failure());
+        assert!(breaker.is_open());
+        assert_eq!(breaker.open_until(), Some(Instant::now() + Duration::from_secs(10)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn breaker_half_opens_after_the_cooldown() {
+        let mut breaker = CircuitBreaker::new(2, Duration::from_secs(10));
+        breaker.record_failure();
+        assert!(breaker.record_failure());
+
+        tokio::time::advance(Duration::from_secs(9)).await;
+        assert!(breaker.is_open());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(!breaker.is_open());
+
+        // A failed probe reopens it right away, for another cooldown
+        assert!(breaker.record_failure());
+        assert!(breaker.is_open());
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(!breaker.is_open());
+
+        // A successful probe closes it
+        breaker.record_success();
+        assert!(!breaker.record_failure());
+        assert!(!breaker.is_open());
+    }
+}
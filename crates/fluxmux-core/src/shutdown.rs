@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+/// Process exit code for a pipeline that was stopped by SIGINT/SIGTERM and drained.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Default time allowed for draining and flushing once shutdown is requested.
+pub const DEFAULT_DRAIN_TIMEOUT_MS: u64 = 10_000;
+
+/// Shutdown coordinator shared between the signal listener and the engines.
+///
+/// Once triggered, the engine stops the source, processes what is already
+/// queued, finalizes stateful stages and flushes every sink, giving up on
+/// whatever is left when the drain deadline passes.
+#[derive(Clone)]
+pub struct Shutdown {
+    tx: Arc<watch::Sender<bool>>,
+    rx: watch::Receiver<bool>,
+    drain_timeout: Duration,
+}
+
+impl Shutdown {
+    pub fn new(drain_timeout: Duration) -> Self {
+        let (tx, rx) = watch::channel(false);
+        Self { tx: Arc::new(tx), rx, drain_timeout }
+    }
+
+    pub fn trigger(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Resolves once shutdown has been requested.
+    pub async fn wait(&self) {
+        let mut rx = self.rx.clone();
+        // The sender lives as long as any clone of self, so this cannot fail
+        let _ = rx.wait_for(|triggered| *triggered).await;
+    }
+
+    pub fn drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+
+    pub fn drain_deadline(&self) -> Instant {
+        Instant::now() + self.drain_timeout
+    }
+
+    /// Trigger shutdown on Ctrl-C (SIGINT) or SIGTERM.
+    pub fn listen_for_signals(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            tracing::info!("Shutdown requested, draining pipeline");
+            shutdown.trigger();
+        });
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(DEFAULT_DRAIN_TIMEOUT_MS))
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = sigterm.recv() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,492 @@
+//! Windowed join of two sources.
+//!
+//! `StreamJoin` is itself a `Source`: it runs a left and a right source,
+//! buffers each record by its key for the join window, and emits one message
+//! per pair of left and right records with equal keys that arrive within the
+//! window of each other, e.g. an order and its payment:
+//!
+//! ```json
+//! {"orders": {"order_id": 7, "total": 30}, "payments": {"order_id": 7, "paid": 30}}
+//! ```
+//!
+//! A record is kept for the whole window, so it joins every match that
+//! arrives in time. Records that never match are dropped, emitted alone (the
+//! other side `null`) or routed to a side sink, per `Unmatched`.
+
+use crate::ack::Ack;
+use crate::dead_letter::DeadLetterSender;
+use crate::lineage::Parents;
+use crate::lookup::key_string;
+use crate::message::{Format, Message};
+use crate::traits::Source;
+use crate::union::spawn_input;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+
+/// What happens to a record that found no match within the window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unmatched {
+    /// Discard the record
+    #[default]
+    Drop,
+    /// Emit it alone, with the other side `null` (an outer join)
+    Emit,
+    /// Hand the record to a side sink
+    Route,
+}
+
+impl std::str::FromStr for Unmatched {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(Self::Drop),
+            "emit" => Ok(Self::Emit),
+            "route" => Ok(Self::Route),
+            other => Err(anyhow::anyhow!("Unknown unmatched policy '{}', expected drop, emit or route", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for Unmatched {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Drop => write!(f, "drop"),
+            Self::Emit => write!(f, "emit"),
+            Self::Route => write!(f, "route"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StreamJoinConfig {
+    pub with: String, // URI of the right source
+    pub on: String, // key field (dotted path) of left records
+    pub right_on: Option<String>, // key field of right records; defaults to `on`
+    pub window_ms: u64, // how far apart matching records may arrive
+    #[serde(default)]
+    pub unmatched: Unmatched,
+    pub unmatched_to: Option<String>, // sink URI for `route`; defaults to the dead-letter sink
+    pub left_as: Option<String>, // field holding the left record (default "left")
+    pub right_as: Option<String>, // field holding the right record (default "right")
+}
+
+impl StreamJoinConfig {
+    pub fn right_on(&self) -> &str {
+        self.right_on.as_deref().unwrap_or(&self.on)
+    }
+
+    pub fn left_as(&self) -> &str {
+        self.left_as.as_deref().unwrap_or("left")
+    }
+
+    pub fn right_as(&self) -> &str {
+        self.right_as.as_deref().unwrap_or("right")
+    }
+}
+
+impl std::fmt::Display for StreamJoinConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} on={} right_on={} window_ms={} unmatched={} as={},{}",
+            self.with, self.on, self.right_on(), self.window_ms, self.unmatched, self.left_as(), self.right_as()
+        )?;
+        match (self.unmatched, &self.unmatched_to) {
+            (_, Some(to)) => write!(f, " unmatched_to={}", to),
+            (Unmatched::Route, None) => write!(f, " unmatched_to=(dead letter)"),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct StreamJoin {
+    left: Option<Box<dyn Source>>,
+    right: Option<Box<dyn Source>>,
+    names: [String; 2], // fields holding the left and right record
+    sides: [Side; 2],
+    window: Duration,
+    unmatched: Unmatched,
+    route: Option<DeadLetterSender>,
+    expiry: VecDeque<(Instant, usize, String)>, // arrival order: (expires, side, key)
+}
+
+/// Records of one source waiting for a match, by key.
+struct Side {
+    on: String,
+    buffered: HashMap<String, VecDeque<Buffered>>,
+}
+
+struct Buffered {
+    msg: Message,
+    matched: bool,
+}
+
+const LEFT: usize = 0;
+const RIGHT: usize = 1;
+
+impl StreamJoin {
+    pub fn new(left: Box<dyn Source>, right: Box<dyn Source>, config: &StreamJoinConfig) -> Self {
+        let side = |on: &str| Side { on: on.to_string(), buffered: HashMap::new() };
+        Self {
+            left: Some(left),
+            right: Some(right),
+            names: [config.left_as().to_string(), config.right_as().to_string()],
+            sides: [side(&config.on), side(config.right_on())],
+            window: Duration::from_millis(config.window_ms.max(1)),
+            unmatched: config.unmatched,
+            route: None,
+            expiry: VecDeque::new(),
+        }
+    }
+
+    /// Side sink for unmatched records under `Unmatched::Route`.
+    pub fn with_route(mut self, route: DeadLetterSender) -> Self {
+        self.route = Some(route);
+        self
+    }
+
+    /// Join a record with the buffered records of the other side, then
+    /// buffer it for the window.
+    async fn arrive(&mut self, side: usize, msg: Message, out: &mpsc::Sender<Message>) -> anyhow::Result<()> {
+        let Some(key) = key_of(&msg, &self.sides[side].on) else {
+            tracing::debug!(id = ?msg.id, side = %self.names[side], "Record has no join key");
+            return self.unmatched(side, msg, out).await;
+        };
+        let mut pairs = vec![];
+        if let Some(others) = self.sides[1 - side].buffered.get_mut(&key) {
+            for other in others.iter_mut() {
+                other.matched = true;
+                pairs.push(match side {
+                    LEFT => joined(&self.names, Some(&msg), Some(&other.msg)),
+                    _ => joined(&self.names, Some(&other.msg), Some(&msg)),
+                });
+            }
+        }
+        let matched = !pairs.is_empty();
+        for msg in pairs {
+            out.send(msg).await?;
+        }
+        self.expiry.push_back((Instant::now() + self.window, side, key.clone()));
+        self.sides[side].buffered.entry(key).or_default().push_back(Buffered { msg, matched });
+        Ok(())
+    }
+
+    /// Release records whose window ended before `now`.
+    async fn expire(&mut self, now: Instant, out: &mpsc::Sender<Message>) -> anyhow::Result<()> {
+        while self.expiry.front().is_some_and(|(expires, _, _)| *expires <= now) {
+            let Some((_, side, key)) = self.expiry.pop_front() else { break };
+            let buffered = &mut self.sides[side].buffered;
+            let Some(records) = buffered.get_mut(&key) else { continue };
+            let record = records.pop_front();
+            if records.is_empty() {
+                buffered.remove(&key);
+            }
+            match record {
+                Some(Buffered { msg, matched: false }) => self.unmatched(side, msg, out).await?,
+                // Its joined messages carry its acknowledgement from here on
+                Some(Buffered { msg, matched: true }) => release(msg),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn unmatched(&self, side: usize, msg: Message, out: &mpsc::Sender<Message>) -> anyhow::Result<()> {
+        match self.unmatched {
+            Unmatched::Emit => {
+                let single = match side {
+                    LEFT => joined(&self.names, Some(&msg), None),
+                    _ => joined(&self.names, None, Some(&msg)),
+                };
+                out.send(single).await?;
+            }
+            Unmatched::Route => match self.route {
+                Some(ref route) => {
+                    // Not yet seen by the engine, so armed here for the side sink
+                    msg.arm_ack();
+                    let reason = format!("no {} match within {}ms", self.names[1 - side], self.window.as_millis());
+                    route.send(msg, "join", reason, 1);
+                }
+                None => release(msg),
+            },
+            Unmatched::Drop => release(msg),
+        }
+        Ok(())
+    }
+}
+
+/// `{left_as: left record, right_as: right record}`, acknowledged with
+/// both records.
+fn joined(names: &[String; 2], left: Option<&Message>, right: Option<&Message>) -> Message {
+    let mut obj = Map::new();
+    let mut parents = Parents::default();
+    let mut acks = vec![];
+    for (side, msg) in [(LEFT, left), (RIGHT, right)] {
+        // Records that are not JSON are joined as strings
+        let value = msg.map_or(Value::Null, |m| {
+            m.parsed.clone().unwrap_or_else(|| Value::String(String::from_utf8_lossy(&m.payload).into_owned()))
+        });
+        obj.insert(names[side].clone(), value);
+        if let Some(m) = msg {
+            parents.add(m);
+            acks.extend(m.ack.clone());
+        }
+    }
+    let value = Value::Object(obj);
+    let first = left.or(right);
+    let mut msg = Message {
+        id: None,
+        parents: Vec::new(),
+        key: first.and_then(|m| m.key.clone()),
+        payload: value.to_string().into_bytes(),
+        format: Some(Format::Json),
+        parsed: Some(value),
+        timestamp: [left, right].into_iter().flatten().map(|m| m.timestamp).max().unwrap_or_else(chrono::Utc::now),
+        headers: Default::default(),
+        meta: first.map(|m| m.meta.clone()).unwrap_or_default(),
+        ack: Ack::merge(acks),
+    };
+    parents.derive(&mut msg);
+    msg
+}
+
+/// Discard a record that is done with, acknowledging it to its source.
+fn release(msg: Message) {
+    msg.arm_ack();
+}
+
+/// The value at the dotted path `on`; numbers match their string form.
+fn key_of(msg: &Message, on: &str) -> Option<String> {
+    key_string(on.split('.').try_fold(msg.parsed.as_ref()?, |v, part| v.get(part))?)
+}
+
+#[async_trait]
+impl Source for StreamJoin {
+    async fn start(&mut self, out: mpsc::Sender<Message>) -> anyhow::Result<()> {
+        let (Some(left), Some(right)) = (self.left.take(), self.right.take()) else {
+            anyhow::bail!("stream join already started");
+        };
+        let (mut left_rx, left_task) = spawn_input(left, 1024);
+        let (mut right_rx, right_task) = spawn_input(right, 1024);
+
+        let mut ticker = tokio::time::interval(Duration::from_millis(100).min(self.window));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let (mut left_open, mut right_open) = (true, true);
+        while left_open || right_open {
+            tokio::select! {
+                msg = left_rx.recv(), if left_open => match msg {
+                    Some(msg) => self.arrive(LEFT, msg, &out).await?,
+                    None => left_open = false,
+                },
+                msg = right_rx.recv(), if right_open => match msg {
+                    Some(msg) => self.arrive(RIGHT, msg, &out).await?,
+                    None => right_open = false,
+                },
+                _ = ticker.tick() => self.expire(Instant::now(), &out).await?,
+            }
+        }
+
+        // Both sources are done: nothing can match any more
+        self.expire(Instant::now() + self.window, &out).await?;
+        for mut task in [left_task, right_task] {
+            (&mut task.0).await??;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dead_letter::DeadLetterQueue;
+    use crate::traits::Sink;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    /// Sends its messages, then ends.
+    struct VecSource(Vec<Message>);
+
+    #[async_trait]
+    impl Source for VecSource {
+        async fn start(&mut self, out: mpsc::Sender<Message>) -> anyhow::Result<()> {
+            for msg in self.0.drain(..) {
+                out.send(msg).await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Keeps what it is sent.
+    struct VecSink(Arc<Mutex<Vec<Message>>>);
+
+    #[async_trait]
+    impl Sink for VecSink {
+        async fn send(&mut self, msg: Message) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(msg);
+            Ok(())
+        }
+        async fn flush(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A record with ID `id` that logs its ID when acknowledged.
+    fn record(id: &str, value: Value, acked: &Log) -> Message {
+        let (log, logged) = (acked.clone(), id.to_string());
+        Message {
+            id: Some(id.to_string()),
+            parents: Vec::new(),
+            key: None,
+            payload: value.to_string().into_bytes(),
+            format: Some(Format::Json),
+            parsed: Some(value),
+            timestamp: chrono::Utc::now(),
+            headers: Default::default(),
+            meta: Default::default(),
+            ack: Some(Ack::new(move || log.lock().unwrap().push(logged))),
+        }
+    }
+
+    fn config(unmatched: Unmatched) -> StreamJoinConfig {
+        StreamJoinConfig {
+            with: "memory".to_string(),
+            on: "order_id".to_string(),
+            right_on: None,
+            window_ms: 1000,
+            unmatched,
+            unmatched_to: None,
+            left_as: Some("orders".to_string()),
+            right_as: Some("payments".to_string()),
+        }
+    }
+
+    fn join(unmatched: Unmatched) -> StreamJoin {
+        StreamJoin::new(Box::new(VecSource(vec![])), Box::new(VecSource(vec![])), &config(unmatched))
+    }
+
+    fn acked(log: &Log) -> Vec<String> {
+        let mut acked = log.lock().unwrap().clone();
+        acked.sort();
+        acked
+    }
+
+    #[tokio::test]
+    async fn joins_records_within_the_window() {
+        let log = Log::default();
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut join = join(Unmatched::Emit);
+        join.arrive(LEFT, record("o1", json!({ "order_id": 7, "total": 30 }), &log), &tx).await.unwrap();
+        join.arrive(RIGHT, record("p1", json!({ "order_id": "7", "paid": 30 }), &log), &tx).await.unwrap();
+
+        let joined = rx.try_recv().unwrap();
+        assert_eq!(joined.parsed, Some(json!({ "orders": { "order_id": 7, "total": 30 }, "payments": { "order_id": "7", "paid": 30 } })));
+        assert_eq!(joined.parents, vec!["o1".to_string(), "p1".to_string()]);
+
+        // Matched records are released without an unmatched copy
+        join.expire(Instant::now() + join.window, &tx).await.unwrap();
+        assert!(rx.try_recv().is_err());
+        assert!(acked(&log).is_empty());
+
+        // The joined message carries both acknowledgements
+        joined.arm_ack();
+        drop(joined);
+        assert_eq!(acked(&log), vec!["o1", "p1"]);
+    }
+
+    #[tokio::test]
+    async fn records_expire_after_the_window() {
+        let log = Log::default();
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut join = join(Unmatched::Drop);
+        join.arrive(LEFT, record("o1", json!({ "order_id": 7 }), &log), &tx).await.unwrap();
+
+        // Not yet expired
+        join.expire(Instant::now(), &tx).await.unwrap();
+        assert!(acked(&log).is_empty());
+
+        join.expire(Instant::now() + join.window, &tx).await.unwrap();
+        assert_eq!(acked(&log), vec!["o1"]);
+        join.arrive(RIGHT, record("p1", json!({ "order_id": 7 }), &log), &tx).await.unwrap();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn unmatched_records_are_emitted_alone() {
+        let log = Log::default();
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut join = join(Unmatched::Emit);
+        join.arrive(RIGHT, record("p1", json!({ "order_id": 7 }), &log), &tx).await.unwrap();
+        join.expire(Instant::now() + join.window, &tx).await.unwrap();
+
+        let single = rx.try_recv().unwrap();
+        assert_eq!(single.parsed, Some(json!({ "orders": null, "payments": { "order_id": 7 } })));
+        assert!(acked(&log).is_empty());
+        single.arm_ack();
+        drop(single);
+        assert_eq!(acked(&log), vec!["p1"]);
+    }
+
+    #[tokio::test]
+    async fn unmatched_records_are_routed() {
+        let log = Log::default();
+        let routed = Arc::new(Mutex::new(Vec::new()));
+        let queue = DeadLetterQueue::spawn(Box::new(VecSink(routed.clone())));
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut join = join(Unmatched::Route).with_route(queue.sender());
+
+        join.arrive(LEFT, record("o1", json!({ "order_id": 7 }), &log), &tx).await.unwrap();
+        join.arrive(LEFT, record("o2", json!({ "total": 5 }), &log), &tx).await.unwrap();
+        join.expire(Instant::now() + join.window, &tx).await.unwrap();
+        drop(join);
+        queue.close().await.unwrap();
+        assert!(rx.try_recv().is_err());
+
+        // A record without a key is routed straight away, before the expired one
+        let letters: Vec<Message> = std::mem::take(&mut *routed.lock().unwrap());
+        let reasons: Vec<_> = letters.iter().map(|m| m.parsed.as_ref().unwrap()["reason"].clone()).collect();
+        assert_eq!(reasons, vec![json!("no payments match within 1000ms"); 2]);
+        assert_eq!(letters.iter().map(|m| m.id.clone().unwrap()).collect::<Vec<_>>(), vec!["o2", "o1"]);
+        drop(letters);
+        assert_eq!(acked(&log), vec!["o1", "o2"]);
+    }
+
+    #[tokio::test]
+    async fn flushes_when_both_sources_end() {
+        let log = Log::default();
+        let left = VecSource(vec![
+            record("o1", json!({ "order_id": 1 }), &log),
+            record("o2", json!({ "order_id": 2 }), &log),
+        ]);
+        let right = VecSource(vec![record("p1", json!({ "order_id": 1 }), &log)]);
+        let mut config = config(Unmatched::Emit);
+        config.window_ms = 60_000;
+        let mut join = StreamJoin::new(Box::new(left), Box::new(right), &config);
+
+        // Far inside the window, so only the end of input releases o2
+        let (tx, mut rx) = mpsc::channel(16);
+        join.start(tx).await.unwrap();
+        let mut out = vec![];
+        while let Ok(msg) = rx.try_recv() {
+            out.push(msg);
+        }
+        let values: Vec<_> = out.iter().map(|m| m.parsed.clone().unwrap()).collect();
+        assert_eq!(values, vec![
+            json!({ "orders": { "order_id": 1 }, "payments": { "order_id": 1 } }),
+            json!({ "orders": { "order_id": 2 }, "payments": null }),
+        ]);
+
+        out.iter().for_each(|m| m.arm_ack());
+        assert!(acked(&log).is_empty());
+        drop(out);
+        assert_eq!(acked(&log), vec!["o1", "o2", "p1"]);
+    }
+}
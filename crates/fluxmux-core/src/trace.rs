@@ -0,0 +1,93 @@
+//! Per-message trace context.
+//!
+//! A message carries its W3C trace context (`traceparent`, `tracestate`) in
+//! `headers`, e.g. copied from Kafka record headers. The engines open a
+//! `receive` span for every message taken from a source, then one `stage`
+//! span per middleware / pipe action call and one `send` span per sink write,
+//! all in the message's trace. Spans reach a collector only when the
+//! application installs a `tracing-opentelemetry` layer and sets the global
+//! text map propagator (the CLI does both for `--otlp-endpoint`); otherwise
+//! this is a no-op.
+
+use crate::message::Message;
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::trace::TraceContextExt;
+use std::collections::HashMap;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Header holding the W3C trace parent of a message.
+pub const TRACEPARENT: &str = "traceparent";
+
+struct HeaderExtractor<'a>(&'a HashMap<String, String>);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(String::as_str).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HashMap<String, String>);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        // An empty `tracestate` is left out rather than carried downstream
+        if value.is_empty() {
+            self.0.remove(key);
+        } else {
+            self.0.insert(key.to_string(), value);
+        }
+    }
+}
+
+fn extract(headers: &HashMap<String, String>) -> Option<opentelemetry::Context> {
+    if !headers.contains_key(TRACEPARENT) {
+        return None;
+    }
+    let cx = opentelemetry::global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(headers)));
+    cx.span().span_context().is_valid().then_some(cx)
+}
+
+/// Open the `receive` span for a message taken from a source. It continues
+/// the upstream trace when the message carries one and starts a new trace
+/// otherwise; the message then carries the `receive` span as its parent.
+pub fn receive(msg: &mut Message) {
+    let span = tracing::debug_span!(parent: None, "receive", bytes = msg.payload.len());
+    if span.is_disabled() {
+        return;
+    }
+    if let Some(cx) = extract(&msg.headers) {
+        let _ = span.set_parent(cx);
+    }
+    let cx = span.context();
+    opentelemetry::global::get_text_map_propagator(|p| p.inject_context(&cx, &mut HeaderInjector(&mut msg.headers)));
+}
+
+/// Make `span` part of the message's trace.
+pub fn follow(span: &Span, msg: &Message) {
+    if span.is_disabled() {
+        return;
+    }
+    if let Some(cx) = extract(&msg.headers) {
+        let _ = span.set_parent(cx);
+    }
+}
+
+/// Make `span` part of the first message's trace and link it to the traces
+/// of the others, for work done on a whole batch.
+pub fn follow_batch(span: &Span, batch: &[Message]) {
+    let Some((first, rest)) = batch.split_first() else { return };
+    follow(span, first);
+    if span.is_disabled() {
+        return;
+    }
+    for msg in rest {
+        if let Some(cx) = extract(&msg.headers) {
+            span.add_link(cx.span().span_context().clone());
+        }
+    }
+}
@@ -7,10 +7,51 @@ pub trait Source: Send + Sync {
     async fn start(&mut self, out: mpsc::Sender<Message>) -> anyhow::Result<()>;
 }
 
+/// Dropping a message acknowledges it to its source, so a sink must hold on
+/// to each message until it is durably written (e.g. buffered sinks keep
+/// messages until `flush` succeeds).
 #[async_trait]
 pub trait Sink: Send + Sync {
     async fn send(&mut self, msg: Message) -> anyhow::Result<()>;
+    /// Write several messages at once. Sinks with a bulk write path override
+    /// this; on error the whole batch may be retried, so writes should be
+    /// all-or-nothing where the backend allows it.
+    async fn send_batch(&mut self, batch: Vec<Message>) -> anyhow::Result<()> {
+        for msg in batch {
+            self.send(msg).await?;
+        }
+        Ok(())
+    }
     async fn flush(&mut self) -> anyhow::Result<()>;
+    /// Sink label used in metrics.
+    fn name(&self) -> &'static str {
+        "sink"
+    }
+}
+
+// Boxed sources and sinks (e.g. from `source_from_uri`) can be used wherever
+// a concrete one is expected
+#[async_trait]
+impl<S: Source + ?Sized> Source for Box<S> {
+    async fn start(&mut self, out: mpsc::Sender<Message>) -> anyhow::Result<()> {
+        (**self).start(out).await
+    }
+}
+
+#[async_trait]
+impl<S: Sink + ?Sized> Sink for Box<S> {
+    async fn send(&mut self, msg: Message) -> anyhow::Result<()> {
+        (**self).send(msg).await
+    }
+    async fn send_batch(&mut self, batch: Vec<Message>) -> anyhow::Result<()> {
+        (**self).send_batch(batch).await
+    }
+    async fn flush(&mut self) -> anyhow::Result<()> {
+        (**self).flush().await
+    }
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
 }
 
 #[async_trait]
@@ -0,0 +1,177 @@
+//! Fan-in of several sources into one stream.
+//!
+//! `UnionSource` runs every input on its own small channel and forwards from
+//! them in turn, one message per ready input, so a busy input (a Kafka topic
+//! with a backlog) cannot starve a quiet one: when the pipeline falls behind,
+//! each input is held back by its own full channel. Every message is tagged
+//! with the URI it came from in `meta["source"]`.
+
+use crate::message::Message;
+use crate::traits::Source;
+use async_trait::async_trait;
+use std::task::Poll;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+/// `Message.meta` key holding the URI of the source a message came from.
+pub const SOURCE_META: &str = "source";
+
+/// Messages each input may have queued ahead of the union.
+const INPUT_CAPACITY: usize = 64;
+
+pub struct UnionSource {
+    inputs: Vec<(String, Box<dyn Source>)>,
+}
+
+impl UnionSource {
+    /// `inputs` pairs each source with its URI, used as its tag.
+    pub fn new(inputs: Vec<(String, Box<dyn Source>)>) -> Self {
+        Self { inputs }
+    }
+}
+
+/// Stops a source task when its reader stops, e.g. when that reader's own
+/// task is aborted at shutdown.
+pub(crate) struct AbortOnDrop(pub JoinHandle<anyhow::Result<()>>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Run `source` on its own task, feeding a channel of `capacity`.
+pub(crate) fn spawn_input(mut source: Box<dyn Source>, capacity: usize) -> (mpsc::Receiver<Message>, AbortOnDrop) {
+    let (tx, rx) = mpsc::channel(capacity);
+    let task = tokio::spawn(async move { source.start(tx).await }.in_current_span());
+    (rx, AbortOnDrop(task))
+}
+
+#[async_trait]
+impl Source for UnionSource {
+    async fn start(&mut self, out: mpsc::Sender<Message>) -> anyhow::Result<()> {
+        let mut inputs: Vec<_> = self.inputs.drain(..).map(|(uri, source)| {
+            let (rx, task) = spawn_input(source, INPUT_CAPACITY);
+            (uri, rx, task)
+        }).collect();
+
+        // Round robin: each poll starts after the input served last
+        let mut next = 0;
+        while !inputs.is_empty() {
+            let (i, msg) = std::future::poll_fn(|cx| {
+                for offset in 0..inputs.len() {
+                    let i = (next + offset) % inputs.len();
+                    if let Poll::Ready(msg) = inputs[i].1.poll_recv(cx) {
+                        return Poll::Ready((i, msg));
+                    }
+                }
+                Poll::Pending
+            })
+            .await;
+            match msg {
+                Some(mut msg) => {
+                    msg.meta.insert(SOURCE_META.to_string(), inputs[i].0.clone());
+                    out.send(msg).await?;
+                    next = i + 1;
+                }
+                None => {
+                    // A failing input stops the union, like a failing single source
+                    let (uri, _, mut task) = inputs.remove(i);
+                    (&mut task.0).await?.map_err(|e| e.context(format!("source {}", uri)))?;
+                    tracing::debug!(source = %uri, "Union input finished");
+                    next = i;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends `count` messages named after `name`, then ends, fails or stays open.
+    struct Fixed {
+        name: &'static str,
+        count: usize,
+        end: End,
+    }
+
+    enum End {
+        Finish,
+        Fail,
+        Open,
+    }
+
+    #[async_trait]
+    impl Source for Fixed {
+        async fn start(&mut self, out: mpsc::Sender<Message>) -> anyhow::Result<()> {
+            for n in 0..self.count {
+                let msg = Message {
+                    id: None,
+                    parents: Vec::new(),
+                    key: None,
+                    payload: format!("{}{}", self.name, n).into_bytes(),
+                    format: None,
+                    parsed: None,
+                    timestamp: chrono::Utc::now(),
+                    headers: Default::default(),
+                    meta: Default::default(),
+                    ack: None,
+                };
+                out.send(msg).await?;
+            }
+            match self.end {
+                End::Finish => Ok(()),
+                End::Fail => Err(anyhow::anyhow!("connection lost")),
+                End::Open => std::future::pending().await,
+            }
+        }
+    }
+
+    fn input(name: &'static str, count: usize, end: End) -> (String, Box<dyn Source>) {
+        (format!("mem://{}", name), Box::new(Fixed { name, count, end }))
+    }
+
+    /// Run the union with a one-message output queue, read only once every
+    /// input had time to fill its own queue; returns (payload, source) pairs.
+    async fn run(inputs: Vec<(String, Box<dyn Source>)>) -> (anyhow::Result<()>, Vec<(String, String)>) {
+        let (tx, mut rx) = mpsc::channel(1);
+        let union = tokio::spawn(async move { UnionSource::new(inputs).start(tx).await });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let mut received = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            received.push((String::from_utf8(msg.payload).unwrap(), msg.meta[SOURCE_META].clone()));
+        }
+        (union.await.unwrap(), received)
+    }
+
+    #[tokio::test]
+    async fn inputs_take_turns() {
+        let (result, received) = run(vec![input("a", 5, End::Finish), input("b", 2, End::Finish)]).await;
+        result.unwrap();
+        let payloads: Vec<&str> = received.iter().map(|(payload, _)| payload.as_str()).collect();
+        // Once b ends, a continues alone
+        assert_eq!(payloads, vec!["a0", "b0", "a1", "b1", "a2", "a3", "a4"]);
+    }
+
+    #[tokio::test]
+    async fn messages_are_tagged_with_their_input() {
+        let (result, received) = run(vec![input("a", 2, End::Finish), input("b", 2, End::Finish)]).await;
+        result.unwrap();
+        for (payload, source) in received {
+            assert_eq!(source, format!("mem://{}", &payload[..1]));
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failing_input_fails_the_union() {
+        let (result, received) = run(vec![input("a", 1, End::Open), input("b", 1, End::Fail)]).await;
+        // What the failing input sent before is still delivered
+        assert_eq!(received.len(), 2);
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("source mem://b") && error.contains("connection lost"), "{error}");
+    }
+}
@@ -0,0 +1,208 @@
+//! Time windows for streaming aggregation.
+//!
+//! A window is placed on the time of each message: processing time, or an
+//! event time (see `event_time`). Windows close once the clock (for
+//! processing time) or the watermark (for event time) passes their end, so
+//! results flow while the source keeps running.
+
+use crate::event_time::EventTime;
+use crate::message::Message;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How messages are grouped in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum WindowKind {
+    /// Back-to-back windows of `size_ms`
+    Tumbling { size_ms: u64 },
+    /// Windows of `size_ms` starting every `advance_ms`; when they overlap a
+    /// message counts in each window it falls in
+    Hopping { size_ms: u64, advance_ms: u64 },
+    /// Per group, a window that closes after `gap_ms` without messages
+    Session { gap_ms: u64 },
+}
+
+impl std::fmt::Display for WindowKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tumbling { size_ms } => write!(f, "tumbling size_ms={}", size_ms),
+            Self::Hopping { size_ms, advance_ms } => write!(f, "hopping size_ms={} advance_ms={}", size_ms, advance_ms),
+            Self::Session { gap_ms } => write!(f, "session gap_ms={}", gap_ms),
+        }
+    }
+}
+
+/// Bounds of one window in epoch milliseconds, end exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bounds {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Bounds {
+    pub fn start_time(&self) -> DateTime<Utc> {
+        millis_to_time(self.start)
+    }
+
+    pub fn end_time(&self) -> DateTime<Utc> {
+        millis_to_time(self.end)
+    }
+}
+
+/// Which clock windows follow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeDomain {
+    /// When the message is processed
+    #[default]
+    Processing,
+    /// When the message says it happened
+    Event,
+}
+
+impl std::str::FromStr for TimeDomain {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "processing" => Ok(Self::Processing),
+            "event" => Ok(Self::Event),
+            other => Err(anyhow::anyhow!("Unknown time '{}', expected processing or event", other)),
+        }
+    }
+}
+
+/// Where a window reads the time of a message.
+#[derive(Debug, Clone)]
+pub enum WindowTime {
+    Processing,
+    /// `Message.timestamp`, set from the payload by the pipeline's event time extractor
+    Timestamp,
+    /// A payload field of its own
+    Field(EventTime),
+}
+
+#[derive(Debug, Clone)]
+pub struct Window {
+    kind: WindowKind,
+    time: WindowTime,
+}
+
+impl Window {
+    pub fn new(kind: WindowKind, time: WindowTime) -> Self {
+        Self { kind, time }
+    }
+
+    pub fn kind(&self) -> WindowKind {
+        self.kind
+    }
+
+    pub fn is_event_time(&self) -> bool {
+        !matches!(self.time, WindowTime::Processing)
+    }
+
+    /// The message's time in epoch milliseconds; `None` when its event time
+    /// field is missing or not a timestamp.
+    pub fn time_of(&self, msg: &Message) -> Option<i64> {
+        match self.time {
+            WindowTime::Processing => Some(Utc::now().timestamp_millis()),
+            WindowTime::Timestamp => Some(msg.timestamp.timestamp_millis()),
+            WindowTime::Field(ref event_time) => event_time.extract(msg).map(|t| t.timestamp_millis()),
+        }
+    }
+
+    /// The fixed windows containing `time`; sessions are placed per group by
+    /// the caller.
+    pub fn assign(&self, time: i64) -> Vec<Bounds> {
+        match self.kind {
+            WindowKind::Tumbling { size_ms } => {
+                let size = size_ms.max(1) as i64;
+                let start = time - time.rem_euclid(size);
+                vec![Bounds { start, end: start + size }]
+            }
+            WindowKind::Hopping { size_ms, advance_ms } => {
+                let (size, advance) = (size_ms.max(1) as i64, advance_ms.max(1) as i64);
+                let mut start = time - time.rem_euclid(advance);
+                let mut windows = Vec::new();
+                while start + size > time {
+                    windows.push(Bounds { start, end: start + size });
+                    start -= advance;
+                }
+                windows.reverse();
+                windows
+            }
+            WindowKind::Session { gap_ms } => vec![Bounds { start: time, end: time + gap_ms.max(1) as i64 }],
+        }
+    }
+}
+
+fn millis_to_time(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(millis).single().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(kind: WindowKind) -> Window {
+        Window::new(kind, WindowTime::Timestamp)
+    }
+
+    #[test]
+    fn tumbling_assigns_one_window() {
+        let tumbling = window(WindowKind::Tumbling { size_ms: 10 });
+        assert_eq!(tumbling.assign(0), vec![Bounds { start: 0, end: 10 }]);
+        assert_eq!(tumbling.assign(19), vec![Bounds { start: 10, end: 20 }]);
+        assert_eq!(tumbling.assign(-1), vec![Bounds { start: -10, end: 0 }]);
+    }
+
+    #[test]
+    fn hopping_assigns_every_overlapping_window() {
+        let hopping = window(WindowKind::Hopping { size_ms: 10, advance_ms: 5 });
+        assert_eq!(hopping.assign(12), vec![Bounds { start: 5, end: 15 }, Bounds { start: 10, end: 20 }]);
+        assert_eq!(hopping.assign(10), vec![Bounds { start: 5, end: 15 }, Bounds { start: 10, end: 20 }]);
+
+        // Gaps between windows when the advance exceeds the size
+        let sparse = window(WindowKind::Hopping { size_ms: 5, advance_ms: 10 });
+        assert_eq!(sparse.assign(3), vec![Bounds { start: 0, end: 5 }]);
+        assert!(sparse.assign(7).is_empty());
+    }
+
+    #[test]
+    fn session_starts_at_the_message() {
+        let session = window(WindowKind::Session { gap_ms: 30 });
+        assert_eq!(session.assign(100), vec![Bounds { start: 100, end: 130 }]);
+    }
+
+    #[test]
+    fn time_of_follows_the_configured_clock() {
+        let mut msg = crate::message::Message {
+            id: None,
+            parents: Vec::new(),
+            key: None,
+            payload: br#"{"ts":2000}"#.to_vec(),
+            format: None,
+            parsed: Some(serde_json::json!({ "ts": 2000 })),
+            timestamp: millis_to_time(1000),
+            headers: Default::default(),
+            meta: Default::default(),
+            ack: None,
+        };
+        let kind = WindowKind::Tumbling { size_ms: 10 };
+        assert_eq!(Window::new(kind, WindowTime::Timestamp).time_of(&msg), Some(1000));
+
+        let field = EventTime::new("ts", crate::event_time::TimeFormat::EpochMillis);
+        assert_eq!(Window::new(kind, WindowTime::Field(field.clone())).time_of(&msg), Some(2000));
+        msg.parsed = Some(serde_json::json!({}));
+        assert_eq!(Window::new(kind, WindowTime::Field(field)).time_of(&msg), None);
+        assert!(!Window::new(kind, WindowTime::Processing).is_event_time());
+    }
+
+    #[test]
+    fn bounds_convert_to_times() {
+        let bounds = Bounds { start: 0, end: 1500 };
+        assert_eq!(bounds.start_time().timestamp_millis(), 0);
+        assert_eq!(bounds.end_time().timestamp_millis(), 1500);
+    }
+}
@@ -1,5 +1,7 @@
 use fluxmux_core::traits::Sink;
 use fluxmux_core::message::Message;
+use fluxmux_core::retry::transient;
+use std::io::ErrorKind;
 use async_trait::async_trait;
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
@@ -16,30 +18,159 @@ impl FileSink {
     }
 }
 
+// Bad paths and missing permissions will not fix themselves; other I/O
+// failures (full disk, interrupted writes) may
+fn io_error(e: std::io::Error) -> anyhow::Error {
+    match e.kind() {
+        ErrorKind::PermissionDenied | ErrorKind::NotFound | ErrorKind::InvalidInput | ErrorKind::IsADirectory => e.into(),
+        _ => transient(e),
+    }
+}
+
+impl FileSink {
+    /// Write `messages` as lines in a single append. On failure the file is cut
+    /// back to its previous length, so a retry never writes a line twice.
+    async fn write(&self, messages: &[Message]) -> anyhow::Result<()> {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)
+            .await
+            .map_err(io_error)?;
+        let len = file.metadata().await.map_err(io_error)?.len();
+        let mut data = Vec::with_capacity(messages.iter().map(|m| m.payload.len() + 1).sum());
+        for msg in messages {
+            data.extend_from_slice(&msg.payload);
+            data.push(b'\n');
+        }
+        let written = match file.write_all(&data).await {
+            Ok(()) => file.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            if let Err(truncate) = file.set_len(len).await {
+                tracing::error!(path = %self.path, error = %truncate, "Failed to remove a partial write");
+            }
+            return Err(io_error(e));
+        }
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl Sink for FileSink {
     async fn send(&mut self, msg: Message) -> anyhow::Result<()> {
         self.buffer.push(msg);
         if self.buffer.len() >= self.buffer_size {
-            self.flush().await?;
+            if let Err(e) = self.flush().await {
+                // The caller retries or gives up on this message; the rest stay buffered
+                self.buffer.pop();
+                return Err(e);
+            }
         }
         Ok(())
     }
 
+    async fn send_batch(&mut self, batch: Vec<Message>) -> anyhow::Result<()> {
+        // A batch is written out right away, together with anything buffered
+        let buffered = self.buffer.len();
+        self.buffer.extend(batch);
+        let flushed = self.flush().await;
+        if flushed.is_err() {
+            // Leave nothing of a failed batch behind: a retry sends it again
+            self.buffer.truncate(buffered);
+        }
+        flushed
+    }
+
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
     async fn flush(&mut self) -> anyhow::Result<()> {
         if self.buffer.is_empty() {
             return Ok(());
         }
-        let mut file = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(&self.path)
-            .await?;
-        for msg in self.buffer.drain(..) {
-            file.write_all(&msg.payload).await?;
-            file.write_all(b"\n").await?;
-        }
-        file.flush().await?;
+        self.write(&self.buffer).await?;
+        // Only release (and thereby acknowledge) messages once they are written
+        self.buffer.clear();
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    fn line(text: &str) -> Message {
+        Message {
+            id: None,
+            parents: Vec::new(),
+            key: None,
+            payload: text.as_bytes().to_vec(),
+            format: None,
+            parsed: None,
+            timestamp: chrono::Utc::now(),
+            headers: Default::default(),
+            meta: Default::default(),
+            ack: None,
+        }
+    }
+
+    /// A scratch directory and an output path in a subdirectory that does
+    /// not exist yet, so writes fail until it is created.
+    fn scratch(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("fluxmux-file-sink-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        (dir.clone(), dir.join("out").join("out.ndjson"))
+    }
+
+    fn read(path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn retried_batches_are_written_once() {
+        let (dir, path) = scratch("batch");
+        let mut sink = FileSink::new(path.to_string_lossy().into_owned(), 1024);
+        for _ in 0..3 {
+            assert!(sink.send_batch(vec![line("a"), line("b")]).await.is_err());
+        }
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        sink.send_batch(vec![line("a"), line("b")]).await.unwrap();
+        sink.flush().await.unwrap();
+        assert_eq!(read(&path), "a\nb\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn retried_sends_are_written_once() {
+        let (dir, path) = scratch("send");
+        let mut sink = FileSink::new(path.to_string_lossy().into_owned(), 2);
+        sink.send(line("a")).await.unwrap();
+        assert!(sink.send(line("b")).await.is_err());
+        assert!(sink.send(line("b")).await.is_err());
+
+        // The message accepted before the failure is still written, once
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        sink.send(line("b")).await.unwrap();
+        sink.flush().await.unwrap();
+        assert_eq!(read(&path), "a\nb\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn abandoned_batches_are_not_written_later() {
+        let (dir, path) = scratch("abandoned");
+        let mut sink = FileSink::new(path.to_string_lossy().into_owned(), 1024);
+        assert!(sink.send_batch(vec![line("dead-lettered")]).await.is_err());
+
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        sink.send_batch(vec![line("next")]).await.unwrap();
+        sink.flush().await.unwrap();
+        assert_eq!(read(&path), "next\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,19 +1,36 @@
 use fluxmux_core::traits::Sink;
 use fluxmux_core::message::Message;
+use fluxmux_core::retry::{is_transient, partial_failure, transient};
 use async_trait::async_trait;
+use futures_util::future::join_all;
+use rdkafka::message::{Header, OwnedHeaders};
 use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
 use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
 use std::time::Duration;
 
 pub struct KafkaSink {
     pub brokers: String,
     pub topic: String,
     producer: Option<FutureProducer>,
+    event_time: bool, // write message timestamps as record timestamps
 }
 
 impl KafkaSink {
     pub fn new(brokers: String, topic: String) -> Self {
-        Self { brokers, topic, producer: None }
+        Self { brokers, topic, producer: None, event_time: false }
+    }
+
+    /// Write each message's timestamp as its record timestamp. Without this
+    /// the producer stamps the time a record is sent.
+    pub fn with_event_time(mut self) -> Self {
+        self.event_time = true;
+        self
+    }
+
+    // Only an event time is worth carrying; an ingest time would replace the send time
+    fn record_timestamp(&self, msg: &Message) -> Option<i64> {
+        self.event_time.then(|| msg.timestamp.timestamp_millis())
     }
 
     fn ensure_producer(&mut self) {
@@ -28,22 +45,88 @@ impl KafkaSink {
     }
 }
 
+// Records the broker will never accept are permanent; everything else
+// (timeouts, full queues, leader changes, lost brokers) is retried
+fn delivery_error(e: KafkaError, what: &str) -> anyhow::Error {
+    let permanent = matches!(
+        e.rdkafka_error_code(),
+        Some(RDKafkaErrorCode::MessageSizeTooLarge)
+            | Some(RDKafkaErrorCode::InvalidMessage)
+            | Some(RDKafkaErrorCode::InvalidMessageSize)
+            | Some(RDKafkaErrorCode::InvalidRecord)
+            | Some(RDKafkaErrorCode::TopicAuthorizationFailed)
+            | Some(RDKafkaErrorCode::InvalidTopic)
+    );
+    let err = anyhow::anyhow!("{}: {}", what, e);
+    if permanent { err } else { transient(err) }
+}
+
+/// Message headers (including its trace context) as Kafka record headers.
+fn record_headers(msg: &Message) -> OwnedHeaders {
+    msg.headers.iter().fold(OwnedHeaders::new_with_capacity(msg.headers.len()), |headers, (key, value)| {
+        headers.insert(Header { key, value: Some(value) })
+    })
+}
+
 #[async_trait]
 impl Sink for KafkaSink {
     async fn send(&mut self, msg: Message) -> anyhow::Result<()> {
         self.ensure_producer();
         
         let topic = self.topic.clone();
-        let record = FutureRecord::to(&topic)
+        let mut record = FutureRecord::to(&topic)
             .payload(&msg.payload)
-            .key(msg.key.as_deref().unwrap_or_default());
+            .key(msg.key.as_deref().unwrap_or_default())
+            .headers(record_headers(&msg));
+        if let Some(timestamp) = self.record_timestamp(&msg) {
+            record = record.timestamp(timestamp);
+        }
 
         match self.producer.as_ref().unwrap().send(record, Duration::from_secs(5)).await {
             Ok(_) => Ok(()),
-            Err((e, _)) => Err(anyhow::anyhow!("Failed to send message: {}", e))
+            Err((e, _)) => Err(delivery_error(e, "Failed to send message"))
         }
     }
 
+    async fn send_batch(&mut self, batch: Vec<Message>) -> anyhow::Result<()> {
+        self.ensure_producer();
+        let producer = self.producer.as_ref().unwrap();
+
+        // Enqueue every record before awaiting any delivery report
+        let deliveries = batch.iter().map(|msg| {
+            let mut record = FutureRecord::to(&self.topic)
+                .payload(&msg.payload)
+                .key(msg.key.as_deref().unwrap_or_default())
+                .headers(record_headers(msg));
+            if let Some(timestamp) = self.record_timestamp(msg) {
+                record = record.timestamp(timestamp);
+            }
+            producer.send(record, Duration::from_secs(5))
+        });
+        // Report only the records that failed, so delivered ones are not sent again
+        let mut failed = Vec::new();
+        let mut errors = Vec::new();
+        for (index, result) in join_all(deliveries).await.into_iter().enumerate() {
+            if let Err((e, _)) = result {
+                failed.push(index);
+                errors.push(delivery_error(e, "Failed to send batch"));
+            }
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        // Retry while any failure may clear up; records failing permanently fail again
+        let error = match errors.iter().position(is_transient) {
+            Some(index) => errors.swap_remove(index),
+            None => errors.swap_remove(0),
+        };
+        Err(partial_failure(failed, error))
+    }
+
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
     async fn flush(&mut self) -> anyhow::Result<()> {
         if let Some(producer) = &self.producer {
             producer.flush(Duration::from_secs(5))?;
@@ -7,3 +7,42 @@ pub use file::FileSink;
 pub use kafka::KafkaSink;
 pub use postgres::PostgresSink;
 pub use pipe::PipeSink;
+
+use fluxmux_core::endpoints::SinkType;
+use fluxmux_core::lineage::LineageHeaders;
+use fluxmux_core::traits::Sink;
+use std::str::FromStr;
+
+/// Pipeline settings that change what a sink writes.
+#[derive(Debug, Clone, Default)]
+pub struct SinkOptions {
+    /// Lineage headers the pipeline stamps; the Postgres sink writes them as columns
+    pub lineage: Option<LineageHeaders>,
+    /// Whether message timestamps are event times; the Kafka sink then writes them as record timestamps
+    pub event_time: bool,
+}
+
+/// Build a sink from a URI such as `file:out.ndjson`,
+/// `kafka://localhost:9092/events`, `postgres:host=db user=etl?table=events`
+/// or `stdout`.
+pub fn sink_from_uri(uri: &str, options: &SinkOptions) -> anyhow::Result<Box<dyn Sink>> {
+    Ok(sink_from_type(SinkType::from_str(uri)?, options))
+}
+
+pub fn sink_from_type(sink_type: SinkType, options: &SinkOptions) -> Box<dyn Sink> {
+    match sink_type {
+        SinkType::File { path } => Box::new(FileSink::new(path, 1024)),
+        SinkType::Kafka { brokers, topic } => {
+            let sink = KafkaSink::new(brokers, topic);
+            Box::new(if options.event_time { sink.with_event_time() } else { sink })
+        }
+        SinkType::Postgres { connection_string, table, schema } => {
+            let sink = PostgresSink::new(connection_string, table, schema);
+            Box::new(match options.lineage {
+                Some(ref headers) => sink.with_lineage(headers.clone()),
+                None => sink,
+            })
+        }
+        SinkType::Stdout => Box::new(PipeSink::new()),
+    }
+}
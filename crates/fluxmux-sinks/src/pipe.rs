@@ -3,6 +3,7 @@ use fluxmux_core::message::Message;
 use fluxmux_core::traits::Sink;
 use tokio::io::{self, AsyncWriteExt};
 
+#[derive(Default)]
 pub struct PipeSink;
 
 impl PipeSink { 
@@ -21,6 +22,10 @@ impl Sink for PipeSink {
 		Ok(())
 	}
 
+	fn name(&self) -> &'static str {
+		"stdout"
+	}
+
 	async fn flush(&mut self) -> anyhow::Result<()> {
 		io::stdout().flush().await?;
 		Ok(())
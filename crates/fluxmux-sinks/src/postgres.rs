@@ -1,18 +1,24 @@
 use std::collections::HashMap;
 use fluxmux_core::{
+    lineage::{LineageHeader, LineageHeaders},
     message::Message,
+    retry::transient,
     traits::Sink,
 };
 use async_trait::async_trait;
-use tokio_postgres::{Client, NoTls};
-use serde_json::Value;
+use tokio_postgres::{Client, GenericClient, NoTls};
+use serde_json::{Map, Value};
 use anyhow::Context;
 
+/// Upper bound on bind parameters in one statement (the protocol allows 65535).
+const MAX_BIND_PARAMS: usize = 65535;
+
 pub struct PostgresSink {
     client: Option<Client>,
     table: String,
     connection_string: String,
     required_schema: Option<HashMap<String, String>>, // column_name -> data_type
+    lineage: LineageHeaders, // lineage headers written as columns
 }
 
 impl PostgresSink {
@@ -26,27 +32,39 @@ impl PostgresSink {
             table,
             connection_string,
             required_schema: schema,
+            lineage: LineageHeaders::default(),
         }
     }
 
+    /// Write the given lineage headers as columns, e.g. `fluxmux-source` as
+    /// `fluxmux_source`. Other headers never become columns.
+    pub fn with_lineage(mut self, headers: LineageHeaders) -> Self {
+        self.lineage = headers;
+        self
+    }
+
     async fn get_or_connect_client(&mut self) -> anyhow::Result<()> {
+        // A closed connection never recovers; reconnect on the next attempt
+        if self.client.as_ref().is_some_and(|c| c.is_closed()) {
+            self.client = None;
+        }
         if self.client.is_none() {
             // Connect and validate table/schema if needed
             let (client, connection) = tokio_postgres::connect(&self.connection_string, NoTls)
                 .await
-                .context("Failed to connect to PostgreSQL")?;
+                .map_err(|e| db_error(e, "Failed to connect to PostgreSQL"))?;
 
             // The connection object performs the actual communication with the database,
             // so spawn it off to run on its own
             tokio::spawn(async move {
                 if let Err(e) = connection.await {
-                    eprintln!("Database connection error: {}", e);
+                    tracing::error!(error = %e, "Database connection error");
                 }
             });
 
-            // If schema validation is requested, verify table schema
-            if let Some(schema) = &self.required_schema {
-                self.validate_table_schema(&client, schema).await?;
+            // If schema validation is requested or lineage columns are written, verify table schema
+            if self.required_schema.is_some() || !self.lineage.is_empty() {
+                self.validate_table_schema(&client).await?;
             }
 
             self.client = Some(client);
@@ -55,7 +73,7 @@ impl PostgresSink {
         Ok(())
     }
 
-    async fn validate_table_schema(&self, client: &Client, required: &HashMap<String, String>) -> anyhow::Result<()> {
+    async fn validate_table_schema(&self, client: &Client) -> anyhow::Result<()> {
         // Query table information from information_schema
         let rows = client
             .query(
@@ -75,7 +93,7 @@ impl PostgresSink {
         }
 
         // Validate required columns exist with correct types
-        for (col, required_type) in required {
+        for (col, required_type) in self.required_schema.iter().flatten() {
             match actual_schema.get(col) {
                 Some(actual_type) => {
                     if !Self::types_are_compatible(actual_type, required_type) {
@@ -94,6 +112,17 @@ impl PostgresSink {
             }
         }
 
+        // Every stamped lineage header needs a column to go to
+        for header in self.lineage.iter() {
+            let col = lineage_column(header);
+            if !actual_schema.contains_key(&col) {
+                return Err(anyhow::anyhow!(
+                    "Lineage column '{}' not found in table '{}'; add it or leave {} out of the lineage headers",
+                    col, self.table, header.name()
+                ));
+            }
+        }
+
         Ok(())
     }
 
@@ -123,7 +152,7 @@ impl PostgresSink {
         }
     }
 
-    async fn insert_message(&mut self, msg: &Message) -> anyhow::Result<()> {
+    fn message_row(&self, msg: &Message) -> anyhow::Result<Map<String, Value>> {
         // Parse message payload as JSON if not already parsed
         let data = if let Some(parsed) = &msg.parsed {
             parsed.clone()
@@ -133,41 +162,83 @@ impl PostgresSink {
         };
 
         // Handle nested JSON objects
-        match data {
-            Value::Object(map) => {
-                // Build the SQL statement dynamically based on the data
-                let columns: Vec<String> = map.keys().cloned().collect();
-                let values: Vec<&Value> = columns.iter().map(|k| &map[k]).collect();
-                
-                let placeholders: Vec<String> = (1..=columns.len())
-                    .map(|i| format!("${}", i))
-                    .collect();
-
-                let query = format!(
-                    "INSERT INTO {} ({}) VALUES ({})",
-                    self.table,
-                    columns.join(", "),
-                    placeholders.join(", ")
-                );
-
-                // Convert serde_json::Value to tokio_postgres::types::ToSql
-                let params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = values
-                    .iter()
-                    .map(|&v| v as &(dyn tokio_postgres::types::ToSql + Sync))
-                    .collect();
-
-                self.client.as_ref().unwrap()
-                    .execute(&query, &params)
-                    .await
-                    .context("Failed to insert row")?;
-
-                Ok(())
+        let mut row = match data {
+            Value::Object(map) => map,
+            _ => return Err(anyhow::anyhow!("Message payload must be a JSON object"))
+        };
+
+        // Configured lineage headers become columns; headers from upstream stay out
+        for header in self.lineage.iter() {
+            if let Some(value) = msg.headers.get(header.header()) {
+                row.entry(lineage_column(header)).or_insert_with(|| Value::String(value.clone()));
             }
-            _ => Err(anyhow::anyhow!("Message payload must be a JSON object"))
         }
+        Ok(row)
+    }
+
+    async fn insert_message(&mut self, msg: &Message) -> anyhow::Result<()> {
+        let row = self.message_row(msg)?;
+        let client = self.client.as_ref().unwrap();
+        Self::insert_rows(client, &self.table, &[row]).await
+    }
+
+    // Multi-row INSERT of rows sharing the same columns, split to stay under
+    // the protocol's bind parameter limit
+    async fn insert_rows<C: GenericClient>(client: &C, table: &str, rows: &[Map<String, Value>]) -> anyhow::Result<()> {
+        let Some(first) = rows.first() else { return Ok(()) };
+        // Build the SQL statement dynamically based on the data
+        let columns: Vec<String> = first.keys().cloned().collect();
+        let rows_per_statement = (MAX_BIND_PARAMS / columns.len().max(1)).max(1);
+
+        for chunk in rows.chunks(rows_per_statement) {
+            let tuples: Vec<String> = (0..chunk.len())
+                .map(|r| {
+                    let placeholders: Vec<String> = (1..=columns.len())
+                        .map(|c| format!("${}", r * columns.len() + c))
+                        .collect();
+                    format!("({})", placeholders.join(", "))
+                })
+                .collect();
+
+            let query = format!(
+                "INSERT INTO {} ({}) VALUES {}",
+                table,
+                columns.join(", "),
+                tuples.join(", ")
+            );
+
+            // Convert serde_json::Value to tokio_postgres::types::ToSql
+            let params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = chunk
+                .iter()
+                .flat_map(|row| columns.iter().map(move |k| &row[k] as &(dyn tokio_postgres::types::ToSql + Sync)))
+                .collect();
+
+            client
+                .execute(query.as_str(), &params)
+                .await
+                .map_err(|e| db_error(e, "Failed to insert rows"))?;
+        }
+
+        Ok(())
     }
 }
 
+// Column a lineage header is written to, e.g. fluxmux-source -> fluxmux_source
+fn lineage_column(header: LineageHeader) -> String {
+    header.header().replace('-', "_")
+}
+
+// Connection problems and transient server conditions are worth retrying;
+// constraint violations, unknown columns and the like are not
+fn db_error(e: tokio_postgres::Error, what: &'static str) -> anyhow::Error {
+    let retryable = match e.code() {
+        Some(state) => ["08", "40", "53", "57"].iter().any(|class| state.code().starts_with(class)),
+        None => e.is_closed() || std::error::Error::source(&e).is_some_and(|s| s.is::<std::io::Error>()),
+    };
+    let err = anyhow::Error::new(e).context(what);
+    if retryable { transient(err) } else { err }
+}
+
 #[async_trait]
 impl Sink for PostgresSink {
     async fn send(&mut self, msg: Message) -> anyhow::Result<()> {
@@ -175,6 +246,29 @@ impl Sink for PostgresSink {
         self.insert_message(&msg).await
     }
 
+    async fn send_batch(&mut self, batch: Vec<Message>) -> anyhow::Result<()> {
+        self.get_or_connect_client().await?;
+        let rows = batch.iter().map(|msg| self.message_row(msg)).collect::<anyhow::Result<Vec<_>>>()?;
+
+        // One transaction per batch so a retry never leaves half of it behind
+        let tx = self.client.as_mut().unwrap().transaction().await
+            .map_err(|e| db_error(e, "Failed to start transaction"))?;
+        let mut start = 0;
+        for end in 1..=rows.len() {
+            // Consecutive rows with the same columns share one INSERT
+            if end == rows.len() || !rows[end].keys().eq(rows[start].keys()) {
+                Self::insert_rows(&tx, &self.table, &rows[start..end]).await?;
+                start = end;
+            }
+        }
+        tx.commit().await.map_err(|e| db_error(e, "Failed to commit batch"))?;
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
     async fn flush(&mut self) -> anyhow::Result<()> {
         // PostgreSQL automatically flushes after each transaction
         Ok(())